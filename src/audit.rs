@@ -0,0 +1,84 @@
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Durable record of every prompt sent to the model and the raw response received.
+///
+/// Each request is appended as one JSON line with `timestamp_ms`, `model`,
+/// `instruction`, either `inputs` or `inputs_sha256` (a SHA-256 over the inputs joined
+/// by newlines, when inputs are hashed for privacy) and `response`. Lines are written
+/// under a lock, so concurrent rayon workers never interleave. A failed write is
+/// reported on stdout and does not fail the query.
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Mutex<File>,
+    hash_inputs: bool,
+}
+
+impl AuditLog {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: impl AsRef<Path>, hash_inputs: bool) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            hash_inputs,
+        })
+    }
+
+    pub fn record(&self, model: &str, instruction: &str, inputs: &[String], response: &str) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let mut entry = json!({
+            "timestamp_ms": timestamp_ms,
+            "model": model,
+            "instruction": instruction,
+            "response": response,
+        });
+        if self.hash_inputs {
+            entry["inputs_sha256"] = json!(hash_inputs(inputs));
+        } else {
+            entry["inputs"] = json!(inputs);
+        }
+
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", entry) {
+            println!("failed to write audit log entry: {}", e);
+        }
+    }
+}
+
+fn hash_inputs(inputs: &[String]) -> String {
+    let digest = Sha256::digest(inputs.join("\n").as_bytes());
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_json_lines_with_hashed_inputs() {
+        let path = std::env::temp_dir().join(format!("audit_{}.jsonl", std::process::id()));
+        let audit = AuditLog::open(&path, true).unwrap();
+        let inputs = vec!["Wrong item delivered.".to_string()];
+        audit.record("llama32-df:latest", "Categorize", &inputs, "1 -> negative");
+        audit.record("llama32-df:latest", "Categorize", &inputs, "1 -> negative");
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["response"], "1 -> negative");
+        assert_eq!(lines[0]["inputs_sha256"].as_str().unwrap().len(), 64);
+        assert!(lines[0].get("inputs").is_none());
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Bounds and target for [`BatchTuner`].
+#[derive(Debug, Clone)]
+pub struct AutoTuneConfig {
+    /// Batch size to start with and never go below.
+    pub min_items: usize,
+    /// Batch size never exceeded.
+    pub max_items: usize,
+    /// Per-row latency up to which the batch size keeps growing.
+    pub target_latency_per_row: Duration,
+}
+
+impl Default for AutoTuneConfig {
+    fn default() -> Self {
+        Self {
+            min_items: 2,
+            max_items: 50,
+            target_latency_per_row: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Adapts the number of rows per prompt to what the backend handles well.
+///
+/// Starts at `min_items`. After every chunk:
+/// - a count mismatch halves the size, since large prompts are the usual cause;
+/// - a per-row latency above twice the target shrinks the size by one;
+/// - a per-row latency within the target grows the size by one.
+///
+/// The size always stays within `[min_items, max_items]`, and each change is logged.
+#[derive(Debug)]
+pub struct BatchTuner {
+    config: AutoTuneConfig,
+    current: AtomicUsize,
+}
+
+impl BatchTuner {
+    pub fn new(config: AutoTuneConfig) -> Self {
+        let min_items = config.min_items.max(1);
+        let config = AutoTuneConfig {
+            min_items,
+            max_items: config.max_items.max(min_items),
+            ..config
+        };
+        Self {
+            current: AtomicUsize::new(min_items),
+            config,
+        }
+    }
+
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    /// Records the outcome of a chunk of `rows` rows that took `elapsed`.
+    pub fn observe(&self, rows: usize, elapsed: Duration, mismatched: bool) {
+        if rows == 0 {
+            return;
+        }
+        let per_row = elapsed / rows as u32;
+        let target = self.config.target_latency_per_row;
+        let (min, max) = (self.config.min_items, self.config.max_items);
+        let update = self
+            .current
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |size| {
+                let next = if mismatched {
+                    size / 2
+                } else if per_row > target * 2 {
+                    size.saturating_sub(1)
+                } else if per_row <= target {
+                    size + 1
+                } else {
+                    size
+                };
+                let next = next.clamp(min, max);
+                (next != size).then_some(next)
+            });
+        if let Ok(previous) = update {
+            println!(
+                "auto-tune: items per prompt {} -> {} (per-row latency {:?}, mismatch: {})",
+                previous,
+                self.current(),
+                per_row,
+                mismatched
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grows_and_shrinks_within_bounds() {
+        let tuner = BatchTuner::new(AutoTuneConfig {
+            min_items: 2,
+            max_items: 4,
+            target_latency_per_row: Duration::from_millis(100),
+        });
+        assert_eq!(tuner.current(), 2);
+        for _ in 0..5 {
+            tuner.observe(tuner.current(), Duration::from_millis(50), false);
+        }
+        assert_eq!(tuner.current(), 4);
+        tuner.observe(4, Duration::from_millis(50), true);
+        assert_eq!(tuner.current(), 2);
+        tuner.observe(2, Duration::from_secs(1), false);
+        assert_eq!(tuner.current(), 2);
+    }
+}
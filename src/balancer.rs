@@ -0,0 +1,201 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// How [`EndpointPool`] picks the endpoint for the next request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoadBalancing {
+    /// Cycles through the endpoints in order.
+    #[default]
+    RoundRobin,
+    /// Picks the endpoint with the fewest requests in flight, which adapts to
+    /// servers of different speed.
+    LeastInFlight,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    in_flight: AtomicUsize,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn is_up(&self, now: Instant) -> bool {
+        self.down_until
+            .lock()
+            .unwrap()
+            .is_none_or(|down_until| down_until <= now)
+    }
+}
+
+/// Spreads requests over several servers serving the same model, e.g. Ollama
+/// instances on different GPUs or ports.
+///
+/// An endpoint whose request failed to connect is marked down with
+/// [`EndpointLease::mark_down`] and left out of the rotation for the cooldown (30
+/// seconds by default), after which it is tried again. When every endpoint is down,
+/// all of them are used, so requests fail with the backend's error rather than
+/// waiting for a cooldown.
+#[derive(Debug)]
+pub struct EndpointPool {
+    endpoints: Vec<Endpoint>,
+    strategy: LoadBalancing,
+    cooldown: Duration,
+    next: AtomicUsize,
+}
+
+impl EndpointPool {
+    /// Creates a pool over `urls`, which must not be empty.
+    pub fn new(urls: impl IntoIterator<Item = String>) -> Self {
+        let endpoints: Vec<Endpoint> = urls
+            .into_iter()
+            .map(|url| Endpoint {
+                url,
+                in_flight: AtomicUsize::new(0),
+                down_until: Mutex::new(None),
+            })
+            .collect();
+        assert!(!endpoints.is_empty(), "an endpoint pool needs an endpoint");
+        Self {
+            endpoints,
+            strategy: LoadBalancing::default(),
+            cooldown: Duration::from_secs(30),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn with_strategy(mut self, strategy: LoadBalancing) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+
+    /// The number of endpoints currently in the rotation.
+    pub fn up_count(&self) -> usize {
+        let now = Instant::now();
+        self.endpoints.iter().filter(|e| e.is_up(now)).count()
+    }
+
+    /// Picks the endpoint for a request, which counts as in flight until the lease
+    /// is dropped.
+    pub fn acquire(&self) -> EndpointLease<'_> {
+        let now = Instant::now();
+        let mut candidates: Vec<usize> = (0..self.endpoints.len())
+            .filter(|&i| self.endpoints[i].is_up(now))
+            .collect();
+        if candidates.is_empty() {
+            candidates = (0..self.endpoints.len()).collect();
+        }
+        let turn = self.next.fetch_add(1, Ordering::Relaxed);
+        // rotating the candidates also spreads ties between idle endpoints
+        let rotated = (0..candidates.len()).map(|i| candidates[(turn + i) % candidates.len()]);
+        let index = match self.strategy {
+            LoadBalancing::RoundRobin => candidates[turn % candidates.len()],
+            LoadBalancing::LeastInFlight => rotated
+                .min_by_key(|&i| self.endpoints[i].in_flight.load(Ordering::SeqCst))
+                .unwrap_or(candidates[0]),
+        };
+        self.endpoints[index]
+            .in_flight
+            .fetch_add(1, Ordering::SeqCst);
+        EndpointLease { pool: self, index }
+    }
+}
+
+/// An endpoint picked by [`EndpointPool::acquire`].
+#[derive(Debug)]
+pub struct EndpointLease<'a> {
+    pool: &'a EndpointPool,
+    index: usize,
+}
+
+impl EndpointLease<'_> {
+    pub fn url(&self) -> &str {
+        &self.pool.endpoints[self.index].url
+    }
+
+    /// Takes the endpoint out of the rotation for the pool's cooldown.
+    pub fn mark_down(&self) {
+        let endpoint = &self.pool.endpoints[self.index];
+        *endpoint.down_until.lock().unwrap() = Some(Instant::now() + self.pool.cooldown);
+        println!(
+            "{} is unreachable, taken out of rotation for {:?}",
+            endpoint.url, self.pool.cooldown
+        );
+    }
+}
+
+impl Drop for EndpointLease<'_> {
+    fn drop(&mut self) {
+        self.pool.endpoints[self.index]
+            .in_flight
+            .fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(strategy: LoadBalancing) -> EndpointPool {
+        EndpointPool::new(["a", "b", "c"].map(String::from)).with_strategy(strategy)
+    }
+
+    #[test]
+    fn test_round_robin_cycles_through_endpoints() {
+        let pool = pool(LoadBalancing::RoundRobin);
+        let urls: Vec<String> = (0..4).map(|_| pool.acquire().url().to_string()).collect();
+        assert_eq!(urls, vec!["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn test_least_in_flight_prefers_idle_endpoints() {
+        let pool = pool(LoadBalancing::LeastInFlight);
+        let first = pool.acquire();
+        let second = pool.acquire();
+        let third = pool.acquire();
+        let mut urls = vec![first.url(), second.url(), third.url()];
+        urls.sort();
+        assert_eq!(urls, vec!["a", "b", "c"]);
+        drop(second);
+        let fourth = pool.acquire();
+        assert_eq!(fourth.url(), "b");
+    }
+
+    #[test]
+    fn test_down_endpoints_leave_the_rotation() {
+        let pool = pool(LoadBalancing::RoundRobin);
+        pool.acquire().mark_down();
+        assert_eq!(pool.up_count(), 2);
+        assert!((0..4).all(|_| pool.acquire().url() != "a"));
+
+        // with every endpoint down, all are used again
+        let pool = pool.with_cooldown(Duration::from_secs(60));
+        for _ in 0..3 {
+            pool.acquire().mark_down();
+        }
+        assert_eq!(pool.up_count(), 0);
+        assert!(!pool.acquire().url().is_empty());
+
+        let pool = EndpointPool::new(["a".to_string()]).with_cooldown(Duration::ZERO);
+        pool.acquire().mark_down();
+        assert_eq!(
+            pool.up_count(),
+            1,
+            "the endpoint is back after the cooldown"
+        );
+    }
+}
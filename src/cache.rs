@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, RandomState};
+use std::sync::Mutex;
+
+/// A concurrent map split into independently locked shards.
+///
+/// Every rayon worker reads and writes the cache around each chunk, so a single
+/// `Mutex<HashMap>` makes the workers queue on one lock. Here a key only locks the
+/// shard it hashes to, and with several shards per worker two workers rarely touch
+/// the same shard at the same time.
+#[derive(Debug)]
+pub struct ShardedCache<K, V> {
+    shards: Vec<Mutex<HashMap<K, V>>>,
+    hasher: RandomState,
+}
+
+impl<K: Hash + Eq, V: Clone> ShardedCache<K, V> {
+    /// Shards allocated per worker by [`ShardedCache::for_workers`].
+    pub const SHARDS_PER_WORKER: usize = 4;
+
+    /// A cache with `shards` shards, rounded up to a power of two.
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1).next_power_of_two())
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// A cache sized for `workers` concurrent users, e.g. the threads of a rayon pool.
+    pub fn for_workers(workers: usize) -> Self {
+        Self::new(workers * Self::SHARDS_PER_WORKER)
+    }
+
+    /// A cache sized for `workers` holding a copy of every entry of this one.
+    pub fn resized_for_workers(&self, workers: usize) -> Self
+    where
+        K: Clone,
+    {
+        let resized = Self::for_workers(workers);
+        for (key, value) in self.entries() {
+            resized.insert(key, value);
+        }
+        resized
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<HashMap<K, V>> {
+        // the shard count is a power of two, so masking picks a shard uniformly
+        let index = self.hasher.hash_one(key) as usize & (self.shards.len() - 1);
+        &self.shards[index]
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.shard(key)
+            .lock()
+            .expect("cache shard poisoned")
+            .get(key)
+            .cloned()
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.shard(&key)
+            .lock()
+            .expect("cache shard poisoned")
+            .insert(key, value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().expect("cache shard poisoned").len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// A copy of every entry, locking one shard at a time.
+    pub fn entries(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+    {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                let shard = shard.lock().expect("cache shard poisoned");
+                shard
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_sharded_cache_get_and_insert() {
+        let cache = ShardedCache::for_workers(3);
+        assert_eq!(cache.shard_count(), 16);
+        cache.insert("a".to_string(), 1);
+        cache.insert("b".to_string(), 2);
+        cache.insert("a".to_string(), 3);
+        assert_eq!(cache.get(&"a".to_string()), Some(3));
+        assert_eq!(cache.get(&"c".to_string()), None);
+        assert_eq!(cache.len(), 2);
+        let mut entries = cache.entries();
+        entries.sort();
+        assert_eq!(entries, vec![("a".to_string(), 3), ("b".to_string(), 2)]);
+    }
+
+    // workers hammering the cache concurrently, as rayon workers do around chunks
+    fn contended(cache: &ShardedCache<usize, usize>, workers: usize) -> Duration {
+        let start = Instant::now();
+        std::thread::scope(|scope| {
+            for worker in 0..workers {
+                scope.spawn(move || {
+                    for i in 0..200_000 {
+                        let key = worker * 1_000_000 + i % 1_000;
+                        if cache.get(&key).is_none() {
+                            cache.insert(key, i);
+                        }
+                    }
+                });
+            }
+        });
+        start.elapsed()
+    }
+
+    // run with `cargo test --release -- --ignored --nocapture bench_lock_contention`
+    #[test]
+    #[ignore]
+    fn bench_lock_contention() {
+        let workers = std::thread::available_parallelism().map_or(4, |n| n.get());
+        let single = contended(&ShardedCache::new(1), workers);
+        let sharded = contended(&ShardedCache::for_workers(workers), workers);
+        println!(
+            "{} workers: single lock {:?}, {} shards {:?}",
+            workers,
+            single,
+            workers * ShardedCache::<usize, usize>::SHARDS_PER_WORKER,
+            sharded
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use datafusion_common::{DataFusionError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// One answer of the answer cache as stored on disk, see
+/// [`crate::llm_udf::AskLLM::export_answer_cache`] and
+/// [`crate::llm_udf::AskLLM::with_answer_cache_preload`].
+///
+/// A cache file has one JSON object per line with these fields. `prompt_sha256` is a
+/// SHA-256 of the parts of the prompt that are not in the cache key (the system prompt
+/// and the instruction wrapper) and of the settings that shape the answer (temperature,
+/// Ollama options, output type, response parsing and normalization), so an answer is
+/// only reused by an `AskLLM` that would have asked the same question of the same
+/// model and read the answer the same way.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub model: String,
+    pub prompt_sha256: String,
+    pub instruction: String,
+    pub value: String,
+    pub answer: String,
+}
+
+/// Writes `entries` to `path` as JSON lines, replacing the file.
+pub fn write_entries(path: impl AsRef<Path>, entries: &[CacheEntry]) -> Result<()> {
+    let path = path.as_ref();
+    let io_error = |e: std::io::Error| {
+        DataFusionError::Execution(format!("cannot write cache file {}: {}", path.display(), e))
+    };
+    let mut file = BufWriter::new(File::create(path).map_err(io_error)?);
+    for entry in entries {
+        let line = serde_json::to_string(entry).map_err(|e| {
+            DataFusionError::Execution(format!("cannot serialize cache entry: {}", e))
+        })?;
+        writeln!(file, "{}", line).map_err(io_error)?;
+    }
+    file.flush().map_err(io_error)
+}
+
+/// Reads the entries of a cache file written by [`write_entries`]. Blank lines are
+/// skipped; any other line that is not an entry fails the read.
+pub fn read_entries(path: impl AsRef<Path>) -> Result<Vec<CacheEntry>> {
+    let path = path.as_ref();
+    let io_error = |e: std::io::Error| {
+        DataFusionError::Execution(format!("cannot read cache file {}: {}", path.display(), e))
+    };
+    let file = File::open(path).map_err(io_error)?;
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(io_error)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|e| {
+            DataFusionError::Execution(format!(
+                "invalid entry on line {} of cache file {}: {}",
+                number + 1,
+                path.display(),
+                e
+            ))
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_round_trip_through_a_file() {
+        let path = std::env::temp_dir().join(format!("cache_{}.jsonl", std::process::id()));
+        let entries = vec![
+            CacheEntry {
+                model: "llama32-df:latest".to_string(),
+                prompt_sha256: "00".to_string(),
+                instruction: "Categorize".to_string(),
+                value: "Great!\nReally".to_string(),
+                answer: "positive".to_string(),
+            };
+            2
+        ];
+        write_entries(&path, &entries).unwrap();
+        assert_eq!(read_entries(&path).unwrap(), entries);
+
+        std::fs::write(&path, "{\"model\": \"m\"}\n").unwrap();
+        let error = read_entries(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.contains("line 1"), "{error}");
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A cloneable handle used to stop an in-progress LLM query gracefully.
+///
+/// Once cancelled, chunks that are already talking to the backend are allowed to
+/// finish and their results are kept. Chunks that have not started yet are never
+/// sent to the backend, and their rows are returned as NULL. This means a query
+/// interrupted with Ctrl-C still completes with partial (but never half-written) results.
+///
+/// The local llama.cpp backend is the exception: its generation loop checks the handle
+/// before every token, so an in-flight chunk stops mid-stream and returns NULL rows.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation. Safe to call multiple times and from any thread.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Cancel this handle when the process receives Ctrl-C (SIGINT). A second Ctrl-C
+    /// exits the process right away, for when the in-flight chunks are stuck.
+    /// Must be called from within a tokio runtime.
+    pub fn cancel_on_ctrl_c(&self) {
+        let cancellation = self.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                println!("received Ctrl-C, finishing in-flight chunks before shutting down");
+                println!("press Ctrl-C again to exit immediately");
+                cancellation.cancel();
+            }
+            if tokio::signal::ctrl_c().await.is_ok() {
+                println!("received a second Ctrl-C, exiting");
+                // 130 = 128 + SIGINT, the status a shell reports for an interrupted command
+                std::process::exit(130);
+            }
+        });
+    }
+}
@@ -0,0 +1,86 @@
+use crate::normalize::Normalization;
+
+/// A strict policy for categorical `ask_llm` columns, see
+/// [`crate::llm_udf::AskLLM::with_strict_categories`]: a row only gets a value if the
+/// model answered one of the allowed labels, and with self-consistency only if enough
+/// samples agree on it. Every other row is NULL, so the column holds nothing but
+/// labels.
+///
+/// The checks run in this order on every answer:
+///
+/// 1. answers matching a null sentinel become NULL
+///    ([`crate::llm_udf::AskLLM::with_null_sentinels`]);
+/// 2. the UDF's normalization and post-processing are applied, if configured;
+/// 3. the answer is normalized with [`CategoricalGate::with_normalization`]
+///    ([`Normalization::categorical`] by default) and compared with the labels,
+///    normalized the same way. A match is returned as the label is written here;
+///    anything else becomes NULL;
+/// 4. with [`crate::llm_udf::AskLLM::with_self_consistency`], the samples vote on the
+///    gated answers, so off-list answers do not count as votes, and the majority is
+///    only kept if its share of the samples is at least
+///    [`CategoricalGate::with_min_agreement`] (and the self-consistency
+///    `min_confidence`, if higher).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoricalGate {
+    labels: Vec<String>,
+    normalization: Normalization,
+    min_agreement: f64,
+}
+
+impl CategoricalGate {
+    /// Allows `labels`, with a minimum agreement of half the samples.
+    pub fn new(labels: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            labels: labels.into_iter().map(Into::into).collect(),
+            normalization: Normalization::categorical(),
+            min_agreement: 0.5,
+        }
+    }
+
+    /// How answers and labels are normalized before they are compared.
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// The share of self-consistency samples, in `0.0..=1.0`, that must agree on a
+    /// label for it to be kept. Has no effect without self-consistency.
+    pub fn with_min_agreement(mut self, min_agreement: f64) -> Self {
+        self.min_agreement = min_agreement;
+        self
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    pub fn min_agreement(&self) -> f64 {
+        self.min_agreement
+    }
+
+    /// The label `answer` names, `None` if it is not one of the labels.
+    pub fn check(&self, answer: &str) -> Option<&str> {
+        let answer = self.normalization.apply(answer);
+        self.labels
+            .iter()
+            .find(|label| self.normalization.apply(label) == answer)
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_labels_pass_the_gate() {
+        let gate = CategoricalGate::new(["Positive", "Negative"]);
+        assert_eq!(gate.check("  positive "), Some("Positive"));
+        assert_eq!(gate.check("NEGATIVE"), Some("Negative"));
+        assert_eq!(gate.check("Mostly positive"), None);
+
+        let exact = gate.with_normalization(Normalization::default());
+        assert_eq!(exact.check("positive"), None);
+        assert_eq!(exact.check("Positive"), Some("Positive"));
+    }
+}
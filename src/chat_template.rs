@@ -0,0 +1,115 @@
+/// Prompt formats of common local model families.
+///
+/// Each family is trained with its own special tokens around the system, user and
+/// assistant turns. Rendering a prompt with the wrong template does not fail, it just
+/// silently degrades the answers, so the template should match the loaded model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChatTemplate {
+    /// Llama 3 (`<|start_header_id|>` ... `<|eot_id|>`).
+    #[default]
+    Llama3,
+    /// Mistral / Mixtral (`[INST]` ... `[/INST]`). Has no system turn, so the system
+    /// text is prepended to the user turn.
+    Mistral,
+    /// ChatML (`<|im_start|>` ... `<|im_end|>`), used by Qwen and many fine-tunes.
+    ChatMl,
+    /// Gemma (`<start_of_turn>` ... `<end_of_turn>`). Has no system turn either.
+    Gemma,
+}
+
+impl ChatTemplate {
+    /// Guesses the family from the Jinja chat template stored in the GGUF metadata
+    /// (`tokenizer.chat_template`) by looking for its characteristic tokens.
+    pub fn detect(gguf_chat_template: &str) -> Option<Self> {
+        if gguf_chat_template.contains("<|start_header_id|>") {
+            Some(Self::Llama3)
+        } else if gguf_chat_template.contains("<|im_start|>") {
+            Some(Self::ChatMl)
+        } else if gguf_chat_template.contains("<start_of_turn>") {
+            Some(Self::Gemma)
+        } else if gguf_chat_template.contains("[INST]") {
+            Some(Self::Mistral)
+        } else {
+            None
+        }
+    }
+
+    /// Guesses the family from a model name, an Ollama tag or a GGUF file name, for when
+    /// the template cannot be read from the model itself:
+    ///
+    /// | name contains                        | template  |
+    /// |--------------------------------------|-----------|
+    /// | `llama3`, `llama-3`, `llama_3`       | `Llama3`  |
+    /// | `mistral`, `mixtral`                 | `Mistral` |
+    /// | `qwen`, `qwq`, `yi-`, `hermes`       | `ChatMl`  |
+    /// | `gemma`                              | `Gemma`   |
+    ///
+    /// Matching ignores case. Fine-tunes usually keep the template of their base model
+    /// and name it, e.g. `llama32-df` does not match while `Llama-3.2-3B` does.
+    pub fn for_model_name(model_name: &str) -> Option<Self> {
+        let name = model_name.to_lowercase();
+        let matches = |fragments: &[&str]| fragments.iter().any(|f| name.contains(f));
+        if matches(&["llama3", "llama-3", "llama_3"]) {
+            Some(Self::Llama3)
+        } else if matches(&["mistral", "mixtral"]) {
+            Some(Self::Mistral)
+        } else if matches(&["qwen", "qwq", "yi-", "hermes"]) {
+            Some(Self::ChatMl)
+        } else if matches(&["gemma"]) {
+            Some(Self::Gemma)
+        } else {
+            None
+        }
+    }
+
+    /// Renders a single system + user exchange, ending where the assistant answer starts.
+    pub fn render(&self, system: &str, user: &str) -> String {
+        match self {
+            Self::Llama3 => format!(
+                "\n<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n{system}\n<|eot_id|><|start_header_id|>user<|end_header_id|>\n{user}\n<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n"
+            ),
+            Self::Mistral => format!("[INST] {system}\n\n{user} [/INST]"),
+            Self::ChatMl => format!(
+                "<|im_start|>system\n{system}<|im_end|>\n<|im_start|>user\n{user}<|im_end|>\n<|im_start|>assistant\n"
+            ),
+            Self::Gemma => format!(
+                "<start_of_turn>user\n{system}\n\n{user}<end_of_turn>\n<start_of_turn>model\n"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_from_gguf_template() {
+        assert_eq!(
+            ChatTemplate::detect("{% for message in messages %}<|im_start|>{{ message.role }}"),
+            Some(ChatTemplate::ChatMl)
+        );
+        assert_eq!(
+            ChatTemplate::detect("{{ bos_token }}[INST] {{ message['content'] }} [/INST]"),
+            Some(ChatTemplate::Mistral)
+        );
+        assert_eq!(ChatTemplate::detect("{{ content }}"), None);
+    }
+
+    #[test]
+    fn test_template_from_model_name() {
+        assert_eq!(
+            ChatTemplate::for_model_name("models/Llama-3.2-3B-Instruct-Q4_K_M.gguf"),
+            Some(ChatTemplate::Llama3)
+        );
+        assert_eq!(
+            ChatTemplate::for_model_name("qwen2.5:7b"),
+            Some(ChatTemplate::ChatMl)
+        );
+        assert_eq!(
+            ChatTemplate::for_model_name("gemma2:9b"),
+            Some(ChatTemplate::Gemma)
+        );
+        assert_eq!(ChatTemplate::for_model_name("llama32-df:latest"), None);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::RuntimeFlavor;
+
+/// Merges the rows of `ask_llm` invocations that run at the same time into one
+/// evaluation, see [`crate::llm_udf::AskLLM::with_coalescing`].
+///
+/// Each record batch is a separate invocation, so a query over many small batches pays
+/// the prompt overhead (system prompt, instruction, a partly filled last chunk) once
+/// per batch. With coalescing, an invocation adds its rows to the open group of its
+/// instruction and waits until the group holds `min_rows` rows or has been open for
+/// `window`. Whichever waiting invocation notices first evaluates the whole group and
+/// hands every other invocation its own rows' answers.
+///
+/// The trade-off is latency: an invocation may wait up to `window` before its rows
+/// are even sent, which is pure overhead when nothing else is running. Only
+/// invocations that overlap in time can be merged, e.g. those of different partitions;
+/// the batches of one partition arrive one after the other, since each invocation
+/// must return before the next starts.
+///
+/// Waiting blocks the calling thread. On a worker of a multi-threaded tokio runtime,
+/// where DataFusion invokes UDFs, the wait runs in [`tokio::task::block_in_place`], so
+/// the worker's other tasks (such as the invocations to merge with) move to other
+/// threads. On a current-thread runtime the whole runtime waits, so nothing can be
+/// merged and every invocation waits for `window`.
+#[derive(Debug)]
+pub struct Coalescer<T> {
+    window: Duration,
+    min_rows: usize,
+    state: Mutex<State<T>>,
+    changed: Condvar,
+}
+
+#[derive(Debug)]
+struct Group {
+    opened: Instant,
+    // (member id, rows)
+    members: Vec<(u64, usize)>,
+    values: Vec<Option<String>>,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    next_id: u64,
+    open: HashMap<String, Group>,
+    done: HashMap<u64, Result<Vec<T>, String>>,
+}
+
+impl<T> Coalescer<T> {
+    pub fn new(window: Duration, min_rows: usize) -> Self {
+        Self {
+            window,
+            min_rows: min_rows.max(1),
+            state: Mutex::new(State {
+                next_id: 0,
+                open: HashMap::new(),
+                done: HashMap::new(),
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Adds `values` to the open group of `key` and returns their results once the
+    /// group has been evaluated by `run`, which gets the values of every member in
+    /// arrival order and must return one result per value.
+    pub fn submit<F>(
+        &self,
+        key: &str,
+        values: Vec<Option<String>>,
+        run: F,
+    ) -> Result<Vec<T>, String>
+    where
+        F: FnOnce(&[Option<String>]) -> Result<Vec<T>, String>,
+    {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        let group = state.open.entry(key.to_string()).or_insert_with(|| Group {
+            opened: Instant::now(),
+            members: Vec::new(),
+            values: Vec::new(),
+        });
+        group.members.push((id, values.len()));
+        group.values.extend(values);
+        // the group may have reached min_rows
+        self.changed.notify_all();
+        let mut run = Some(run);
+        loop {
+            if let Some(result) = state.done.remove(&id) {
+                return result;
+            }
+            // None once another member has taken the group, Some(wait) while it is open
+            let wait = state
+                .open
+                .get(key)
+                .filter(|group| group.members.iter().any(|&(member, _)| member == id))
+                .map(|group| {
+                    if group.values.len() >= self.min_rows {
+                        Duration::ZERO
+                    } else {
+                        self.window.saturating_sub(group.opened.elapsed())
+                    }
+                });
+            match wait {
+                Some(Duration::ZERO) => {
+                    let group = state.open.remove(key).expect("the group is open");
+                    drop(state);
+                    let run = run.take().expect("a member evaluates at most one group");
+                    let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                        run(&group.values)
+                    }))
+                    .unwrap_or_else(|_| Err("coalesced evaluation panicked".to_string()));
+                    state = self.state.lock().unwrap();
+                    Self::distribute(&mut state, group, outcome);
+                    self.changed.notify_all();
+                }
+                Some(wait) => state = park(|| self.changed.wait_timeout(state, wait).unwrap().0),
+                None => state = park(|| self.changed.wait(state).unwrap()),
+            }
+        }
+    }
+
+    fn distribute(state: &mut State<T>, group: Group, outcome: Result<Vec<T>, String>) {
+        let outcome = outcome.and_then(|results| {
+            if results.len() == group.values.len() {
+                return Ok(results);
+            }
+            Err(format!(
+                "coalesced evaluation returned {} results for {} rows",
+                results.len(),
+                group.values.len()
+            ))
+        });
+        match outcome {
+            Ok(results) => {
+                let mut results = results.into_iter();
+                for (member, rows) in group.members {
+                    state
+                        .done
+                        .insert(member, Ok(results.by_ref().take(rows).collect()));
+                }
+            }
+            Err(error) => {
+                for (member, _) in group.members {
+                    state.done.insert(member, Err(error.clone()));
+                }
+            }
+        }
+    }
+}
+
+// blocks the calling thread in `wait`, first handing the other tasks of a tokio worker
+// to another thread
+fn park<R>(wait: impl FnOnce() -> R) -> R {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(wait)
+        }
+        _ => wait(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn values(values: &[&str]) -> Vec<Option<String>> {
+        values.iter().map(|v| Some(v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_overlapping_submissions_are_evaluated_together() {
+        let coalescer = Coalescer::new(Duration::from_secs(10), 4);
+        let runs = AtomicUsize::new(0);
+        let run = |all: &[Option<String>]| -> Result<Vec<String>, String> {
+            runs.fetch_add(1, Ordering::SeqCst);
+            assert_eq!(all.len(), 4);
+            Ok(all
+                .iter()
+                .map(|v| v.clone().unwrap().to_uppercase())
+                .collect())
+        };
+        let (first, second) = std::thread::scope(|scope| {
+            let first = scope.spawn(|| coalescer.submit("Classify", values(&["a", "b"]), run));
+            let second = scope.spawn(|| coalescer.submit("Classify", values(&["c", "d"]), run));
+            (first.join().unwrap(), second.join().unwrap())
+        });
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        let mut results = [first.unwrap(), second.unwrap()];
+        results.sort();
+        assert_eq!(results, [["A", "B"], ["C", "D"]]);
+    }
+
+    #[test]
+    fn test_lone_submission_runs_after_the_window() {
+        let coalescer = Coalescer::new(Duration::from_millis(20), 100);
+        let start = Instant::now();
+        let result = coalescer.submit("Classify", values(&["a"]), |all| Ok(vec![all.len()]));
+        assert_eq!(result.unwrap(), [1]);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let error = coalescer.submit("Classify", values(&["a"]), |_| Ok(Vec::<usize>::new()));
+        assert!(error.unwrap_err().contains("0 results for 1 rows"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_waiting_does_not_block_the_tokio_worker() {
+        let coalescer = Arc::new(Coalescer::new(Duration::from_secs(10), 2));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let start = Instant::now();
+        let submissions: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|value| {
+                let (coalescer, runs) = (coalescer.clone(), runs.clone());
+                // the second submission only runs while the first one waits
+                tokio::spawn(async move {
+                    coalescer.submit("Classify", values(&[value]), |all| {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        Ok(vec![(); all.len()])
+                    })
+                })
+            })
+            .collect();
+        for submission in submissions {
+            submission.await.unwrap().unwrap();
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
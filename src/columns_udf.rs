@@ -0,0 +1,195 @@
+use datafusion::arrow::array::{ArrayRef, StructArray};
+use datafusion::arrow::datatypes::{DataType, Field, Fields};
+use datafusion_common::cast::as_string_array;
+use datafusion_common::{Result, ScalarValue, plan_err};
+use datafusion_doc::Documentation;
+use datafusion_expr::ScalarUDFImpl;
+use datafusion_expr::{ColumnarValue, ScalarFunctionArgs, Signature, Volatility};
+use datafusion_macros::user_doc;
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::config::AiConfig;
+use crate::llm_udf::AskLLM;
+
+/// Answers the same instruction for several independent columns in one call, e.g. to
+/// classify the title and the body of a ticket separately:
+///
+/// ```sql
+/// ask_llm_columns('Categorize the sentiment', title, body)
+/// ```
+///
+/// returns a struct with one field per column, `column1`, `column2`, ... by default
+/// (see [`AskLlmColumns::with_field_names`]). Unlike concatenating the columns into one
+/// input, every column is its own task: its values are chunked on their own, so a
+/// chunk never mixes columns and the answer count of every chunk is matched against
+/// that column's rows alone.
+///
+/// The columns are evaluated by one [`AskLLM`] (see [`AskLlmColumns::with_ask_llm`])
+/// in a single invocation: the chunks of all columns are spread together over its
+/// worker pool and share its HTTP client, answer and response caches, rate limit and
+/// retry budget, so the setup is paid once rather than once per column. Each field
+/// has the output type of that `AskLLM`; its sidecar columns are not returned.
+#[user_doc(
+    doc_section(label = "AI functions"),
+    description = "Ask LLM the same instruction for several independent columns, returning a struct with one answer per column",
+    syntax_example = "ask_llm_columns('instruction', column_a, column_b, ...)"
+)]
+#[derive(Debug)]
+pub struct AskLlmColumns {
+    name: String,
+    signature: Signature,
+    ask_llm: AskLLM,
+    field_names: Option<Vec<String>>,
+}
+
+impl AskLlmColumns {
+    pub fn new() -> Self {
+        Self::with_config(&AiConfig::default())
+    }
+
+    /// Creates the UDF using the chat model from `config`.
+    pub fn with_config(config: &AiConfig) -> Self {
+        Self::with_ask_llm(AskLLM::with_config(config))
+    }
+
+    /// Answers the columns with `ask_llm` and all its settings (prompt, chunking,
+    /// caches, output type, ...).
+    pub fn with_ask_llm(ask_llm: AskLLM) -> Self {
+        Self {
+            name: "ask_llm_columns".to_string(),
+            signature: Signature::variadic(vec![DataType::Utf8], Volatility::Volatile),
+            ask_llm,
+            field_names: None,
+        }
+    }
+
+    /// Registers the function under `name` instead of its default name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Names the struct fields, one per column in argument order. Calls with a
+    /// different number of columns fail at plan time.
+    pub fn with_field_names(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.field_names = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    fn fields(&self, columns: usize) -> Result<Fields> {
+        let names: Vec<String> = match &self.field_names {
+            Some(names) if names.len() != columns => {
+                return plan_err!(
+                    "{} has {} field names but was called with {} columns",
+                    self.name,
+                    names.len(),
+                    columns
+                );
+            }
+            Some(names) => names.clone(),
+            None => (1..=columns).map(|i| format!("column{i}")).collect(),
+        };
+        let answer_type = self.ask_llm.answer_type();
+        Ok(names
+            .into_iter()
+            .map(|name| Field::new(name, answer_type.clone(), true))
+            .collect())
+    }
+}
+
+impl Default for AskLlmColumns {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScalarUDFImpl for AskLlmColumns {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+    fn return_type(&self, args: &[DataType]) -> Result<DataType> {
+        if args.len() < 2 || args.iter().any(|arg| arg != &DataType::Utf8) {
+            return plan_err!("{} only accepts at least 2 Utf8 arguments", self.name);
+        }
+        Ok(DataType::Struct(self.fields(args.len() - 1)?))
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        let ScalarFunctionArgs { args, .. } = args;
+        let (instruction, columns) = match args.as_slice() {
+            [
+                ColumnarValue::Scalar(ScalarValue::Utf8(instruction)),
+                columns @ ..,
+            ] if !columns.is_empty() => (instruction.as_deref().unwrap_or_default(), columns),
+            _ => {
+                return plan_err!(
+                    "{} only accepts arguments in the form of 'instruction' (string) followed by one or more columns",
+                    self.name
+                );
+            }
+        };
+        let columns = columns
+            .iter()
+            .map(|column| match column {
+                ColumnarValue::Array(column) => {
+                    Ok(as_string_array(column.as_ref())?.iter().collect())
+                }
+                ColumnarValue::Scalar(_) => {
+                    plan_err!("{} expects columns after the instruction", self.name)
+                }
+            })
+            .collect::<Result<Vec<Vec<Option<&str>>>>>()?;
+        let answers: Vec<ArrayRef> = self.ask_llm.answer_columns(instruction, &columns)?;
+        let result = StructArray::try_new(self.fields(columns.len())?, answers, None)?;
+        Ok(ColumnarValue::Array(Arc::new(result)))
+    }
+
+    fn documentation(&self) -> Option<&Documentation> {
+        self.doc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dry_run::DryRun;
+    use datafusion::arrow::array::{Array, AsArray};
+
+    #[tokio::test]
+    async fn test_each_column_is_its_own_task() {
+        let config = AiConfig::default().with_items_per_prompt(2);
+        let dry_run = DryRun::new();
+        let udf =
+            AskLlmColumns::with_ask_llm(AskLLM::with_config(&config).with_dry_run(dry_run.clone()))
+                .with_field_names(["title", "body"]);
+        let ctx = datafusion::prelude::SessionContext::new();
+        ctx.register_udf(datafusion_expr::ScalarUDF::from(udf));
+        let batches = ctx
+            .sql(
+                "SELECT ask_llm_columns('Categorize', column1, column2) AS r \
+                 FROM (VALUES ('Late', 'It arrived late'), ('Great', 'Works well'), ('Ok', 'Fine'))",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let result = batches[0].column(0).as_struct();
+        assert_eq!(result.column_names(), vec!["title", "body"]);
+        assert_eq!(result.column(1).len(), 3);
+        // 3 rows in chunks of 2 are 2 calls per column, chunks never span columns
+        let estimate = dry_run.total();
+        assert_eq!(estimate.rows, 6);
+        assert_eq!(estimate.calls, 4);
+
+        let udf = AskLlmColumns::new().with_field_names(["title"]);
+        assert!(udf.return_type(&[DataType::Utf8; 3]).is_err());
+    }
+}
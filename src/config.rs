@@ -0,0 +1,571 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context as AnyhowContext;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::ollama_utils::HttpSettings;
+use crate::windowing::WindowingConfig;
+
+/// The inference backends, which differ in how much concurrency they handle well.
+///
+/// | backend  | default concurrent prompts | maximum |
+/// |----------|----------------------------|---------|
+/// | `Ollama` | 4                          | 32      |
+/// | `Llama`  | 1                          | 1       |
+///
+/// Ollama serves parallel requests, while the local llama.cpp model sits behind a single
+/// lock, so extra workers only wait on each other. The Ollama UDFs are always
+/// registered and use the Ollama limits; `Llama` additionally registers
+/// `ask_llm_confidence`, which uses the Llama ones. A requested concurrency
+/// ([`AiConfig::max_concurrent_prompts`]) overrides the default but is clamped to
+/// `1..=maximum`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Ollama,
+    Llama,
+}
+
+impl Backend {
+    pub fn default_concurrency(&self) -> usize {
+        match self {
+            Backend::Ollama => 4,
+            Backend::Llama => 1,
+        }
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        match self {
+            Backend::Ollama => 32,
+            Backend::Llama => 1,
+        }
+    }
+
+    /// The number of concurrent prompts to use given an optional override, clamped to
+    /// `1..=max_concurrency()` with a warning.
+    pub fn resolve_concurrency(&self, requested: Option<usize>) -> usize {
+        let resolved = requested
+            .unwrap_or(self.default_concurrency())
+            .clamp(1, self.max_concurrency());
+        if let Some(requested) = requested.filter(|&requested| requested != resolved) {
+            log::warn!(
+                "{:?} backend: {} concurrent prompts requested, clamped to {}",
+                self,
+                requested,
+                resolved
+            );
+        }
+        resolved
+    }
+}
+
+/// Shared configuration for the AI UDFs.
+///
+/// Chat and embedding requests usually go to different models on the same Ollama
+/// server (e.g. a chat model for `ask_llm` and `nomic-embed-text` for `ollama_embed`),
+/// so the two model names are configured separately.
+#[derive(Debug, Clone)]
+pub struct AiConfig {
+    /// The backend the deployment uses. The Ollama UDFs are always registered; `Llama`
+    /// additionally needs [`AiConfig::model_path`].
+    pub backend: Backend,
+    /// GGUF model file for the `Llama` backend.
+    pub model_path: Option<String>,
+    /// Base URL of the Ollama server, without the `/api/...` path.
+    pub ollama_host: String,
+    /// Further Ollama servers serving the same chat model. `ask_llm` spreads its chunk
+    /// requests over [`AiConfig::ollama_host`] and these, see [`crate::balancer`].
+    pub ollama_hosts: Vec<String>,
+    /// Model used by `ask_llm`.
+    pub chat_model: String,
+    /// Model used by `ollama_embed`.
+    pub embed_model: String,
+    /// Embedding size of [`AiConfig::embed_model`]. When set, `ollama_embed` returns
+    /// a `FixedSizeList` of this size instead of a `List`.
+    pub embed_dimensions: Option<usize>,
+    /// Splits oversized `ask_llm` values into overlapping windows. Disabled by default.
+    pub windowing: Option<WindowingConfig>,
+    /// Number of rows `ask_llm` sends to the model in a single prompt.
+    pub items_per_prompt: usize,
+    /// Maximum number of prompts in flight at once. `None` uses the default of the
+    /// backend each UDF runs on, and larger values are clamped to its maximum, see
+    /// [`Backend`]; values above the Ollama maximum are rejected.
+    pub max_concurrent_prompts: Option<usize>,
+    /// Number of times a failed `ask_llm` chunk request is retried, if the failure is
+    /// retryable, see [`crate::ollama_utils::ErrorClass`].
+    pub max_chunk_retries: usize,
+    /// Maximum total retries across all chunks of one invocation, see
+    /// [`crate::retry::RetryBudget`]. `None` is unbounded.
+    pub retry_budget: Option<usize>,
+    /// Sampling temperature sent with chat requests. `None` uses the model default.
+    pub temperature: Option<f32>,
+    /// Time after which an `ask_llm` chunk is given up and returned as NULL.
+    pub chunk_deadline: Option<Duration>,
+    /// Estimated prompt size, in characters, above which an `ask_llm` chunk is split
+    /// into smaller chunks. `None` never splits.
+    pub max_prompt_chars: Option<usize>,
+    /// Logs an `ask_llm` progress summary every this many processed rows. `None`
+    /// logs none.
+    pub progress_interval: Option<usize>,
+    /// Maximum chat requests per second `ask_llm` sends, across all its chunks and
+    /// hosts. `None` is unlimited.
+    pub max_requests_per_second: Option<f64>,
+    /// Model `ask_llm` sends a chunk to when it still fails after its retries. `None`
+    /// with a [`AiConfig::fallback_host`] uses [`AiConfig::chat_model`].
+    pub fallback_model: Option<String>,
+    /// Ollama server of the fallback model. `None` with a [`AiConfig::fallback_model`]
+    /// uses [`AiConfig::ollama_host`].
+    pub fallback_host: Option<String>,
+    /// Connection settings of the HTTP clients of `ask_llm` and `ollama_embed`,
+    /// reqwest's defaults unless set. With a [`crate::shared_backend::SharedBackend`]
+    /// they apply to its client instead, see
+    /// [`crate::shared_backend::SharedBackend::with_http`].
+    pub http: HttpSettings,
+    /// Extra entries of the `options` object of `ask_llm` chat requests, e.g.
+    /// `repeat_penalty` or `stop`. They override [`AiConfig::temperature`].
+    pub ollama_options: Map<String, Value>,
+}
+
+impl Default for AiConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Ollama,
+            model_path: None,
+            ollama_host: "http://localhost:11434".to_string(),
+            ollama_hosts: Vec::new(),
+            chat_model: "llama32-df:latest".to_string(),
+            embed_model: "nomic-embed-text:latest".to_string(),
+            embed_dimensions: None,
+            windowing: None,
+            items_per_prompt: 5,
+            max_concurrent_prompts: None,
+            max_chunk_retries: 0,
+            retry_budget: None,
+            temperature: None,
+            chunk_deadline: None,
+            max_prompt_chars: None,
+            progress_interval: None,
+            max_requests_per_second: None,
+            fallback_model: None,
+            fallback_host: None,
+            http: HttpSettings::default(),
+            ollama_options: Map::new(),
+        }
+    }
+}
+
+/// The on-disk form of [`AiConfig`], read by [`AiConfig::from_file`] from TOML
+/// (`.toml`) or JSON (any other extension). Every key is optional and falls back
+/// to the [`AiConfig::default`] value; unknown keys are rejected.
+///
+/// | key                      | type                   | default                     |
+/// |--------------------------|------------------------|-----------------------------|
+/// | `backend`                | `"ollama"` / `"llama"` | `"ollama"`                  |
+/// | `model_path`             | string                 | required for `"llama"`      |
+/// | `ollama_host`            | string                 | `"http://localhost:11434"`  |
+/// | `ollama_hosts`           | array of strings       | `[]`                        |
+/// | `chat_model`             | string                 | `"llama32-df:latest"`       |
+/// | `embed_model`            | string                 | `"nomic-embed-text:latest"` |
+/// | `embed_dimensions`       | integer >= 1           | variable-size output        |
+/// | `items_per_prompt`       | integer >= 1           | `5`                         |
+/// | `max_concurrent_prompts` | integer in `1..=32`    | `4`                         |
+/// | `max_chunk_retries`      | integer                | `0`                         |
+/// | `retry_budget`           | integer                | unbounded                   |
+/// | `temperature`            | number in `0.0..=2.0`  | model default               |
+/// | `chunk_deadline_ms`      | integer >= 1           | no deadline                 |
+/// | `max_prompt_chars`       | integer >= 1           | unlimited                   |
+/// | `progress_interval`      | integer >= 1           | no progress output          |
+/// | `max_requests_per_second`| number > 0             | unlimited                   |
+/// | `fallback_model`         | string                 | no fallback                 |
+/// | `fallback_host`          | string                 | `ollama_host`               |
+/// | `http2_prior_knowledge`  | boolean                | `false`                     |
+/// | `http_compression`       | boolean                | `true`                      |
+/// | `pool_max_idle_per_host` | integer                | unlimited                   |
+/// | `pool_idle_timeout_ms`   | integer                | `90000`                     |
+/// | `ollama_options`         | table                  | none                        |
+///
+/// See `ai_config.example.toml` in the repository root for a complete file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    backend: Option<Backend>,
+    model_path: Option<String>,
+    ollama_host: Option<String>,
+    ollama_hosts: Option<Vec<String>>,
+    chat_model: Option<String>,
+    embed_model: Option<String>,
+    embed_dimensions: Option<usize>,
+    items_per_prompt: Option<usize>,
+    max_concurrent_prompts: Option<usize>,
+    max_chunk_retries: Option<usize>,
+    retry_budget: Option<usize>,
+    temperature: Option<f32>,
+    chunk_deadline_ms: Option<u64>,
+    max_prompt_chars: Option<usize>,
+    progress_interval: Option<usize>,
+    max_requests_per_second: Option<f64>,
+    fallback_model: Option<String>,
+    fallback_host: Option<String>,
+    http2_prior_knowledge: Option<bool>,
+    http_compression: Option<bool>,
+    pool_max_idle_per_host: Option<usize>,
+    pool_idle_timeout_ms: Option<u64>,
+    ollama_options: Option<Map<String, Value>>,
+}
+
+impl FileConfig {
+    fn into_config(self) -> anyhow::Result<AiConfig> {
+        let defaults = AiConfig::default();
+        let config = AiConfig {
+            backend: self.backend.unwrap_or(defaults.backend),
+            model_path: self.model_path,
+            ollama_host: self.ollama_host.unwrap_or(defaults.ollama_host),
+            ollama_hosts: self.ollama_hosts.unwrap_or_default(),
+            chat_model: self.chat_model.unwrap_or(defaults.chat_model),
+            embed_model: self.embed_model.unwrap_or(defaults.embed_model),
+            embed_dimensions: self.embed_dimensions,
+            windowing: None,
+            items_per_prompt: self.items_per_prompt.unwrap_or(defaults.items_per_prompt),
+            max_concurrent_prompts: self.max_concurrent_prompts,
+            max_chunk_retries: self.max_chunk_retries.unwrap_or(defaults.max_chunk_retries),
+            retry_budget: self.retry_budget,
+            temperature: self.temperature,
+            chunk_deadline: self.chunk_deadline_ms.map(Duration::from_millis),
+            max_prompt_chars: self.max_prompt_chars,
+            progress_interval: self.progress_interval,
+            max_requests_per_second: self.max_requests_per_second,
+            fallback_model: self.fallback_model,
+            fallback_host: self.fallback_host,
+            http: HttpSettings {
+                http2_prior_knowledge: self.http2_prior_knowledge.unwrap_or_default(),
+                compression: self.http_compression.unwrap_or(true),
+                pool_max_idle_per_host: self.pool_max_idle_per_host,
+                pool_idle_timeout: self.pool_idle_timeout_ms.map(Duration::from_millis),
+            },
+            ollama_options: self.ollama_options.unwrap_or_default(),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl AiConfig {
+    /// Reads the configuration from a TOML or JSON file, see [`FileConfig`] for the
+    /// schema. Missing keys keep their defaults.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let file_config: FileConfig = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&contents)
+                .with_context(|| format!("Invalid config file {}", path.display()))?
+        } else {
+            serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid config file {}", path.display()))?
+        };
+        file_config
+            .into_config()
+            .with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Checks the values that have a restricted range.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.backend == Backend::Llama && self.model_path.is_none() {
+            anyhow::bail!("model_path is required with the llama backend");
+        }
+        for host in std::iter::once(&self.ollama_host)
+            .chain(&self.ollama_hosts)
+            .chain(&self.fallback_host)
+        {
+            if !host.starts_with("http://") && !host.starts_with("https://") {
+                anyhow::bail!("ollama_host must be an http(s) URL, got {}", host);
+            }
+        }
+        if self.items_per_prompt == 0 {
+            anyhow::bail!("items_per_prompt must be at least 1");
+        }
+        let max_prompts = Backend::Ollama.max_concurrency();
+        if let Some(prompts) = self.max_concurrent_prompts
+            && !(1..=max_prompts).contains(&prompts)
+        {
+            anyhow::bail!(
+                "max_concurrent_prompts must be between 1 and {}, got {}",
+                max_prompts,
+                prompts
+            );
+        }
+        if let Some(temperature) = self.temperature
+            && !(0.0..=2.0).contains(&temperature)
+        {
+            anyhow::bail!(
+                "temperature must be between 0.0 and 2.0, got {}",
+                temperature
+            );
+        }
+        if self.chunk_deadline == Some(Duration::ZERO) {
+            anyhow::bail!("chunk_deadline_ms must be at least 1");
+        }
+        if self.max_prompt_chars == Some(0) {
+            anyhow::bail!("max_prompt_chars must be at least 1");
+        }
+        if self.embed_dimensions == Some(0) {
+            anyhow::bail!("embed_dimensions must be at least 1");
+        }
+        if self.progress_interval == Some(0) {
+            anyhow::bail!("progress_interval must be at least 1");
+        }
+        if let Some(rps) = self.max_requests_per_second
+            && !(rps > 0.0 && rps.is_finite())
+        {
+            anyhow::bail!("max_requests_per_second must be above 0, got {}", rps);
+        }
+        Ok(())
+    }
+
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_model_path(mut self, model_path: impl Into<String>) -> Self {
+        self.model_path = Some(model_path.into());
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_chunk_deadline(mut self, deadline: Duration) -> Self {
+        self.chunk_deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_max_prompt_chars(mut self, max_prompt_chars: usize) -> Self {
+        self.max_prompt_chars = Some(max_prompt_chars);
+        self
+    }
+
+    pub fn with_ollama_options(mut self, options: Map<String, Value>) -> Self {
+        self.ollama_options = options;
+        self
+    }
+
+    pub fn with_progress_interval(mut self, progress_interval: usize) -> Self {
+        self.progress_interval = Some(progress_interval);
+        self
+    }
+
+    pub fn with_max_requests_per_second(mut self, max_requests_per_second: f64) -> Self {
+        self.max_requests_per_second = Some(max_requests_per_second);
+        self
+    }
+
+    /// Sends `ask_llm` chunks that still fail after their retries to `model` on the
+    /// Ollama server at `host`.
+    pub fn with_fallback(mut self, model: impl Into<String>, host: impl Into<String>) -> Self {
+        self.fallback_model = Some(model.into());
+        self.fallback_host = Some(host.into());
+        self
+    }
+
+    pub fn with_http(mut self, http: HttpSettings) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn with_chat_model(mut self, model: impl Into<String>) -> Self {
+        self.chat_model = model.into();
+        self
+    }
+
+    pub fn with_embed_model(mut self, model: impl Into<String>) -> Self {
+        self.embed_model = model.into();
+        self
+    }
+
+    pub fn with_windowing(mut self, windowing: WindowingConfig) -> Self {
+        self.windowing = Some(windowing);
+        self
+    }
+
+    pub fn with_items_per_prompt(mut self, items_per_prompt: usize) -> Self {
+        self.items_per_prompt = items_per_prompt;
+        self
+    }
+
+    pub fn with_max_concurrent_prompts(mut self, max_concurrent_prompts: usize) -> Self {
+        self.max_concurrent_prompts = Some(max_concurrent_prompts);
+        self
+    }
+
+    pub fn with_max_chunk_retries(mut self, max_chunk_retries: usize) -> Self {
+        self.max_chunk_retries = max_chunk_retries;
+        self
+    }
+
+    pub fn with_retry_budget(mut self, retry_budget: usize) -> Self {
+        self.retry_budget = Some(retry_budget);
+        self
+    }
+
+    pub fn with_ollama_host(mut self, host: impl Into<String>) -> Self {
+        self.ollama_host = host.into();
+        self
+    }
+
+    /// Adds Ollama servers that `ask_llm` balances its chunk requests over.
+    pub fn with_ollama_hosts(mut self, hosts: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.ollama_hosts.extend(hosts.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn chat_url(&self) -> String {
+        format!("{}/api/chat", self.ollama_host.trim_end_matches('/'))
+    }
+
+    /// The chat URLs of [`AiConfig::ollama_host`] and every [`AiConfig::ollama_hosts`].
+    pub fn chat_urls(&self) -> Vec<String> {
+        std::iter::once(&self.ollama_host)
+            .chain(&self.ollama_hosts)
+            .map(|host| format!("{}/api/chat", host.trim_end_matches('/')))
+            .collect()
+    }
+
+    /// The fallback model and its chat URL, if either fallback key is set.
+    pub fn fallback_chat(&self) -> Option<(String, String)> {
+        if self.fallback_model.is_none() && self.fallback_host.is_none() {
+            return None;
+        }
+        let model = self.fallback_model.as_ref().unwrap_or(&self.chat_model);
+        let host = self.fallback_host.as_ref().unwrap_or(&self.ollama_host);
+        Some((
+            model.clone(),
+            format!("{}/api/chat", host.trim_end_matches('/')),
+        ))
+    }
+
+    pub fn embed_url(&self) -> String {
+        format!("{}/api/embed", self.ollama_host.trim_end_matches('/'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrency_defaults_per_backend() {
+        assert_eq!(Backend::Ollama.resolve_concurrency(None), 4);
+        assert_eq!(Backend::Ollama.resolve_concurrency(Some(8)), 8);
+        assert_eq!(Backend::Ollama.resolve_concurrency(Some(64)), 32);
+        assert_eq!(Backend::Ollama.resolve_concurrency(Some(0)), 1);
+        assert_eq!(Backend::Llama.resolve_concurrency(None), 1);
+        assert_eq!(Backend::Llama.resolve_concurrency(Some(8)), 1);
+    }
+
+    #[test]
+    fn test_urls_are_derived_from_host() {
+        let config = AiConfig::default().with_ollama_host("http://gpu-box:11434/");
+        assert_eq!(config.chat_url(), "http://gpu-box:11434/api/chat");
+        assert_eq!(config.embed_url(), "http://gpu-box:11434/api/embed");
+        assert_eq!(config.fallback_chat(), None);
+        let fallback = AiConfig {
+            fallback_model: Some("llama3.2:1b".to_string()),
+            ..config.clone()
+        };
+        assert_eq!(
+            fallback.fallback_chat(),
+            Some((
+                "llama3.2:1b".to_string(),
+                "http://gpu-box:11434/api/chat".to_string()
+            ))
+        );
+        let config = config.with_ollama_hosts(["http://gpu-box:11435"]);
+        assert_eq!(
+            config.chat_urls(),
+            vec![
+                "http://gpu-box:11434/api/chat",
+                "http://gpu-box:11435/api/chat"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_file_config_fills_defaults() {
+        let file_config: FileConfig = toml::from_str(
+            r#"
+            backend = "llama"
+            model_path = "models/llama.gguf"
+            chat_model = "qwen2.5:7b"
+            temperature = 0.2
+            chunk_deadline_ms = 30000
+
+            [ollama_options]
+            repeat_penalty = 1.1
+            "#,
+        )
+        .unwrap();
+        let config = file_config.into_config().unwrap();
+        assert_eq!(config.backend, Backend::Llama);
+        assert_eq!(config.model_path.as_deref(), Some("models/llama.gguf"));
+        assert_eq!(config.chat_model, "qwen2.5:7b");
+        assert_eq!(config.embed_model, "nomic-embed-text:latest");
+        assert_eq!(config.items_per_prompt, 5);
+        assert_eq!(config.temperature, Some(0.2));
+        assert_eq!(config.chunk_deadline, Some(Duration::from_secs(30)));
+        assert_eq!(config.ollama_options["repeat_penalty"], 1.1);
+    }
+
+    #[test]
+    fn test_http_settings_are_read() {
+        let file_config: FileConfig = toml::from_str(
+            r#"
+            http2_prior_knowledge = true
+            http_compression = false
+            pool_idle_timeout_ms = 5000
+            "#,
+        )
+        .unwrap();
+        let config = file_config.into_config().unwrap();
+        assert!(config.http.http2_prior_knowledge);
+        assert!(!config.http.compression);
+        assert_eq!(config.http.pool_max_idle_per_host, None);
+        assert_eq!(config.http.pool_idle_timeout, Some(Duration::from_secs(5)));
+        config.http.client().unwrap();
+        assert_eq!(AiConfig::default().http, HttpSettings::default());
+    }
+
+    #[test]
+    fn test_file_config_is_validated() {
+        let invalid = [
+            r#"{ "backend": "llama" }"#,
+            r#"{ "items_per_prompt": 0 }"#,
+            r#"{ "max_concurrent_prompts": 64 }"#,
+            r#"{ "temperature": 3.5 }"#,
+            r#"{ "ollama_host": "localhost:11434" }"#,
+            r#"{ "ollama_hosts": ["localhost:11435"] }"#,
+            r#"{ "chunk_size": 5 }"#,
+            r#"{ "max_prompt_chars": 0 }"#,
+            r#"{ "max_requests_per_second": 0 }"#,
+            r#"{ "fallback_host": "localhost:11436" }"#,
+        ];
+        for json in invalid {
+            let result = serde_json::from_str::<FileConfig>(json)
+                .map_err(anyhow::Error::from)
+                .and_then(FileConfig::into_config);
+            assert!(result.is_err(), "{json} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_example_config_file_is_valid() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/ai_config.example.toml");
+        let config = AiConfig::from_file(path).unwrap();
+        assert_eq!(config.backend, Backend::Ollama);
+    }
+}
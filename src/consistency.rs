@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+/// Asks the model the same chunk several times and keeps the majority answer of each
+/// row, with the fraction of samples that agree with it as the row's confidence, see
+/// [`crate::llm_udf::AskLLM::with_self_consistency`].
+///
+/// This gives a confidence signal for any backend, including those without logprobs,
+/// but multiplies the cost: every chunk is sent `samples` times, so a query makes
+/// `samples` times the calls and uses `samples` times the tokens. Samples only differ
+/// when the model samples with some randomness, so use a temperature well above 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelfConsistency {
+    /// How many times each chunk is sent, at least 1.
+    pub samples: usize,
+    /// Rows whose confidence is below this are answered with NULL.
+    pub min_confidence: Option<f64>,
+}
+
+impl SelfConsistency {
+    pub fn new(samples: usize) -> Self {
+        Self {
+            samples: samples.max(1),
+            min_confidence: None,
+        }
+    }
+
+    pub fn with_min_confidence(mut self, min_confidence: f64) -> Self {
+        self.min_confidence = Some(min_confidence);
+        self
+    }
+
+    /// The majority answer and confidence of each of `rows` rows, given the answers of
+    /// each completed sample. Failed samples count as disagreeing, so the confidence
+    /// is the share of all [`SelfConsistency::samples`], and ties go to the answer
+    /// seen first. Rows without any answer get neither.
+    pub fn vote(
+        &self,
+        samples: &[Vec<Option<String>>],
+        rows: usize,
+    ) -> (Vec<Option<String>>, Vec<Option<f64>>) {
+        (0..rows)
+            .map(|row| {
+                // answer -> (votes, first sample)
+                let mut votes: HashMap<&str, (usize, usize)> = HashMap::new();
+                for (i, sample) in samples.iter().enumerate() {
+                    if let Some(Some(answer)) = sample.get(row) {
+                        votes.entry(answer).or_insert((0, i)).0 += 1;
+                    }
+                }
+                let Some((answer, (count, _))) =
+                    votes
+                        .into_iter()
+                        .max_by(|(_, (a, first_a)), (_, (b, first_b))| {
+                            a.cmp(b).then(first_b.cmp(first_a))
+                        })
+                else {
+                    return (None, None);
+                };
+                let confidence = count as f64 / self.samples.max(samples.len()) as f64;
+                let answer = Some(answer.to_string())
+                    .filter(|_| self.min_confidence.is_none_or(|min| confidence >= min));
+                (answer, Some(confidence))
+            })
+            .unzip()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(answers: &[Option<&str>]) -> Vec<Option<String>> {
+        answers.iter().map(|a| a.map(str::to_string)).collect()
+    }
+
+    #[test]
+    fn test_vote_keeps_the_majority_and_its_share() {
+        let consistency = SelfConsistency::new(4);
+        // the fourth sample failed
+        let samples = [
+            sample(&[Some("pos"), Some("neg"), None]),
+            sample(&[Some("pos"), Some("neu"), None]),
+            sample(&[Some("neg"), Some("neg"), None]),
+        ];
+        let (answers, confidences) = consistency.vote(&samples, 3);
+        assert_eq!(answers, sample(&[Some("pos"), Some("neg"), None]));
+        assert_eq!(confidences, [Some(0.5), Some(0.5), None]);
+
+        let (answers, confidences) = consistency.with_min_confidence(0.6).vote(&samples, 3);
+        assert_eq!(answers, [None, None, None]);
+        assert_eq!(confidences, [Some(0.5), Some(0.5), None]);
+    }
+}
@@ -0,0 +1,280 @@
+use datafusion::arrow::array::{Array, ArrayRef};
+use datafusion::arrow::datatypes::DataType;
+use datafusion_common::cast::as_string_array;
+use datafusion_common::{Result, ScalarValue, plan_err};
+use datafusion_doc::Documentation;
+use datafusion_expr::ScalarUDFImpl;
+use datafusion_expr::{ColumnarValue, ScalarFunctionArgs, Signature, Volatility};
+use datafusion_macros::user_doc;
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::config::AiConfig;
+use crate::llm_udf::AskLLM;
+
+/// The default system prompt of `ask_llm_context`, which explains the labeled context that
+/// follows each item.
+pub const ASK_LLM_CONTEXT_SYSTEM_PROMPT: &str = "\
+    You evaluate a numbered list of items according to the user's instruction. Items may \
+    be followed by labeled context in parentheses; use it to inform the answer, but \
+    answer about the item itself. Answer every item, in order, on its own line formatted \
+    as 'N -> answer' where N is the item number.";
+
+/// One row as seen by the prompt builder: the value being answered and the labeled
+/// context values of the same row, in argument order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowContext<'a> {
+    pub value: Option<&'a str>,
+    pub context: Vec<(&'a str, Option<&'a str>)>,
+}
+
+impl RowContext<'_> {
+    /// The context value with the given label, `None` if it is missing or NULL.
+    pub fn get(&self, label: &str) -> Option<&str> {
+        self.context
+            .iter()
+            .find(|(name, _)| *name == label)
+            .and_then(|(_, value)| *value)
+    }
+}
+
+// renders a row as the item text sent to the model, see AskLlmWithContext::with_prompt_builder
+#[derive(Clone)]
+struct PromptBuilder(Arc<dyn Fn(&RowContext) -> String + Send + Sync>);
+
+impl std::fmt::Debug for PromptBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PromptBuilder").finish_non_exhaustive()
+    }
+}
+
+/// The default item text: the value followed by its labeled context, e.g.
+/// `Late again (customer: ACME, plan: gold)`. NULL context values are left out.
+pub fn default_prompt_builder(row: &RowContext) -> String {
+    let value = row.value.unwrap_or_default();
+    let context: Vec<String> = row
+        .context
+        .iter()
+        .filter_map(|(label, value)| value.map(|value| format!("{label}: {value}")))
+        .collect();
+    if context.is_empty() {
+        return value.to_string();
+    }
+    format!("{} ({})", value, context.join(", "))
+}
+
+/// Like `ask_llm`, but with labeled context columns that inform the answer without
+/// being the thing that is answered. Context is passed as label/column pairs after
+/// the value, e.g.
+///
+/// ```sql
+/// ask_llm_context('Categorize the feedback', feedback, 'customer', customer_name, 'plan', plan)
+/// ```
+///
+/// Each row is turned into an item of the prompt by a prompt builder, which sees the
+/// value and every context value by label (see [`AskLlmWithContext::with_prompt_builder`]
+/// and [`default_prompt_builder`]). Rows with a NULL value are NULL. Everything else is
+/// [`AskLLM`]'s, with the items in place of the values.
+#[user_doc(
+    doc_section(label = "AI functions"),
+    description = "Ask LLM about a value, with labeled context columns from the same row",
+    syntax_example = "ask_llm_context('instruction', column_value, 'label', context_column, ...)"
+)]
+#[derive(Debug)]
+pub struct AskLlmWithContext {
+    ask_llm: AskLLM,
+    signature: Signature,
+    prompt_builder: PromptBuilder,
+}
+
+impl AskLlmWithContext {
+    pub fn new() -> Self {
+        Self::with_config(&AiConfig::default())
+    }
+
+    /// Creates the UDF using the chat model from `config`, with
+    /// [`ASK_LLM_CONTEXT_SYSTEM_PROMPT`].
+    pub fn with_config(config: &AiConfig) -> Self {
+        Self::with_ask_llm(
+            AskLLM::with_config(config).with_system_prompt(ASK_LLM_CONTEXT_SYSTEM_PROMPT),
+        )
+    }
+
+    /// Asks `ask_llm` about the rendered items, with all its settings (prompt, chunking,
+    /// retries, output type, ...).
+    pub fn with_ask_llm(ask_llm: AskLLM) -> Self {
+        Self {
+            ask_llm: ask_llm.with_name("ask_llm_context"),
+            signature: Signature::variadic(vec![DataType::Utf8], Volatility::Volatile),
+            prompt_builder: PromptBuilder(Arc::new(default_prompt_builder)),
+        }
+    }
+
+    /// Registers the function under `name` instead of its default name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.ask_llm = self.ask_llm.with_name(name);
+        self
+    }
+
+    /// Sets how the function advertises itself to the planner, [`Volatility::Volatile`]
+    /// by default, see [`AskLLM::with_volatility`].
+    pub fn with_volatility(mut self, volatility: Volatility) -> Self {
+        self.signature = Signature::variadic(vec![DataType::Utf8], volatility);
+        self
+    }
+
+    /// Replaces the system prompt, [`ASK_LLM_CONTEXT_SYSTEM_PROMPT`] by default, see
+    /// [`AskLLM::with_system_prompt`] for how it relates to the model's own system prompt.
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.ask_llm = self.ask_llm.with_system_prompt(system_prompt);
+        self
+    }
+
+    /// Replaces [`default_prompt_builder`] with a custom rendering of each row.
+    pub fn with_prompt_builder(
+        mut self,
+        prompt_builder: impl Fn(&RowContext) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.prompt_builder = PromptBuilder(Arc::new(prompt_builder));
+        self
+    }
+}
+
+impl Default for AskLlmWithContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// splits the arguments after the value into (label, column) pairs
+fn context_columns(args: &[ColumnarValue]) -> Result<Vec<(&str, &ArrayRef)>> {
+    if args.len() % 2 != 0 {
+        return plan_err!("ask_llm_context expects context as 'label', column pairs");
+    }
+    args.chunks(2)
+        .map(|pair| match pair {
+            [
+                ColumnarValue::Scalar(ScalarValue::Utf8(Some(label))),
+                ColumnarValue::Array(column),
+            ] => Ok((label.as_str(), column)),
+            _ => plan_err!("ask_llm_context expects context as 'label', column pairs"),
+        })
+        .collect()
+}
+
+impl ScalarUDFImpl for AskLlmWithContext {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn name(&self) -> &str {
+        self.ask_llm.name()
+    }
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+    fn return_type(&self, args: &[DataType]) -> Result<DataType> {
+        if args.len() < 2 || args.iter().any(|arg| arg != &DataType::Utf8) {
+            return plan_err!("ask_llm_context only accepts at least 2 Utf8 arguments");
+        }
+        Ok(self.ask_llm.answer_type().clone())
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        let ScalarFunctionArgs { args, .. } = args;
+        let (instruction, col_values, context) = match args.as_slice() {
+            [
+                ColumnarValue::Scalar(ScalarValue::Utf8(instruction)),
+                ColumnarValue::Array(col_values),
+                context @ ..,
+            ] => (
+                instruction.as_deref().unwrap_or_default(),
+                col_values,
+                context_columns(context)?,
+            ),
+            _ => {
+                return plan_err!(
+                    "ask_llm_context only accepts arguments in the form of 'instruction' (string), 'column_value' (column), followed by 'label' (string), context (column) pairs"
+                );
+            }
+        };
+        let col_values = as_string_array(col_values.as_ref())?;
+        let context = context
+            .into_iter()
+            .map(|(label, column)| Ok((label, as_string_array(column.as_ref())?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let items: Vec<Option<String>> = (0..col_values.len())
+            .map(|i| {
+                if col_values.is_null(i) {
+                    return None;
+                }
+                let row = RowContext {
+                    value: Some(col_values.value(i)),
+                    context: context
+                        .iter()
+                        .map(|(label, column)| {
+                            (*label, (!column.is_null(i)).then(|| column.value(i)))
+                        })
+                        .collect(),
+                };
+                Some((self.prompt_builder.0)(&row))
+            })
+            .collect();
+        let items: Vec<Option<&str>> = items.iter().map(Option::as_deref).collect();
+        let answers = self.ask_llm.answer_columns(instruction, &[items])?;
+        Ok(ColumnarValue::Array(answers.into_iter().next().unwrap()))
+    }
+
+    fn documentation(&self) -> Option<&Documentation> {
+        self.doc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_udf::tests::{query, spawn_mock_server};
+    use datafusion::arrow::array::{AsArray, StringArray};
+
+    #[test]
+    fn test_default_prompt_builder_labels_context() {
+        let row = RowContext {
+            value: Some("Late again"),
+            context: vec![("customer", Some("ACME")), ("plan", None)],
+        };
+        assert_eq!(default_prompt_builder(&row), "Late again (customer: ACME)");
+        assert_eq!(row.get("customer"), Some("ACME"));
+        assert_eq!(row.get("plan"), None);
+    }
+
+    #[test]
+    fn test_context_must_come_in_labeled_pairs() {
+        let column: ArrayRef = Arc::new(StringArray::from(vec!["ACME"]));
+        let label = ColumnarValue::Scalar(ScalarValue::Utf8(Some("customer".to_string())));
+        let args = vec![label.clone(), ColumnarValue::Array(column.clone())];
+        let pairs = context_columns(&args).unwrap();
+        assert_eq!(pairs[0].0, "customer");
+        assert!(context_columns(&args[..1]).is_err());
+        assert!(context_columns(&[ColumnarValue::Array(column), label]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_items_are_sent_with_their_context() {
+        let (host, requests) = spawn_mock_server(|request| {
+            assert!(request.contains("Late (customer: ACME)"), "{request}");
+            let body = r#"{"message":{"content":"1 -> negative"},"done_reason":"stop"}"#;
+            (200, body.to_string())
+        });
+        let config = AiConfig::default().with_ollama_host(host);
+        let batches = query(
+            AskLlmWithContext::with_config(&config),
+            "SELECT ask_llm_context('Categorize', column1, 'customer', column2) \
+             FROM (VALUES ('Late', 'ACME'), (NULL, 'ACME'))",
+        )
+        .await;
+        let answers = batches[0].column(0).as_string::<i32>();
+        assert_eq!(answers.value(0), "negative");
+        assert!(answers.is_null(1));
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
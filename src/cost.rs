@@ -0,0 +1,74 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Prices of prompt and completion tokens, in any currency, for
+/// [`crate::llm_udf::AskLLM::with_cost_callback`].
+///
+/// The default prices every token at zero, which is what a local Ollama server
+/// costs per token; the spend of a local backend is its time, see
+/// [`RowCost::backend_time`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TokenPricing {
+    pub per_prompt_token: f64,
+    pub per_completion_token: f64,
+}
+
+impl TokenPricing {
+    /// Prices given per million tokens, as hosted APIs list them.
+    pub fn per_million(prompt: f64, completion: f64) -> Self {
+        Self {
+            per_prompt_token: prompt / 1e6,
+            per_completion_token: completion / 1e6,
+        }
+    }
+
+    pub fn cost(&self, prompt_tokens: f64, completion_tokens: f64) -> f64 {
+        prompt_tokens * self.per_prompt_token + completion_tokens * self.per_completion_token
+    }
+}
+
+/// The share of a chunk's backend usage attributed to one of its rows.
+///
+/// A chunk's usage is the sum over all its requests, including retries, re-prompts,
+/// self-consistency samples, windows and the fallback, split evenly over its rows.
+/// Tokens are the counts the backend reports (`prompt_eval_count` and `eval_count`
+/// from Ollama, `usage` from OpenAI-compatible servers); a backend that reports none
+/// counts zero tokens. Rows answered from the response cache cost nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowCost {
+    /// The model that answered the row, `None` if no request was made.
+    pub model: Option<String>,
+    pub prompt_tokens: f64,
+    pub completion_tokens: f64,
+    /// The row's share of the time spent waiting for the backend.
+    pub backend_time: Duration,
+    /// The tokens priced with the [`TokenPricing`] of the callback.
+    pub cost: f64,
+}
+
+// called for every completed row, see AskLLM::with_cost_callback
+#[derive(Clone)]
+pub(crate) struct CostCallback {
+    pub pricing: TokenPricing,
+    pub callback: Arc<dyn Fn(&RowCost) + Send + Sync>,
+}
+
+impl std::fmt::Debug for CostCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CostCallback")
+            .field("pricing", &self.pricing)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pricing_per_million_tokens() {
+        let pricing = TokenPricing::per_million(0.5, 1.5);
+        assert!((pricing.cost(2_000.0, 1_000.0) - 0.0025).abs() < 1e-12);
+        assert_eq!(TokenPricing::default().cost(2_000.0, 1_000.0), 0.0);
+    }
+}
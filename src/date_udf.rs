@@ -0,0 +1,173 @@
+use datafusion::arrow::datatypes::DataType;
+use datafusion_common::Result;
+use datafusion_doc::Documentation;
+use datafusion_expr::ScalarUDFImpl;
+use datafusion_expr::{ColumnarValue, ReturnInfo, ReturnTypeArgs, ScalarFunctionArgs, Signature};
+use datafusion_macros::user_doc;
+use std::any::Any;
+
+use crate::config::AiConfig;
+use crate::llm_udf::AskLLM;
+use crate::output_builder::OutputBuilder;
+
+/// Extracts dates from text as typed values, e.g.
+///
+/// ```sql
+/// ask_llm_date('When was the order placed?', message)
+/// ```
+///
+/// returns a `Date32` column (or `Timestamp(Nanosecond)` with
+/// [`AskLlmDate::with_timestamp_output`]). The model is asked to answer in the chrono
+/// (strftime-like) format of [`AskLlmDate::with_format`], `%Y-%m-%d` by default, and
+/// each answer is parsed with that format, see [`OutputBuilder::date32_with_format`]
+/// and [`OutputBuilder::timestamp_with_format`]. Answers that do not parse, such as
+/// "no date mentioned", are NULL.
+///
+/// Everything else is [`AskLLM`]'s, including sidecar columns, which return the date
+/// in their `answer` field (`value` with [`AskLLM::with_error_column`]).
+#[user_doc(
+    doc_section(label = "AI functions"),
+    description = "Ask LLM to extract a date from each row, returned as a Date32 (or Timestamp) parsed with a configurable format",
+    syntax_example = "ask_llm_date('instruction', 'column_value')"
+)]
+#[derive(Debug)]
+pub struct AskLlmDate {
+    ask_llm: AskLLM,
+    format: String,
+    timestamps: bool,
+}
+
+impl AskLlmDate {
+    pub fn new() -> Self {
+        Self::with_config(&AiConfig::default())
+    }
+
+    /// Creates the UDF using the chat model from `config`.
+    pub fn with_config(config: &AiConfig) -> Self {
+        Self::with_ask_llm(AskLLM::with_config(config))
+    }
+
+    /// Asks `ask_llm` for the dates, with all its settings (prompt, chunking, caches,
+    /// ...); its output type is replaced, and the format is requested after its
+    /// (wrapped) instruction.
+    pub fn with_ask_llm(ask_llm: AskLLM) -> Self {
+        Self {
+            ask_llm: ask_llm.with_name("ask_llm_date"),
+            format: "%Y-%m-%d".to_string(),
+            timestamps: false,
+        }
+        .configured()
+    }
+
+    /// Registers the function under `name` instead of its default name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.ask_llm = self.ask_llm.with_name(name);
+        self
+    }
+
+    /// The chrono format answers are requested in and parsed with, e.g. `%d/%m/%Y`
+    /// or `%Y-%m-%d %H:%M` together with [`AskLlmDate::with_timestamp_output`].
+    pub fn with_format(mut self, format: impl Into<String>) -> Self {
+        self.format = format.into();
+        self.configured()
+    }
+
+    /// Returns `Timestamp(Nanosecond)` values instead of dates. A format without a
+    /// time of day reads midnight.
+    pub fn with_timestamp_output(mut self) -> Self {
+        self.timestamps = true;
+        self.configured()
+    }
+
+    // sets the output and the format hint of the AskLLM the calls are delegated to
+    fn configured(mut self) -> Self {
+        let output = if self.timestamps {
+            OutputBuilder::timestamp_with_format(&self.format)
+        } else {
+            OutputBuilder::date32_with_format(&self.format)
+        };
+        let hint = format!(
+            " Answer with only the date, formatted as the strftime format `{}`.",
+            self.format
+        );
+        self.ask_llm = self
+            .ask_llm
+            .with_output_builder(output)
+            .with_instruction_hint(hint);
+        self
+    }
+}
+
+impl Default for AskLlmDate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScalarUDFImpl for AskLlmDate {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn name(&self) -> &str {
+        self.ask_llm.name()
+    }
+    fn signature(&self) -> &Signature {
+        self.ask_llm.signature()
+    }
+    fn return_type(&self, args: &[DataType]) -> Result<DataType> {
+        self.ask_llm.return_type(args)
+    }
+    fn coerce_types(&self, arg_types: &[DataType]) -> Result<Vec<DataType>> {
+        self.ask_llm.coerce_types(arg_types)
+    }
+    fn return_type_from_args(&self, args: ReturnTypeArgs) -> Result<ReturnInfo> {
+        self.ask_llm.return_type_from_args(args)
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        self.ask_llm.invoke_with_args(args)
+    }
+
+    fn documentation(&self) -> Option<&Documentation> {
+        self.doc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dry_run::DryRun;
+    use datafusion::arrow::datatypes::TimeUnit;
+
+    #[tokio::test]
+    async fn test_dates_are_returned_typed() {
+        let ctx = datafusion::prelude::SessionContext::new();
+        let dry_run = DryRun::new();
+        ctx.register_udf(datafusion_expr::ScalarUDF::from(
+            AskLlmDate::with_ask_llm(AskLLM::new().with_dry_run(dry_run.clone()))
+                .with_format("%d/%m/%Y"),
+        ));
+        ctx.register_udf(datafusion_expr::ScalarUDF::from(
+            AskLlmDate::with_ask_llm(AskLLM::new().with_dry_run(dry_run))
+                .with_name("ask_llm_timestamp")
+                .with_timestamp_output(),
+        ));
+        let batches = ctx
+            .sql(
+                "SELECT ask_llm_date('When was it ordered?', column1) AS d, \
+                 ask_llm_timestamp('When was it ordered?', column1) AS t \
+                 FROM (VALUES ('Ordered on 1 March 2024'))",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let schema = batches[0].schema();
+        assert_eq!(schema.field(0).data_type(), &DataType::Date32);
+        assert_eq!(
+            schema.field(1).data_type(),
+            &DataType::Timestamp(TimeUnit::Nanosecond, None)
+        );
+    }
+}
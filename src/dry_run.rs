@@ -0,0 +1,80 @@
+use std::sync::{Arc, Mutex};
+
+/// The backend usage `ask_llm` expects for a set of rows, see
+/// [`crate::llm_udf::AskLLM::dry_run_plan`].
+///
+/// Token counts are rough: prompts are estimated at about four characters per token
+/// and every answer at [`COMPLETION_TOKENS_PER_ROW`] tokens. Retries, format
+/// re-prompts and count-mismatch re-requests are not included, so the estimate is
+/// a lower bound when the model misbehaves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CallEstimate {
+    /// Rows evaluated; a run-end encoded column counts one row per run.
+    pub rows: usize,
+    /// Rows answered without the model: missing inputs, inputs skipped by the input
+    /// guard and rows resolved by the pre-filter.
+    pub local_rows: usize,
+    /// Rows expected to be served by the answer cache, either because they are already
+    /// cached or because they repeat an earlier value.
+    pub cached_rows: usize,
+    /// Rows sent to the model.
+    pub prompted_rows: usize,
+    /// Backend requests.
+    pub calls: usize,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+/// The completion tokens assumed per answered row: the `n -> ` prefix, a short answer
+/// and the line break.
+pub const COMPLETION_TOKENS_PER_ROW: usize = 8;
+
+impl std::ops::AddAssign for CallEstimate {
+    fn add_assign(&mut self, other: Self) {
+        self.rows += other.rows;
+        self.local_rows += other.local_rows;
+        self.cached_rows += other.cached_rows;
+        self.prompted_rows += other.prompted_rows;
+        self.calls += other.calls;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+impl std::fmt::Display for CallEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} rows, {} sent to the model in {} calls (~{} prompt and ~{} completion tokens), \
+             {} answered locally, {} from the cache",
+            self.rows,
+            self.prompted_rows,
+            self.calls,
+            self.prompt_tokens,
+            self.completion_tokens,
+            self.local_rows,
+            self.cached_rows
+        )
+    }
+}
+
+/// Puts `ask_llm` in dry-run mode (see [`crate::llm_udf::AskLLM::with_dry_run`]): every
+/// invocation adds its [`CallEstimate`] here and returns NULLs without calling the
+/// model, so running a query gives the estimate for exactly the rows it would send.
+#[derive(Debug, Clone, Default)]
+pub struct DryRun(Arc<Mutex<CallEstimate>>);
+
+impl DryRun {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, estimate: CallEstimate) {
+        *self.0.lock().unwrap() += estimate;
+    }
+
+    /// The estimate summed over every invocation so far.
+    pub fn total(&self) -> CallEstimate {
+        *self.0.lock().unwrap()
+    }
+}
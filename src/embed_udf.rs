@@ -0,0 +1,262 @@
+use datafusion::arrow::array::{ArrayRef, FixedSizeListBuilder, Float32Builder, ListBuilder};
+use datafusion::arrow::datatypes::{DataType, Field};
+use datafusion_common::cast::as_string_array;
+use datafusion_common::{DataFusionError, Result, plan_err};
+use datafusion_doc::Documentation;
+use datafusion_expr::ScalarUDFImpl;
+use datafusion_expr::{ColumnarValue, ScalarFunctionArgs, Signature, Volatility};
+use datafusion_macros::user_doc;
+use rayon::prelude::*;
+use std::any::Any;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use crate::config::AiConfig;
+use crate::ollama_utils::{HttpSettings, OllamaApp};
+use crate::runtime::RuntimeConfig;
+use crate::shared_backend::SharedBackend;
+use crate::telemetry;
+
+#[user_doc(
+    doc_section(label = "AI functions"),
+    description = "Compute an embedding vector for each value using Ollama",
+    syntax_example = "ollama_embed('column_value')"
+)]
+#[derive(Debug)]
+pub struct OllamaEmbed {
+    name: String,
+    signature: Signature,
+    ollama_model: Arc<str>,
+    ollama_url: String,
+    dimensions: Option<usize>,
+    http: HttpSettings,
+    backend: OnceLock<SharedBackend>,
+}
+
+impl OllamaEmbed {
+    pub fn new() -> Self {
+        Self::with_config(&AiConfig::default())
+    }
+
+    /// Creates the UDF using the embedding model from `config`.
+    pub fn with_config(config: &AiConfig) -> Self {
+        Self {
+            name: "ollama_embed".to_string(),
+            signature: Signature::exact(vec![DataType::Utf8], Volatility::Immutable),
+            ollama_model: config.embed_model.as_str().into(),
+            ollama_url: config.embed_url(),
+            dimensions: config.embed_dimensions,
+            http: config.http.clone(),
+            backend: OnceLock::new(),
+        }
+    }
+
+    /// Registers the function under `name` instead of its default name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Declares the embedding size of the model, so the output is a
+    /// `FixedSizeList<Float32>` of `dimensions` values, built in one contiguous
+    /// array. Without it the output is a variable-size `List<Float32>`. Either way an
+    /// embedding of a different size than the others fails the invocation.
+    pub fn with_dimensions(mut self, dimensions: usize) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    // the runtime and HTTP client of all chunks, built on first use and kept, so pooled
+    // connections stay with the runtime that drives them
+    fn backend(&self) -> &SharedBackend {
+        self.backend.get_or_init(|| {
+            SharedBackend::with_settings(RuntimeConfig::default(), &self.http)
+                .expect("Failed to create the ollama_embed backend")
+        })
+    }
+
+    fn item_field() -> Arc<Field> {
+        Arc::new(Field::new_list_field(DataType::Float32, true))
+    }
+
+    // appends every row to a single builder; NULL rows of a fixed-size list still
+    // take `dimensions` (null) slots in the values
+    fn build_output(&self, embeddings: Vec<Option<Vec<f32>>>) -> Result<ArrayRef> {
+        let rows = embeddings.len();
+        let Some(dimensions) = self.dimensions.or_else(|| {
+            // a variable-size list only needs the embeddings to agree with each other
+            embeddings.iter().flatten().next().map(Vec::len)
+        }) else {
+            let mut builder = ListBuilder::new(Float32Builder::new());
+            builder.append_nulls(rows);
+            return Ok(Arc::new(builder.finish()));
+        };
+        let check = |embedding: &[f32]| {
+            if embedding.len() != dimensions {
+                return Err(DataFusionError::Execution(format!(
+                    "ollama_embed: embedding of {} dimensions, expected {}",
+                    embedding.len(),
+                    dimensions
+                )));
+            }
+            Ok(())
+        };
+        if self.dimensions.is_none() {
+            let mut builder = ListBuilder::with_capacity(Float32Builder::new(), rows);
+            for embedding in embeddings {
+                match embedding {
+                    Some(embedding) => {
+                        check(&embedding)?;
+                        builder.values().append_slice(&embedding);
+                        builder.append(true);
+                    }
+                    None => builder.append(false),
+                }
+            }
+            return Ok(Arc::new(builder.finish()));
+        }
+        let values = Float32Builder::with_capacity(rows * dimensions);
+        let mut builder = FixedSizeListBuilder::with_capacity(values, dimensions as i32, rows);
+        for embedding in embeddings {
+            match embedding {
+                Some(embedding) => {
+                    check(&embedding)?;
+                    builder.values().append_slice(&embedding);
+                    builder.append(true);
+                }
+                None => {
+                    builder.values().append_nulls(dimensions);
+                    builder.append(false);
+                }
+            }
+        }
+        Ok(Arc::new(builder.finish()))
+    }
+
+    async fn process_chunk(&self, vals: &[String]) -> Result<Vec<Vec<f32>>> {
+        if vals.is_empty() {
+            return Ok(vec![]);
+        }
+        let client = self.backend().client().clone();
+        let ollama_app = OllamaApp::new(&self.ollama_model, &self.ollama_url)
+            .map_err(|e| DataFusionError::Internal(e.to_string()))?
+            .with_client(client);
+        let request_start = Instant::now();
+        let embeddings = ollama_app.embed(vals).await;
+        telemetry::record_request(
+            &self.ollama_model,
+            embeddings.is_ok(),
+            request_start.elapsed(),
+        );
+        let embeddings = embeddings.map_err(|e| DataFusionError::Internal(e.to_string()))?;
+        if embeddings.len() != vals.len() {
+            telemetry::record_count_mismatch(&self.ollama_model);
+            return Err(DataFusionError::Internal(format!(
+                "mismatched embedding count: {} != {}",
+                embeddings.len(),
+                vals.len()
+            )));
+        }
+        Ok(embeddings)
+    }
+}
+
+impl Default for OllamaEmbed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScalarUDFImpl for OllamaEmbed {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+    fn return_type(&self, args: &[DataType]) -> Result<DataType> {
+        if !matches!(args.first(), Some(&DataType::Utf8)) {
+            return plan_err!("ollama_embed only accepts Utf8 arguments");
+        }
+        Ok(match self.dimensions {
+            Some(dimensions) => DataType::FixedSizeList(Self::item_field(), dimensions as i32),
+            None => DataType::List(Self::item_field()),
+        })
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        let ScalarFunctionArgs { args, .. } = args;
+        let Some(ColumnarValue::Array(col_values)) = args.into_iter().next() else {
+            return plan_err!(
+                "ollama_embed only accepts 1 argument in the form of 'column_value' (column)"
+            );
+        };
+        let col_values = as_string_array(col_values.as_ref())?;
+        let values: Vec<_> = col_values.iter().collect();
+        let chunk_size = 16;
+
+        let result: Vec<Option<Vec<f32>>> = values
+            .par_chunks(chunk_size)
+            .flat_map(|chunk| {
+                // NULL inputs are not sent to the model and stay NULL in the output
+                let vals: Vec<String> = chunk.iter().flatten().map(|v| v.to_string()).collect();
+                match self.backend().runtime().block_on(self.process_chunk(&vals)) {
+                    Ok(embeddings) => {
+                        let mut embeddings = embeddings.into_iter();
+                        chunk
+                            .iter()
+                            .map(|opt| opt.and_then(|_| embeddings.next()))
+                            .collect()
+                    }
+                    Err(e) => {
+                        println!("Error processing embedding chunk: {}", e);
+                        vec![None; chunk.len()]
+                    }
+                }
+            })
+            .collect();
+
+        Ok(ColumnarValue::Array(self.build_output(result)?))
+    }
+
+    fn documentation(&self) -> Option<&Documentation> {
+        self.doc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Array, AsArray};
+
+    #[test]
+    fn test_output_is_one_contiguous_fixed_size_list() {
+        let embed = OllamaEmbed::new().with_dimensions(2);
+        let output = embed
+            .build_output(vec![Some(vec![0.1, 0.2]), None, Some(vec![0.3, 0.4])])
+            .unwrap();
+        assert_eq!(
+            output.data_type(),
+            &embed.return_type(&[DataType::Utf8]).unwrap()
+        );
+        let list = output.as_fixed_size_list();
+        assert_eq!(list.len(), 3);
+        assert!(list.is_null(1));
+        assert_eq!(list.values().len(), 6);
+        assert!(embed.build_output(vec![Some(vec![0.1])]).is_err());
+
+        let embed = OllamaEmbed::new();
+        let output = embed
+            .build_output(vec![None, Some(vec![0.1, 0.2])])
+            .unwrap();
+        assert_eq!(output.as_list::<i32>().value_length(1), 2);
+        assert!(
+            embed
+                .build_output(vec![Some(vec![0.1, 0.2]), Some(vec![0.3])])
+                .is_err()
+        );
+    }
+}
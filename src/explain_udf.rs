@@ -0,0 +1,232 @@
+use datafusion::arrow::array::{ArrayRef, StringArray, StructArray};
+use datafusion::arrow::datatypes::{DataType, Field, Fields};
+use datafusion_common::cast::as_string_array;
+use datafusion_common::{Result, ScalarValue, plan_err};
+use datafusion_doc::Documentation;
+use datafusion_expr::ScalarUDFImpl;
+use datafusion_expr::{ColumnarValue, ScalarFunctionArgs, Signature, Volatility};
+use datafusion_macros::user_doc;
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::config::AiConfig;
+use crate::llm_udf::AskLLM;
+
+/// The default system prompt of `ask_llm_explain`, which asks for an answer and a one-line
+/// reason per item.
+pub const ASK_LLM_EXPLAIN_SYSTEM_PROMPT: &str = "\
+    You evaluate a numbered list of items according to the user's instruction and \
+    justify each answer. Answer every item, in order, on its own line starting with \
+    'N -> ' where N is the item number, followed by a short answer and a one-line reason \
+    in the requested format.";
+
+/// Answers every row together with the model's one-line justification, for auditing
+/// surprising labels. The model is asked for `n -> answer | reason` lines and each
+/// answer is split on the first occurrence of the delimiter (`|` by default, see
+/// [`AskLlmExplain::with_delimiter`]). Rows whose line has no delimiter get a NULL
+/// reason. Everything else, including the post-processing and type of the answers, is
+/// [`AskLLM`]'s.
+///
+/// Asking for reasons makes responses several times longer, so this is a separate
+/// UDF that is not registered by default (see [`crate::AiUdf::DEFAULT`]); plain
+/// `ask_llm` does not pay for it.
+#[user_doc(
+    doc_section(label = "AI functions"),
+    description = "Ask LLM and return each answer with the model's reason",
+    syntax_example = "ask_llm_explain('instruction', 'column_value')['reason']"
+)]
+#[derive(Debug)]
+pub struct AskLlmExplain {
+    ask_llm: AskLLM,
+    signature: Signature,
+    delimiter: String,
+}
+
+impl AskLlmExplain {
+    pub fn new() -> Self {
+        Self::with_config(&AiConfig::default())
+    }
+
+    /// Creates the UDF using the chat model from `config`, with
+    /// [`ASK_LLM_EXPLAIN_SYSTEM_PROMPT`].
+    pub fn with_config(config: &AiConfig) -> Self {
+        Self::with_ask_llm(
+            AskLLM::with_config(config).with_system_prompt(ASK_LLM_EXPLAIN_SYSTEM_PROMPT),
+        )
+    }
+
+    /// Asks `ask_llm` for the answers and reasons, with all its settings (prompt,
+    /// chunking, retries, post-processing, ...); the reason is requested after its
+    /// (wrapped) instruction and split off before its post-processing.
+    pub fn with_ask_llm(ask_llm: AskLLM) -> Self {
+        Self {
+            ask_llm: ask_llm.with_name("ask_llm_explain"),
+            signature: Signature::exact(vec![DataType::Utf8, DataType::Utf8], Volatility::Volatile),
+            delimiter: "|".to_string(),
+        }
+        .configured()
+    }
+
+    /// Registers the function under `name` instead of its default name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.ask_llm = self.ask_llm.with_name(name);
+        self
+    }
+
+    /// Sets how the function advertises itself to the planner, [`Volatility::Volatile`]
+    /// by default, see [`AskLLM::with_volatility`].
+    pub fn with_volatility(mut self, volatility: Volatility) -> Self {
+        self.signature = Signature::exact(vec![DataType::Utf8, DataType::Utf8], volatility);
+        self
+    }
+
+    /// Replaces the system prompt, [`ASK_LLM_EXPLAIN_SYSTEM_PROMPT`] by default, see
+    /// [`AskLLM::with_system_prompt`] for how it relates to the model's own system prompt.
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.ask_llm = self.ask_llm.with_system_prompt(system_prompt);
+        self
+    }
+
+    /// Separates the answer from the reason, `|` by default. Pick a delimiter that
+    /// does not occur in the answers themselves.
+    pub fn with_delimiter(mut self, delimiter: impl Into<String>) -> Self {
+        self.delimiter = delimiter.into();
+        self.configured()
+    }
+
+    // sets the reason hint and split of the AskLLM the calls are delegated to
+    fn configured(mut self) -> Self {
+        let hint = format!(
+            " After each answer, add ' {} ' followed by a one-line reason for it.",
+            self.delimiter
+        );
+        let delimiter = self.delimiter.clone();
+        self.ask_llm = self
+            .ask_llm
+            .with_instruction_hint(hint)
+            .with_detail_split(move |answer| split_reason(answer, &delimiter));
+        self
+    }
+
+    fn output_fields(&self) -> Fields {
+        Fields::from(vec![
+            Field::new("answer", self.ask_llm.answer_type().clone(), true),
+            Field::new("reason", DataType::Utf8, true),
+        ])
+    }
+}
+
+impl Default for AskLlmExplain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn split_reason(answer: &str, delimiter: &str) -> (String, Option<String>) {
+    match answer.split_once(delimiter) {
+        Some((answer, reason)) => {
+            let reason = reason.trim();
+            (
+                answer.trim().to_string(),
+                (!reason.is_empty()).then(|| reason.to_string()),
+            )
+        }
+        None => (answer.trim().to_string(), None),
+    }
+}
+
+impl ScalarUDFImpl for AskLlmExplain {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn name(&self) -> &str {
+        self.ask_llm.name()
+    }
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+    fn return_type(&self, args: &[DataType]) -> Result<DataType> {
+        if !matches!(args.first(), Some(&DataType::Utf8)) {
+            return plan_err!("ask_llm_explain only accepts Utf8 arguments");
+        }
+        Ok(DataType::Struct(self.output_fields()))
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        let ScalarFunctionArgs { args, .. } = args;
+        let (instruction, col_values) = match args.as_slice() {
+            [
+                ColumnarValue::Scalar(ScalarValue::Utf8(instruction)),
+                ColumnarValue::Array(col_values),
+            ] => (instruction.as_deref().unwrap_or_default(), col_values),
+            _ => {
+                return plan_err!(
+                    "ask_llm_explain only accepts 2 arguments in the form of 'instruction' (string), 'column_value' (column)"
+                );
+            }
+        };
+        let col_values = as_string_array(col_values.as_ref())?;
+        let (answers, reasons) = self
+            .ask_llm
+            .answer_with_details(instruction, col_values.iter().collect())?;
+        let columns: Vec<ArrayRef> = vec![answers, Arc::new(StringArray::from(reasons))];
+        let result = StructArray::try_new(self.output_fields(), columns, None)?;
+        Ok(ColumnarValue::Array(Arc::new(result)))
+    }
+
+    fn documentation(&self) -> Option<&Documentation> {
+        self.doc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_udf::tests::{invoke, spawn_mock_server, strings};
+    use datafusion::arrow::array::AsArray;
+
+    #[test]
+    fn test_split_reason() {
+        assert_eq!(
+            split_reason("negative | mentions a broken item", "|"),
+            (
+                "negative".to_string(),
+                Some("mentions a broken item".to_string())
+            )
+        );
+        assert_eq!(
+            split_reason("positive", "|"),
+            ("positive".to_string(), None)
+        );
+        assert_eq!(
+            split_reason("neutral ;; no opinion | given", ";;"),
+            (
+                "neutral".to_string(),
+                Some("no opinion | given".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_answers_are_returned_with_their_reasons() {
+        let (host, requests) = spawn_mock_server(|request| {
+            assert!(
+                request.contains("followed by a one-line reason"),
+                "{request}"
+            );
+            let body = r#"{"message":{"content":"1 -> Positive | loved it\n2 -> negative"},"done_reason":"stop"}"#;
+            (200, body.to_string())
+        });
+        let config = AiConfig::default().with_ollama_host(host);
+        let udf = AskLlmExplain::with_config(&config);
+        let result = invoke(&udf, "Categorize", vec![strings(&["Great!", "Awful."])]).unwrap();
+        let result = result.as_struct();
+        let answers = result.column_by_name("answer").unwrap().as_string::<i32>();
+        let reasons = result.column_by_name("reason").unwrap().as_string::<i32>();
+        assert_eq!(answers.value(0), "Positive");
+        assert_eq!(answers.value(1), "negative");
+        assert_eq!(reasons.value(0), "loved it");
+        assert!(reasons.is_null(1));
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
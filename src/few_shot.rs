@@ -0,0 +1,225 @@
+use datafusion::arrow::array::AsArray;
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::DataType;
+use datafusion::execution::session_state::SessionState;
+use datafusion::prelude::SessionContext;
+use datafusion_common::{DataFusionError, Result};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::runtime::RuntimeConfig;
+
+/// Few-shot examples for `ask_llm`, read from a table of `(input, label)` rows, see
+/// [`crate::llm_udf::AskLLM::with_few_shot_table`]. Examples live in data, so they can
+/// change without touching code or queries.
+///
+/// The table is read by the first invocation that needs the examples, which are then
+/// reused; call [`FewShotTable::load`] to read it again after it changed. At most
+/// `limit` (5 by default) examples are put in the prompt. Labels take turns so that
+/// every label gets an example before any label gets a second one, with labels in
+/// lexicographic order. Within a label the rows with the lexicographically smallest
+/// input are picked first, so the selection does not depend on the table's row order.
+/// Rows with a NULL input or label are ignored; both columns are cast to Utf8.
+///
+/// Only a weak handle to the session is kept, so the function can be registered on the
+/// session it reads from; once that session is dropped, reading the table fails.
+#[derive(Clone)]
+pub struct FewShotTable {
+    // the state of the session the table is registered in, None once it was dropped
+    session: Arc<dyn Fn() -> Option<SessionState> + Send + Sync>,
+    table: String,
+    limit: usize,
+    // the examples of the last read, shared by the clones
+    examples: Arc<Mutex<Option<Vec<(String, String)>>>>,
+}
+
+impl std::fmt::Debug for FewShotTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FewShotTable")
+            .field("table", &self.table)
+            .field("limit", &self.limit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FewShotTable {
+    pub fn new(ctx: &SessionContext, table: impl Into<String>) -> Self {
+        let session = ctx.state_weak_ref();
+        Self {
+            session: Arc::new(move || session.upgrade().map(|state| state.read().clone())),
+            table: table.into(),
+            limit: 5,
+            examples: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// The selected examples, read from the table by the first call.
+    pub fn examples(&self) -> Result<Vec<(String, String)>> {
+        if let Some(examples) = self.examples.lock().unwrap().as_ref() {
+            return Ok(examples.clone());
+        }
+        self.load()
+    }
+
+    /// Reads the table and selects the examples, which later calls of
+    /// [`FewShotTable::examples`] return.
+    pub fn load(&self) -> Result<Vec<(String, String)>> {
+        let Some(state) = (self.session)() else {
+            return Err(DataFusionError::Execution(format!(
+                "cannot read few-shot table {}: its session has been dropped",
+                self.table
+            )));
+        };
+        let ctx = SessionContext::new_with_state(state);
+        // on a thread of its own, since the caller may already be inside a runtime
+        let batches = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let rt = RuntimeConfig::current_thread().build()?;
+                    rt.block_on(async {
+                        ctx.table(self.table.as_str())
+                            .await?
+                            .select_columns(&["input", "label"])?
+                            .collect()
+                            .await
+                    })
+                })
+                .join()
+                .map_err(|_| {
+                    DataFusionError::Execution("reading few-shot examples panicked".to_string())
+                })?
+        })?;
+        let mut rows = Vec::new();
+        for batch in batches {
+            let inputs = cast(batch.column(0), &DataType::Utf8)?;
+            let labels = cast(batch.column(1), &DataType::Utf8)?;
+            for (input, label) in inputs
+                .as_string::<i32>()
+                .iter()
+                .zip(labels.as_string::<i32>())
+            {
+                if let (Some(input), Some(label)) = (input, label) {
+                    rows.push((input.to_string(), label.to_string()));
+                }
+            }
+        }
+        let examples = select_examples(rows, self.limit);
+        *self.examples.lock().unwrap() = Some(examples.clone());
+        Ok(examples)
+    }
+}
+
+/// Picks at most `limit` examples, taking labels in turns, see [`FewShotTable`].
+pub fn select_examples(rows: Vec<(String, String)>, limit: usize) -> Vec<(String, String)> {
+    let mut by_label: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (input, label) in rows {
+        by_label.entry(label).or_default().push(input);
+    }
+    for inputs in by_label.values_mut() {
+        inputs.sort();
+        inputs.dedup();
+    }
+    let mut examples = Vec::new();
+    let mut round = 0;
+    while examples.len() < limit {
+        let before = examples.len();
+        for (label, inputs) in &by_label {
+            if examples.len() == limit {
+                break;
+            }
+            if let Some(input) = inputs.get(round) {
+                examples.push((input.clone(), label.clone()));
+            }
+        }
+        if examples.len() == before {
+            break;
+        }
+        round += 1;
+    }
+    examples
+}
+
+/// Appends the examples to `instruction`, one `input -> label` line each.
+pub fn render_examples(instruction: &str, examples: &[(String, String)]) -> String {
+    if examples.is_empty() {
+        return instruction.to_string();
+    }
+    let lines: Vec<String> = examples
+        .iter()
+        .map(|(input, label)| format!("{} -> {}", input, label))
+        .collect();
+    format!(
+        "{}\nExamples of values and their answers:\n{}\n",
+        instruction,
+        lines.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{RecordBatch, StringArray};
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(input, label)| (input.to_string(), label.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_labels_take_turns() {
+        let rows = pairs(&[
+            ("Love it", "pos"),
+            ("Awful", "neg"),
+            ("Great", "pos"),
+            ("Fine", "pos"),
+        ]);
+        assert_eq!(
+            select_examples(rows.clone(), 3),
+            pairs(&[("Awful", "neg"), ("Fine", "pos"), ("Great", "pos")])
+        );
+        assert_eq!(select_examples(rows, 10).len(), 4);
+    }
+
+    #[test]
+    fn test_examples_are_read_from_a_table() {
+        let ctx = SessionContext::new();
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "input",
+                Arc::new(StringArray::from(vec![
+                    Some("Love it"),
+                    Some("Awful"),
+                    None,
+                ])) as Arc<dyn datafusion::arrow::array::Array>,
+            ),
+            (
+                "label",
+                Arc::new(StringArray::from(vec!["pos", "neg", "neg"])) as _,
+            ),
+        ])
+        .unwrap();
+        ctx.register_batch("examples", batch.clone()).unwrap();
+        let few_shot = FewShotTable::new(&ctx, "examples");
+        let examples = few_shot.examples().unwrap();
+        assert_eq!(examples, pairs(&[("Awful", "neg"), ("Love it", "pos")]));
+        // the examples are kept until the table is loaded again
+        ctx.deregister_table("examples").unwrap();
+        ctx.register_batch("examples", batch.slice(0, 1)).unwrap();
+        assert_eq!(few_shot.examples().unwrap(), examples);
+        assert_eq!(few_shot.load().unwrap(), pairs(&[("Love it", "pos")]));
+        assert_eq!(
+            render_examples("Classify", &examples),
+            "Classify\nExamples of values and their answers:\nAwful -> neg\nLove it -> pos\n"
+        );
+        // the table does not keep its session alive
+        drop(ctx);
+        assert!(few_shot.load().unwrap_err().to_string().contains("dropped"));
+    }
+}
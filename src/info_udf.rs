@@ -0,0 +1,113 @@
+use datafusion::arrow::datatypes::DataType;
+use datafusion_common::{Result, ScalarValue};
+use datafusion_doc::Documentation;
+use datafusion_expr::ScalarUDFImpl;
+use datafusion_expr::{ColumnarValue, ScalarFunctionArgs, Signature, Volatility};
+use datafusion_macros::user_doc;
+use serde_json::json;
+use std::any::Any;
+
+use crate::config::AiConfig;
+
+#[user_doc(
+    doc_section(label = "AI functions"),
+    description = "Describe the AI backend configuration as a JSON string",
+    syntax_example = "ask_llm_info()"
+)]
+#[derive(Debug)]
+pub struct AskLlmInfo {
+    name: String,
+    signature: Signature,
+    info: String,
+}
+
+impl AskLlmInfo {
+    pub fn new() -> Self {
+        Self::with_config(&AiConfig::default())
+    }
+
+    /// The JSON is rendered once here, so calls are cheap and have no side effects.
+    pub fn with_config(config: &AiConfig) -> Self {
+        let info = json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "backend": "ollama",
+            "url": config.ollama_host,
+            "chat_model": config.chat_model,
+            "embed_model": config.embed_model,
+            "items_per_prompt": config.items_per_prompt,
+            "max_concurrent_prompts": config.max_concurrent_prompts,
+            "max_chunk_retries": config.max_chunk_retries,
+            "retry_budget": config.retry_budget,
+        });
+        Self {
+            name: "ask_llm_info".to_string(),
+            signature: Signature::nullary(Volatility::Immutable),
+            info: info.to_string(),
+        }
+    }
+
+    /// Registers the function under `name` instead of its default name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+}
+
+impl Default for AskLlmInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScalarUDFImpl for AskLlmInfo {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+    fn return_type(&self, _args: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Utf8)
+    }
+
+    fn invoke_with_args(&self, _args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        Ok(ColumnarValue::Scalar(ScalarValue::Utf8(Some(
+            self.info.clone(),
+        ))))
+    }
+
+    fn documentation(&self) -> Option<&Documentation> {
+        self.doc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::prelude::SessionContext;
+    use datafusion_expr::ScalarUDF;
+
+    #[tokio::test]
+    async fn test_info_from_sql() {
+        let ctx = SessionContext::new();
+        let config = AiConfig::default().with_chat_model("qwen2.5:7b");
+        ctx.register_udf(ScalarUDF::from(AskLlmInfo::with_config(&config)));
+        let batches = ctx
+            .sql("SELECT ask_llm_info() AS info")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let info = datafusion_common::cast::as_string_array(batches[0].column(0))
+            .unwrap()
+            .value(0)
+            .to_string();
+        let info: serde_json::Value = serde_json::from_str(&info).unwrap();
+        assert_eq!(info["chat_model"], "qwen2.5:7b");
+        assert_eq!(info["items_per_prompt"], 5);
+    }
+}
@@ -0,0 +1,117 @@
+use datafusion::arrow::array::{Array, ArrayRef, AsArray, StringArray};
+use datafusion::arrow::datatypes::{DataType, Float32Type, Float64Type};
+use datafusion::arrow::util::display::{ArrayFormatter, FormatOptions};
+use datafusion_common::Result;
+use std::sync::Arc;
+
+/// How `ask_llm` turns a non-string column into the values it puts in the prompt, so
+/// numeric, temporal and boolean columns can be passed without a SQL cast.
+///
+/// The default is Arrow's display formatting, the same text a cast to Utf8 produces.
+/// Date and time formats use chrono's `strftime` syntax, e.g. `%d %B %Y`. NULLs stay
+/// NULL.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputFormat {
+    pub date_format: Option<String>,
+    /// Used for timestamps and `Date64` values.
+    pub datetime_format: Option<String>,
+    pub time_format: Option<String>,
+    /// Digits after the decimal point of floating point values.
+    pub float_precision: Option<usize>,
+}
+
+impl InputFormat {
+    pub fn with_date_format(mut self, date_format: impl Into<String>) -> Self {
+        self.date_format = Some(date_format.into());
+        self
+    }
+
+    pub fn with_datetime_format(mut self, datetime_format: impl Into<String>) -> Self {
+        self.datetime_format = Some(datetime_format.into());
+        self
+    }
+
+    pub fn with_time_format(mut self, time_format: impl Into<String>) -> Self {
+        self.time_format = Some(time_format.into());
+        self
+    }
+
+    pub fn with_float_precision(mut self, float_precision: usize) -> Self {
+        self.float_precision = Some(float_precision);
+        self
+    }
+
+    /// Whether columns of `data_type` are formatted rather than cast to Utf8 by the
+    /// planner.
+    pub fn formats(data_type: &DataType) -> bool {
+        data_type.is_numeric() || data_type.is_temporal() || data_type == &DataType::Boolean
+    }
+
+    pub fn format(&self, array: &ArrayRef) -> Result<ArrayRef> {
+        let values: Vec<Option<String>> = match (self.float_precision, array.data_type()) {
+            (Some(precision), DataType::Float32) => array
+                .as_primitive::<Float32Type>()
+                .iter()
+                .map(|v| v.map(|v| format!("{:.*}", precision, v)))
+                .collect(),
+            (Some(precision), DataType::Float64) => array
+                .as_primitive::<Float64Type>()
+                .iter()
+                .map(|v| v.map(|v| format!("{:.*}", precision, v)))
+                .collect(),
+            _ => {
+                let options = FormatOptions::new()
+                    .with_date_format(self.date_format.as_deref())
+                    .with_datetime_format(self.datetime_format.as_deref())
+                    .with_timestamp_format(self.datetime_format.as_deref())
+                    .with_time_format(self.time_format.as_deref());
+                let formatter = ArrayFormatter::try_new(array.as_ref(), &options)?;
+                (0..array.len())
+                    .map(|i| (!array.is_null(i)).then(|| formatter.value(i).to_string()))
+                    .collect()
+            }
+        };
+        Ok(Arc::new(StringArray::from(values)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Date32Array, Float64Array, Int64Array};
+
+    fn strings(array: ArrayRef) -> Vec<Option<String>> {
+        array
+            .as_string::<i32>()
+            .iter()
+            .map(|v| v.map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn test_formats_follow_the_options() {
+        let ints: ArrayRef = Arc::new(Int64Array::from(vec![Some(42), None]));
+        assert_eq!(
+            strings(InputFormat::default().format(&ints).unwrap()),
+            [Some("42".to_string()), None]
+        );
+        let floats: ArrayRef = Arc::new(Float64Array::from(vec![1.0 / 3.0]));
+        let format = InputFormat::default().with_float_precision(2);
+        assert_eq!(
+            strings(format.format(&floats).unwrap()),
+            [Some("0.33".to_string())]
+        );
+        // 2024-03-01
+        let dates: ArrayRef = Arc::new(Date32Array::from(vec![19783]));
+        assert_eq!(
+            strings(InputFormat::default().format(&dates).unwrap()),
+            [Some("2024-03-01".to_string())]
+        );
+        let format = InputFormat::default().with_date_format("%d %B %Y");
+        assert_eq!(
+            strings(format.format(&dates).unwrap()),
+            [Some("01 March 2024".to_string())]
+        );
+        assert!(!InputFormat::formats(&DataType::Utf8));
+    }
+}
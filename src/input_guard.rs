@@ -0,0 +1,89 @@
+/// Tokens (runs of non-whitespace) at least this long count as non-textual, which
+/// catches base64 and hex blobs that consist of printable characters only.
+const LONG_TOKEN_CHARS: usize = 40;
+
+/// What [`InputGuard`] does with inputs that look non-textual.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GuardAction {
+    /// Logs how many inputs look non-textual and sends them anyway.
+    #[default]
+    Warn,
+    /// Logs them and answers them with NULL without asking the model.
+    Skip,
+}
+
+/// A cheap check for inputs that are probably not text, e.g. a base64 or binary
+/// column selected by mistake, which would only burn tokens on nonsense.
+///
+/// A value scores the share of its characters that are non-printable (control
+/// characters other than whitespace, or U+FFFD from invalid UTF-8) or part of a
+/// token of 40 or more characters; values scoring at least `threshold` are flagged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputGuard {
+    pub threshold: f64,
+    pub action: GuardAction,
+}
+
+impl Default for InputGuard {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            action: GuardAction::default(),
+        }
+    }
+}
+
+impl InputGuard {
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_action(mut self, action: GuardAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    pub fn looks_non_textual(&self, value: &str) -> bool {
+        !value.is_empty() && non_text_score(value) >= self.threshold
+    }
+}
+
+/// The share of characters in `value` that are non-printable or in very long tokens.
+pub fn non_text_score(value: &str) -> f64 {
+    let total = value.chars().count();
+    if total == 0 {
+        return 0.0;
+    }
+    let non_printable = value
+        .chars()
+        .filter(|&c| (c.is_control() && !c.is_whitespace()) || c == char::REPLACEMENT_CHARACTER)
+        .count();
+    let in_long_tokens: usize = value
+        .split_whitespace()
+        .map(|token| token.chars().count())
+        .filter(|&len| len >= LONG_TOKEN_CHARS)
+        .sum();
+    (non_printable + in_long_tokens).min(total) as f64 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_non_textual_inputs_are_flagged() {
+        let guard = InputGuard::default();
+        assert!(!guard.looks_non_textual("The delivery was late and the box was damaged."));
+        assert!(guard.looks_non_textual(
+            "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg=="
+        ));
+        assert!(guard.looks_non_textual("\u{0}\u{1}\u{2}PK\u{3}\u{4}"));
+        assert!(!guard.looks_non_textual(""));
+        assert!(
+            !InputGuard::default()
+                .with_threshold(0.9)
+                .looks_non_textual("see https://example.com/a/very/long/link/that/goes/on/and/on")
+        );
+    }
+}
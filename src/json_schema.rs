@@ -0,0 +1,92 @@
+use serde_json::Value;
+
+/// Validates `value` against a JSON schema and returns the first violation found.
+///
+/// Supports the subset of JSON schema that Ollama's structured outputs use: `type`
+/// (including a list of types), `properties`, `required`, `items` and `enum`.
+/// Other keywords are ignored rather than rejected.
+pub fn validate(value: &Value, schema: &Value) -> Result<(), String> {
+    validate_at(value, schema, "$")
+}
+
+fn validate_at(value: &Value, schema: &Value, path: &str) -> Result<(), String> {
+    if let Some(expected) = schema.get("type") {
+        let matches = match expected {
+            Value::String(t) => type_matches(value, t),
+            Value::Array(types) => types
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|t| type_matches(value, t)),
+            _ => true,
+        };
+        if !matches {
+            return Err(format!("{path}: expected type {expected}, got {value}"));
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array)
+        && !allowed.contains(value)
+    {
+        return Err(format!("{path}: {value} is not one of {allowed:?}"));
+    }
+
+    if let Value::Object(object) = value {
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(key) {
+                    return Err(format!("{path}: missing required property '{key}'"));
+                }
+            }
+        }
+        if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+            for (key, property_schema) in properties {
+                if let Some(property) = object.get(key) {
+                    validate_at(property, property_schema, &format!("{path}.{key}"))?;
+                }
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            validate_at(item, item_schema, &format!("{path}[{i}]"))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn type_matches(value: &Value, expected: &str) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "sentiment": { "type": "string", "enum": ["positive", "negative"] },
+                "score": { "type": "integer" }
+            },
+            "required": ["sentiment"]
+        });
+        assert!(validate(&json!({"sentiment": "positive", "score": 3}), &schema).is_ok());
+        assert!(validate(&json!({"score": 3}), &schema).is_err());
+        assert!(validate(&json!({"sentiment": "meh"}), &schema).is_err());
+        assert!(validate(&json!({"sentiment": "negative", "score": 1.5}), &schema).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use datafusion::arrow::datatypes::DataType;
+use datafusion_common::Result;
+use datafusion_doc::Documentation;
+use datafusion_expr::ScalarUDFImpl;
+use datafusion_expr::{
+    ColumnarValue, ReturnInfo, ReturnTypeArgs, ScalarFunctionArgs, Signature, Volatility,
+};
+use datafusion_macros::user_doc;
+use serde_json::{Value, json};
+use std::any::Any;
+
+use crate::config::AiConfig;
+use crate::json_schema;
+use crate::llm_udf::{AskLLM, ResponseParser, parse_llm_response};
+
+/// The default system prompt of `ask_llm_json`, which asks for schema-conforming JSON only.
+pub const ASK_LLM_JSON_SYSTEM_PROMPT: &str = "\
+    You extract structured data from a numbered list of items according to the user's \
+    instruction. Respond with JSON only, with one result per item, in order, matching \
+    the requested schema.";
+
+/// Structured output: every row is answered with a JSON value that must match `schema`.
+///
+/// The rows of a chunk are answered in one request whose Ollama `format` is an object
+/// with a `results` array of `schema` items. Each item is validated against `schema`
+/// before it is emitted; items that fail validation are returned as NULL.
+///
+/// If the server rejects the `format` (older Ollama versions) or ignores it and answers
+/// in plain text, the plain `n -> answer` line format is parsed instead, with each
+/// answer read as JSON when possible and validated the same way.
+///
+/// Everything else is [`AskLLM`]'s: chunking, retries of retryable errors within the
+/// retry budget, the fallback model, rate limiting, cancellation and NULL inputs, which
+/// are answered NULL without asking the model.
+#[user_doc(
+    doc_section(label = "AI functions"),
+    description = "Ask LLM for a JSON value per row that matches the schema given to the UDF",
+    syntax_example = "ask_llm_json('instruction', 'column_value')"
+)]
+#[derive(Debug)]
+pub struct AskLlmJson {
+    ask_llm: AskLLM,
+}
+
+// reads the `results` array of a structured response, or the line format of a server
+// that ignored the format, and drops the items that fail validation
+#[derive(Debug)]
+struct JsonResults {
+    schema: Value,
+}
+
+impl ResponseParser for JsonResults {
+    fn parse(&self, raw: &str, expected: usize) -> Vec<Option<String>> {
+        let Ok(response) = serde_json::from_str::<Value>(raw) else {
+            // older servers silently ignore `format` and answer in the line format
+            log::warn!("response is not JSON, parsing it as plain text output");
+            return parse_llm_response(raw, expected)
+                .into_iter()
+                .map(|answer| {
+                    let value = serde_json::from_str(&answer).unwrap_or(Value::String(answer));
+                    self.validated(&value)
+                })
+                .collect();
+        };
+        match response["results"].as_array() {
+            Some(results) => results
+                .iter()
+                .map(|result| self.validated(result))
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+impl JsonResults {
+    fn validated(&self, result: &Value) -> Option<String> {
+        match json_schema::validate(result, &self.schema) {
+            Ok(()) => Some(result.to_string()),
+            Err(violation) => {
+                log::warn!(
+                    "dropping result that fails schema validation: {}",
+                    violation
+                );
+                None
+            }
+        }
+    }
+
+    fn chunk_format(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "results": { "type": "array", "items": self.schema }
+            },
+            "required": ["results"]
+        })
+    }
+}
+
+impl AskLlmJson {
+    pub fn new(schema: Value) -> Self {
+        Self::with_config(&AiConfig::default(), schema)
+    }
+
+    /// Creates the UDF using the chat model from `config`, with
+    /// [`ASK_LLM_JSON_SYSTEM_PROMPT`].
+    pub fn with_config(config: &AiConfig, schema: Value) -> Self {
+        Self::with_ask_llm(
+            AskLLM::with_config(config).with_system_prompt(ASK_LLM_JSON_SYSTEM_PROMPT),
+            schema,
+        )
+    }
+
+    /// Asks `ask_llm` for the values, with all its settings (prompt, chunking, retries,
+    /// caches, ...); its response parser is replaced by one that reads and validates
+    /// the JSON results.
+    pub fn with_ask_llm(ask_llm: AskLLM, schema: Value) -> Self {
+        let parser = JsonResults { schema };
+        let ask_llm = ask_llm
+            .with_name("ask_llm_json")
+            .with_response_format(parser.chunk_format())
+            .with_response_parser(parser);
+        Self { ask_llm }
+    }
+
+    /// Registers the function under `name` instead of its default name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.ask_llm = self.ask_llm.with_name(name);
+        self
+    }
+
+    /// Sets how the function advertises itself to the planner, [`Volatility::Volatile`]
+    /// by default, see [`AskLLM::with_volatility`].
+    pub fn with_volatility(mut self, volatility: Volatility) -> Self {
+        self.ask_llm = self.ask_llm.with_volatility(volatility);
+        self
+    }
+
+    /// Replaces the system prompt, [`ASK_LLM_JSON_SYSTEM_PROMPT`] by default, see
+    /// [`AskLLM::with_system_prompt`] for how it relates to the model's own system
+    /// prompt.
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.ask_llm = self.ask_llm.with_system_prompt(system_prompt);
+        self
+    }
+}
+
+impl ScalarUDFImpl for AskLlmJson {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn name(&self) -> &str {
+        self.ask_llm.name()
+    }
+    fn signature(&self) -> &Signature {
+        self.ask_llm.signature()
+    }
+    fn return_type(&self, args: &[DataType]) -> Result<DataType> {
+        self.ask_llm.return_type(args)
+    }
+    fn coerce_types(&self, arg_types: &[DataType]) -> Result<Vec<DataType>> {
+        self.ask_llm.coerce_types(arg_types)
+    }
+    fn return_type_from_args(&self, args: ReturnTypeArgs) -> Result<ReturnInfo> {
+        self.ask_llm.return_type_from_args(args)
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        self.ask_llm.invoke_with_args(args)
+    }
+
+    fn documentation(&self) -> Option<&Documentation> {
+        self.doc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_udf::tests::{invoke, spawn_mock_server, strings};
+    use datafusion::arrow::array::{Array, ArrayRef, StringArray};
+    use datafusion_common::cast::as_string_array;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_plain_text_fallback_is_validated() {
+        let parser = JsonResults {
+            schema: json!({ "type": "string", "enum": ["positive", "negative"] }),
+        };
+        let parsed = parser.parse("1 -> positive\n2 -> \"negative\"\n3 -> meh", 3);
+        assert_eq!(
+            parsed,
+            vec![
+                Some("\"positive\"".to_string()),
+                Some("\"negative\"".to_string()),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejected_requests_are_not_retried() {
+        let not_found = r#"{"error":"model 'nope' not found"}"#;
+        let (host, requests) = spawn_mock_server(move |_| (404, not_found.to_string()));
+        let config = AiConfig::default()
+            .with_ollama_host(host)
+            .with_max_chunk_retries(3);
+        let udf = AskLlmJson::with_config(&config, json!({ "type": "string" }));
+        invoke(&udf, "Extract the product", vec![strings(&["a mug"])]).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_null_rows_are_not_sent() {
+        let content = json!({ "results": [{ "product": "mug" }] }).to_string();
+        let body = json!({ "message": { "content": content }, "done": true }).to_string();
+        let (host, requests) = spawn_mock_server(move |_| (200, body.clone()));
+        let config = AiConfig::default().with_ollama_host(host);
+        let schema = json!({ "type": "object", "required": ["product"] });
+        let udf = AskLlmJson::with_config(&config, schema);
+        let values: ArrayRef = Arc::new(StringArray::from(vec![None, Some("a mug")]));
+        let result = invoke(&udf, "Extract the product", vec![values]).unwrap();
+        let result = as_string_array(&result).unwrap();
+        assert!(result.is_null(0));
+        assert_eq!(result.value(1), r#"{"product":"mug"}"#);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use crate::llm_utils::{LlamaApp, TokenCounter};
+
+// the "n. " numbering and line break around every row of a rendered prompt, as
+// counted by the character estimator
+const ROW_OVERHEAD_CHARS: usize = 6;
+
+/// Estimates the length of a piece of prompt text, used to size chunks against a
+/// prompt limit (see [`crate::llm_udf::AskLLM::with_max_prompt_tokens`]).
+///
+/// [`LengthEstimator::chars`] counts characters, a crude proxy that needs no model.
+/// For token counts, plug in a tokenizer with [`LengthEstimator::new`] (e.g. from the
+/// `tokenizers` crate) or use the loaded llama.cpp model with [`LengthEstimator::llama`].
+#[derive(Clone)]
+pub struct LengthEstimator {
+    name: &'static str,
+    estimate: Arc<dyn Fn(&str) -> usize + Send + Sync>,
+    // a fixed length for a row's numbering instead of estimating it with the row
+    row_overhead: Option<usize>,
+}
+
+impl std::fmt::Debug for LengthEstimator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LengthEstimator")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for LengthEstimator {
+    fn default() -> Self {
+        Self::chars()
+    }
+}
+
+impl LengthEstimator {
+    /// Uses `estimate`, typically a tokenizer's token count, as the length of a text.
+    pub fn new(estimate: impl Fn(&str) -> usize + Send + Sync + 'static) -> Self {
+        Self {
+            name: "custom",
+            estimate: Arc::new(estimate),
+            row_overhead: None,
+        }
+    }
+
+    /// Counts characters.
+    pub fn chars() -> Self {
+        Self {
+            name: "chars",
+            estimate: Arc::new(|text| text.chars().count()),
+            row_overhead: Some(ROW_OVERHEAD_CHARS),
+        }
+    }
+
+    /// Approximates tokens as one per four characters, a common rule of thumb for
+    /// English text with BPE tokenizers.
+    pub fn approx_tokens() -> Self {
+        Self {
+            name: "approx_tokens",
+            estimate: Arc::new(|text| text.chars().count().div_ceil(4)),
+            row_overhead: None,
+        }
+    }
+
+    /// Counts tokens with the tokenizer of the llama.cpp model that is loaded when the
+    /// estimator is created (see [`LlamaApp::token_counter`]), and characters if no
+    /// model was loaded then or it has been unloaded since.
+    pub fn llama() -> Self {
+        Self::tokens(LlamaApp::token_counter())
+    }
+
+    fn tokens(counter: TokenCounter) -> Self {
+        Self {
+            name: "llama",
+            estimate: Arc::new(move |text| {
+                counter.count(text).unwrap_or_else(|| text.chars().count())
+            }),
+            row_overhead: None,
+        }
+    }
+
+    pub fn estimate(&self, text: &str) -> usize {
+        (self.estimate)(text)
+    }
+
+    /// Estimates `value` as the row `number` of a prompt, with its numbering and line
+    /// break.
+    pub fn estimate_row(&self, number: usize, value: &str) -> usize {
+        match self.row_overhead {
+            Some(row_overhead) => self.estimate(value) + row_overhead,
+            None => self.estimate(&format!("\n{}. {}", number, value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimators() {
+        assert_eq!(LengthEstimator::chars().estimate("héllo"), 5);
+        assert_eq!(LengthEstimator::chars().estimate_row(1, "héllo"), 11);
+        assert_eq!(LengthEstimator::approx_tokens().estimate("héllo"), 2);
+        let words = LengthEstimator::new(|text| text.split_whitespace().count());
+        assert_eq!(words.estimate("one two  three"), 3);
+        assert_eq!(words.estimate_row(2, "one two"), 3);
+        // without a model, the llama estimator counts characters
+        let unloaded = LengthEstimator::tokens(TokenCounter::default());
+        assert_eq!(unloaded.estimate("héllo"), 5);
+    }
+}
@@ -0,0 +1,158 @@
+pub mod audit;
+pub mod autotune;
+pub mod balancer;
+pub mod cache;
+pub mod cache_file;
+pub mod cancellation;
+pub mod categorical;
+pub mod chat_template;
+pub mod coalesce;
+pub mod columns_udf;
+pub mod config;
+pub mod consistency;
+pub mod context_udf;
+pub mod cost;
+pub mod date_udf;
+pub mod dry_run;
+pub mod embed_udf;
+pub mod explain_udf;
+pub mod few_shot;
+pub mod info_udf;
+pub mod input_format;
+pub mod input_guard;
+pub mod json_schema;
+pub mod json_udf;
+pub mod length_estimator;
+pub mod list_udf;
+pub mod llama_udf;
+pub mod llm_udf;
+pub mod llm_utils;
+pub mod model_profile;
+pub mod normalize;
+pub mod ollama_utils;
+pub(crate) mod ordering;
+pub mod output_builder;
+pub mod progress;
+pub mod rate_limit;
+pub mod raw_udf;
+pub mod registry;
+pub mod retry;
+pub mod routing;
+pub mod row_error;
+pub mod runtime;
+pub mod shared_backend;
+pub mod streaming;
+pub mod telemetry;
+pub mod warmup;
+pub mod windowing;
+
+pub use registry::{
+    AiUdf, RegisterOptions, register_ai_udfs, register_ai_udfs_async, register_ai_udfs_with,
+};
+
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::Result;
+use datafusion::prelude::{DataFrame, SessionContext, ident, lit};
+use datafusion_common::plan_err;
+use futures_util::StreamExt;
+
+/// Runs `query` and hands each result batch to `sink` as soon as it is produced,
+/// instead of collecting the whole result first. LLM columns are computed batch by
+/// batch, so the first rows reach the sink quickly and memory stays bounded by the
+/// batches in flight. Returns the total number of rows passed to the sink.
+pub async fn run_and_stream<F>(ctx: &SessionContext, query: &str, sink: F) -> Result<usize>
+where
+    F: FnMut(RecordBatch) -> Result<()>,
+{
+    stream_dataframe(ctx.sql(query).await?, sink).await
+}
+
+/// Like [`run_and_stream`] for an already built [`DataFrame`].
+pub async fn stream_dataframe<F>(df: DataFrame, mut sink: F) -> Result<usize>
+where
+    F: FnMut(RecordBatch) -> Result<()>,
+{
+    let mut stream = df.execute_stream().await?;
+    let mut rows = 0;
+    while let Some(batch) = stream.next().await {
+        let batch = batch?;
+        rows += batch.num_rows();
+        sink(batch)?;
+    }
+    Ok(rows)
+}
+
+/// Adds `output_col` to `df`, answering `instruction` for every value of `input_col`,
+/// like `SELECT *, ask_llm(instruction, input_col) AS output_col` without SQL. Uses the
+/// `ask_llm` registered in `df`'s session (see [`register_ai_udfs`]), so the backend
+/// and settings it was registered with apply.
+pub fn apply_llm(
+    df: DataFrame,
+    input_col: &str,
+    instruction: &str,
+    output_col: &str,
+) -> Result<DataFrame> {
+    let name = AiUdf::AskLlm.default_name();
+    let Some(ask_llm) = df.task_ctx().scalar_functions().get(name).cloned() else {
+        return plan_err!(
+            "{} is not registered, register it with register_ai_udfs",
+            name
+        );
+    };
+    df.with_column(
+        output_col,
+        ask_llm.call(vec![lit(instruction), ident(input_col)]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dry_run::DryRun;
+
+    #[tokio::test]
+    async fn test_run_and_stream_passes_every_batch_to_the_sink() {
+        let ctx = SessionContext::new();
+        let mut batches = 0;
+        let rows = run_and_stream(
+            &ctx,
+            "SELECT * FROM (VALUES (1), (2), (3)) AS t(x)",
+            |batch| {
+                assert_eq!(batch.num_columns(), 1);
+                batches += 1;
+                Ok(())
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(rows, 3);
+        assert!(batches >= 1);
+    }
+
+    #[tokio::test]
+    async fn test_apply_llm_adds_the_answer_column() {
+        let ctx = SessionContext::new();
+        let df = ctx
+            .sql("SELECT * FROM (VALUES ('Great!'), ('Awful.')) AS t(\"Review\")")
+            .await
+            .unwrap();
+        assert!(apply_llm(df.clone(), "Review", "Categorize", "sentiment").is_err());
+
+        let dry_run = DryRun::new();
+        let options = RegisterOptions::default().with_dry_run(dry_run.clone());
+        register_ai_udfs_with(&ctx, &config::AiConfig::default(), &options).unwrap();
+        let df = ctx
+            .sql("SELECT * FROM (VALUES ('Great!'), ('Awful.')) AS t(\"Review\")")
+            .await
+            .unwrap();
+        let batches = apply_llm(df, "Review", "Categorize", "sentiment")
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let schema = batches[0].schema();
+        assert_eq!(schema.field(0).name(), "Review");
+        assert_eq!(schema.field(1).name(), "sentiment");
+        assert_eq!(dry_run.total().rows, 2);
+    }
+}
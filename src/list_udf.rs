@@ -0,0 +1,309 @@
+use datafusion::arrow::array::{ListBuilder, StringBuilder};
+use datafusion::arrow::datatypes::{DataType, Field};
+use datafusion_common::cast::as_string_array;
+use datafusion_common::{DataFusionError, Result, ScalarValue, plan_err};
+use datafusion_doc::Documentation;
+use datafusion_expr::ScalarUDFImpl;
+use datafusion_expr::{ColumnarValue, ScalarFunctionArgs, Signature, Volatility};
+use datafusion_macros::user_doc;
+use rayon::prelude::*;
+use serde_json::{Value, json};
+use std::any::Any;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::config::AiConfig;
+use crate::llm_udf::parse_llm_response;
+use crate::ollama_utils::{OllamaApp, is_unsupported_format};
+use crate::telemetry;
+
+// used when the server rejects the structured format and no delimiter was configured
+const FALLBACK_DELIMITER: &str = ",";
+
+/// The default system prompt of `ask_llm_list`, which asks for the matches of every item as
+/// separate strings.
+pub const ASK_LLM_LIST_SYSTEM_PROMPT: &str = "\
+    You extract lists from a numbered list of items according to the user's instruction. \
+    For every item, in order, return each match as a separate short string, and nothing \
+    when there is no match.";
+
+fn create_tokio_runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime")
+}
+
+/// Multi-answer output: every row is answered with a list of strings.
+///
+/// By default the model is constrained (through Ollama's `format`) to a JSON object
+/// whose `results` field is an array with one array of strings per row, so row
+/// boundaries and item boundaries can never be confused. With
+/// [`AskLlmList::with_delimiter`], the plain `n -> answer` line format is used instead
+/// and each answer is split on the delimiter. The line format, split on `,`, is also
+/// the fallback when the server rejects the structured format.
+#[user_doc(
+    doc_section(label = "AI functions"),
+    description = "Ask LLM for a list of answers per row",
+    syntax_example = "ask_llm_list('instruction', 'column_value')"
+)]
+#[derive(Debug)]
+pub struct AskLlmList {
+    name: String,
+    signature: Signature,
+    ollama_model: Arc<str>,
+    ollama_url: String,
+    system_prompt: String,
+    items_per_prompt: usize,
+    delimiter: Option<String>,
+}
+
+impl AskLlmList {
+    pub fn new() -> Self {
+        Self::with_config(&AiConfig::default())
+    }
+
+    /// Creates the UDF using the chat model from `config`.
+    pub fn with_config(config: &AiConfig) -> Self {
+        Self {
+            name: "ask_llm_list".to_string(),
+            signature: Signature::exact(vec![DataType::Utf8, DataType::Utf8], Volatility::Volatile),
+            ollama_model: config.chat_model.as_str().into(),
+            ollama_url: config.chat_url(),
+            system_prompt: ASK_LLM_LIST_SYSTEM_PROMPT.to_string(),
+            items_per_prompt: config.items_per_prompt.max(1),
+            delimiter: None,
+        }
+    }
+
+    /// Registers the function under `name` instead of its default name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets how the function advertises itself to the planner, [`Volatility::Volatile`]
+    /// by default as the lists come from sampling, see
+    /// [`crate::llm_udf::AskLLM::with_volatility`] for when `Immutable` is safe.
+    pub fn with_volatility(mut self, volatility: Volatility) -> Self {
+        self.signature = Signature::exact(vec![DataType::Utf8, DataType::Utf8], volatility);
+        self
+    }
+
+    /// Replaces the system prompt, [`ASK_LLM_LIST_SYSTEM_PROMPT`] by default, see
+    /// [`crate::llm_udf::AskLLM::with_system_prompt`] for how it relates to the model's
+    /// own system prompt.
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.system_prompt = system_prompt.into();
+        self
+    }
+
+    /// Uses the line format and splits each row's answer on `delimiter`.
+    pub fn with_delimiter(mut self, delimiter: impl Into<String>) -> Self {
+        self.delimiter = Some(delimiter.into());
+        self
+    }
+
+    fn chunk_format() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "results": {
+                    "type": "array",
+                    "items": { "type": "array", "items": { "type": "string" } }
+                }
+            },
+            "required": ["results"]
+        })
+    }
+
+    async fn process_chunk(&self, instruction: &str, vals: &[String]) -> Result<Vec<Vec<String>>> {
+        if vals.is_empty() {
+            return Ok(vec![]);
+        }
+        let ollama_app = OllamaApp::new(&self.ollama_model, &self.ollama_url)
+            .map_err(|e| DataFusionError::Internal(e.to_string()))?
+            .with_system_prompt(self.system_prompt.as_str());
+
+        let request_start = Instant::now();
+        let llm_response = match &self.delimiter {
+            Some(_) => ollama_app.generate_text(instruction, vals).await,
+            None => {
+                ollama_app
+                    .generate_structured(instruction, vals, &Self::chunk_format())
+                    .await
+            }
+        };
+        telemetry::record_request(
+            &self.ollama_model,
+            llm_response.is_ok(),
+            request_start.elapsed(),
+        );
+        let (llm_response, delimiter) = match (llm_response, &self.delimiter) {
+            (Err(e), None) if is_unsupported_format(&e) => {
+                println!(
+                    "warning: {}, falling back to plain text output split on '{}'",
+                    e, FALLBACK_DELIMITER
+                );
+                let text = ollama_app
+                    .generate_text(instruction, vals)
+                    .await
+                    .map_err(|e| DataFusionError::Internal(e.to_string()))?;
+                (text, Some(FALLBACK_DELIMITER))
+            }
+            (response, delimiter) => (
+                response.map_err(|e| DataFusionError::Internal(e.to_string()))?,
+                delimiter.as_deref(),
+            ),
+        };
+
+        let lists = match delimiter {
+            Some(delimiter) => parse_llm_response(&llm_response, vals.len())
+                .iter()
+                .map(|answer| split_answer(answer, delimiter))
+                .collect(),
+            None => parse_json_lists(&llm_response)?,
+        };
+        if lists.len() != vals.len() {
+            telemetry::record_count_mismatch(&self.ollama_model);
+            return Err(DataFusionError::Internal(format!(
+                "mismatched result count: {} != {}",
+                lists.len(),
+                vals.len()
+            )));
+        }
+        Ok(lists)
+    }
+}
+
+impl Default for AskLlmList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn split_answer(answer: &str, delimiter: &str) -> Vec<String> {
+    answer
+        .split(delimiter)
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn parse_json_lists(response: &str) -> Result<Vec<Vec<String>>> {
+    let response: Value = serde_json::from_str(response)
+        .map_err(|e| DataFusionError::Internal(format!("invalid JSON response: {e}")))?;
+    let Some(results) = response["results"].as_array() else {
+        return Err(DataFusionError::Internal(
+            "JSON response has no results array".to_string(),
+        ));
+    };
+    Ok(results
+        .iter()
+        .map(|row| {
+            row.as_array()
+                .map(|items| {
+                    items
+                        .iter()
+                        .filter_map(|item| item.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+        .collect())
+}
+
+impl ScalarUDFImpl for AskLlmList {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+    fn return_type(&self, args: &[DataType]) -> Result<DataType> {
+        if !matches!(args.first(), Some(&DataType::Utf8)) {
+            return plan_err!("ask_llm_list only accepts Utf8 arguments");
+        }
+        Ok(DataType::List(Arc::new(Field::new_list_field(
+            DataType::Utf8,
+            true,
+        ))))
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        let ScalarFunctionArgs { args, .. } = args;
+        let (instruction, col_values) = match args.as_slice() {
+            [
+                ColumnarValue::Scalar(ScalarValue::Utf8(instruction)),
+                ColumnarValue::Array(col_values),
+            ] => (instruction.as_deref().unwrap_or_default(), col_values),
+            _ => {
+                return plan_err!(
+                    "ask_llm_list only accepts 2 arguments in the form of 'instruction' (string), 'column_value' (column)"
+                );
+            }
+        };
+        let col_values = as_string_array(col_values.as_ref())?;
+        let values: Vec<_> = col_values.iter().collect();
+
+        let result: Vec<Option<Vec<String>>> = values
+            .par_chunks(self.items_per_prompt)
+            .flat_map(|chunk| {
+                let vals: Vec<String> = chunk
+                    .iter()
+                    .map(|opt| opt.unwrap_or_default().to_string())
+                    .collect();
+                let rt = create_tokio_runtime();
+                match rt.block_on(self.process_chunk(instruction, &vals)) {
+                    Ok(lists) => lists.into_iter().map(Some).collect(),
+                    Err(e) => {
+                        println!("Error processing chunk: {}", e);
+                        vec![None; vals.len()]
+                    }
+                }
+            })
+            .collect();
+
+        let mut builder = ListBuilder::new(StringBuilder::new());
+        for list in result {
+            match list {
+                Some(items) => {
+                    for item in items {
+                        builder.values().append_value(item);
+                    }
+                    builder.append(true);
+                }
+                None => builder.append(false),
+            }
+        }
+        Ok(ColumnarValue::Array(Arc::new(builder.finish())))
+    }
+
+    fn documentation(&self) -> Option<&Documentation> {
+        self.doc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volatility_defaults_to_volatile() {
+        let list = AskLlmList::new();
+        assert_eq!(list.signature().volatility, Volatility::Volatile);
+        let list = list.with_volatility(Volatility::Immutable);
+        assert_eq!(list.signature().volatility, Volatility::Immutable);
+    }
+
+    #[test]
+    fn test_parse_lists() {
+        let lists = parse_json_lists(r#"{"results": [["shipping", "price"], []]}"#).unwrap();
+        assert_eq!(lists, vec![vec!["shipping", "price"], vec![]]);
+        assert_eq!(
+            split_answer(" shipping ; price;", ";"),
+            vec!["shipping", "price"]
+        );
+    }
+}
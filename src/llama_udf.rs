@@ -0,0 +1,339 @@
+use datafusion::arrow::array::{ArrayRef, Float64Array, StringArray, StructArray};
+use datafusion::arrow::datatypes::{DataType, Field, Fields};
+use datafusion_common::cast::as_string_array;
+use datafusion_common::{DataFusionError, Result, ScalarValue, plan_err};
+use datafusion_doc::Documentation;
+use datafusion_expr::ScalarUDFImpl;
+use datafusion_expr::{ColumnarValue, ScalarFunctionArgs, Signature, Volatility};
+use datafusion_macros::user_doc;
+use rayon::prelude::*;
+use std::any::Any;
+use std::sync::{Arc, OnceLock};
+
+use crate::cancellation::Cancellation;
+use crate::chat_template::ChatTemplate;
+use crate::config::{AiConfig, Backend};
+use crate::llm_utils::{
+    DEFAULT_SEED, LineStop, LlamaApp, SYSTEM_PROMPT, get_prompt_with_system, line_confidences,
+};
+use crate::retry::{RetryBudget, attempt_seed};
+
+/// Confidence is only available with the local llama.cpp backend, which exposes the
+/// logits of every sampled token. Ollama's chat API does not return logprobs, so
+/// there is no Ollama variant of this UDF.
+#[user_doc(
+    doc_section(label = "AI functions"),
+    description = "Ask a local llama.cpp model and return the answer with its confidence",
+    syntax_example = "ask_llm_confidence('instruction', 'column_value')['confidence']"
+)]
+#[derive(Debug)]
+pub struct AskLlamaWithConfidence {
+    name: String,
+    signature: Signature,
+    llama_app: LlamaApp,
+    items_per_prompt: usize,
+    ctx_size: u32,
+    temperature: f32,
+    max_new_tokens: Option<usize>,
+    chat_template: ChatTemplate,
+    system_prompt: String,
+    cancellation: Cancellation,
+    seed: Option<u32>,
+    max_chunk_retries: usize,
+    retry_budget: Option<usize>,
+    concurrent_prompts: usize,
+    thread_pool: OnceLock<rayon::ThreadPool>,
+}
+
+// generation budget per row of a chunk when no explicit cap is set: room for the
+// "n -> " prefix, a short answer and the line break
+const TOKENS_PER_ROW: usize = 32;
+
+impl AskLlamaWithConfidence {
+    /// Loads the model at `model_path`. The model is process-global, so this fails if
+    /// another model is loaded (see [`LlamaApp::unload`]).
+    /// The chat template is detected from the model metadata, then from the file name
+    /// (see [`ChatTemplate::for_model_name`]), falling back to Llama 3.
+    pub fn new(model_path: &str) -> anyhow::Result<Self> {
+        let llama_app = LlamaApp::new(model_path)?;
+        let chat_template = llama_app
+            .detect_chat_template()
+            .or_else(|| ChatTemplate::for_model_name(model_path))
+            .unwrap_or_default();
+        Ok(Self {
+            name: "ask_llm_confidence".to_string(),
+            signature: Signature::exact(vec![DataType::Utf8, DataType::Utf8], Volatility::Volatile),
+            llama_app,
+            items_per_prompt: 5,
+            ctx_size: 2048,
+            temperature: 0.1,
+            max_new_tokens: None,
+            chat_template,
+            system_prompt: SYSTEM_PROMPT.to_string(),
+            cancellation: Cancellation::new(),
+            seed: None,
+            max_chunk_retries: 0,
+            retry_budget: None,
+            concurrent_prompts: Backend::Llama.default_concurrency(),
+            thread_pool: OnceLock::new(),
+        })
+    }
+
+    /// Loads the model at [`AiConfig::model_path`] and applies the chunk size, retries,
+    /// retry budget, temperature and concurrency of `config`, the latter resolved for
+    /// [`Backend::Llama`]. Fails if `config` has no model path.
+    pub fn with_config(config: &AiConfig) -> anyhow::Result<Self> {
+        let Some(model_path) = &config.model_path else {
+            anyhow::bail!("model_path is required with the llama backend");
+        };
+        let mut udf = Self::new(model_path)?.with_max_chunk_retries(config.max_chunk_retries);
+        udf.items_per_prompt = config.items_per_prompt.max(1);
+        udf.retry_budget = config.retry_budget;
+        udf.concurrent_prompts = Backend::Llama.resolve_concurrency(config.max_concurrent_prompts);
+        if let Some(temperature) = config.temperature {
+            udf = udf.with_temperature(temperature);
+        }
+        Ok(udf)
+    }
+
+    /// Registers the function under `name` instead of its default name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets how the function advertises itself to the planner, [`Volatility::Volatile`]
+    /// by default since answers are sampled. `Immutable` is only safe together with
+    /// [`AskLlamaWithConfidence::with_deterministic`].
+    pub fn with_volatility(mut self, volatility: Volatility) -> Self {
+        self.signature = Signature::exact(vec![DataType::Utf8, DataType::Utf8], volatility);
+        self
+    }
+
+    /// Overrides the sampling temperature, 0.1 by default.
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Caps the tokens generated per chunk, independently of the context size. By
+    /// default a chunk of `n` rows may generate `n * 32` tokens, enough for short
+    /// answers, so a rambling model cannot run until the context is full. Generation
+    /// also stops as soon as the chunk has one answer line per row, see [`LineStop`].
+    pub fn with_max_new_tokens(mut self, max_new_tokens: usize) -> Self {
+        self.max_new_tokens = Some(max_new_tokens);
+        self
+    }
+
+    /// Uses pure greedy decoding so answers are reproducible, see
+    /// [`LlamaApp::with_deterministic`]. The temperature is then ignored.
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.llama_app = self.llama_app.with_deterministic(deterministic);
+        self
+    }
+
+    /// Seeds the sampler of the first generation of every chunk, [`DEFAULT_SEED`] by
+    /// default. Retries use seeds derived from it, see [`attempt_seed`].
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sets how many times a chunk whose output has the wrong number of lines is
+    /// generated again, 0 by default. Each retry samples with another seed, so it can
+    /// take a different path; with [`AskLlamaWithConfidence::with_deterministic`] the
+    /// seed is ignored and a retry repeats the same output.
+    pub fn with_max_chunk_retries(mut self, max_chunk_retries: usize) -> Self {
+        self.max_chunk_retries = max_chunk_retries;
+        self
+    }
+
+    /// Caps the total number of retries across all chunks of one invocation, see
+    /// [`RetryBudget`].
+    pub fn with_retry_budget(mut self, retry_budget: usize) -> Self {
+        self.retry_budget = Some(retry_budget);
+        self
+    }
+
+    /// Sets how many chunks are generated in parallel, overriding the Llama default.
+    /// See [`Backend`] for the defaults and limits.
+    pub fn with_max_concurrent_prompts(mut self, max_concurrent_prompts: usize) -> Self {
+        self.concurrent_prompts = Backend::Llama.resolve_concurrency(Some(max_concurrent_prompts));
+        self
+    }
+
+    // built on first use, like the pool of AskLLM
+    fn thread_pool(&self) -> &rayon::ThreadPool {
+        self.thread_pool.get_or_init(|| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(self.concurrent_prompts)
+                .build()
+                .expect("Failed to create rayon thread pool")
+        })
+    }
+
+    /// Replaces the system prompt, [`SYSTEM_PROMPT`] by default.
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.system_prompt = system_prompt.into();
+        self
+    }
+
+    /// Overrides the detected chat template.
+    pub fn with_chat_template(mut self, chat_template: ChatTemplate) -> Self {
+        self.chat_template = chat_template;
+        self
+    }
+
+    /// Shares `cancellation` with the UDF, e.g. the handle returned by
+    /// [`crate::register_ai_udfs`]. Once cancelled, the running generation stops
+    /// mid-stream (see [`LlamaApp::with_cancellation`]) and the rows of unfinished
+    /// chunks are returned as NULL.
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.llama_app = self.llama_app.with_cancellation(cancellation.clone());
+        self.cancellation = cancellation;
+        self
+    }
+
+    fn output_fields() -> Fields {
+        Fields::from(vec![
+            Field::new("answer", DataType::Utf8, true),
+            Field::new("confidence", DataType::Float64, true),
+        ])
+    }
+
+    // the answer and confidence of every row of `chunk`, retried within
+    // max_chunk_retries and the retry budget; rows of a chunk cancelled before or while
+    // it ran are NULL
+    fn answer_chunk(
+        &self,
+        instruction: &str,
+        chunk: &[String],
+        retry_budget: &RetryBudget,
+    ) -> Vec<(Option<String>, Option<f64>)> {
+        if self.cancellation.is_cancelled() {
+            return vec![(None, None); chunk.len()];
+        }
+        let mut attempt = 0;
+        let outcome = loop {
+            match self.process_chunk(instruction, chunk, attempt) {
+                Err(e)
+                    if attempt < self.max_chunk_retries
+                        && !self.cancellation.is_cancelled()
+                        && retry_budget.try_acquire() =>
+                {
+                    attempt += 1;
+                    log::warn!("retrying chunk (attempt {}) after error: {}", attempt, e);
+                }
+                outcome => break outcome,
+            }
+        };
+        match outcome {
+            Ok(records) => records
+                .into_iter()
+                .map(|(answer, confidence)| (Some(answer), Some(confidence)))
+                .collect(),
+            // a generation cut short by cancellation has too few lines
+            Err(_) if self.cancellation.is_cancelled() => vec![(None, None); chunk.len()],
+            Err(e) => vec![(Some(format!("Error processing chunk: {}", e)), None); chunk.len()],
+        }
+    }
+
+    // the confidence of a row is the geometric mean of the token probabilities on its output line
+    fn process_chunk(
+        &self,
+        instruction: &str,
+        vals: &[String],
+        attempt: usize,
+    ) -> Result<Vec<(String, f64)>> {
+        let prompt =
+            get_prompt_with_system(self.chat_template, &self.system_prompt, instruction, vals);
+        let tokens = self
+            .llama_app
+            .generate_text_with_logprobs(
+                &prompt,
+                self.ctx_size,
+                self.temperature,
+                Some(attempt_seed(self.seed.unwrap_or(DEFAULT_SEED), attempt)),
+                Some(self.max_new_tokens.unwrap_or(vals.len() * TOKENS_PER_ROW)),
+                Some(&LineStop::answer_lines(vals.len())),
+            )
+            .map_err(|e| DataFusionError::Internal(e.to_string()))?;
+        let answers: Vec<(String, f64)> = line_confidences(&tokens)
+            .into_iter()
+            .filter_map(|(line, confidence)| {
+                line.split_once("->")
+                    .map(|(_, value)| (value.trim().to_string(), confidence))
+            })
+            .collect();
+        if answers.len() != vals.len() {
+            return Err(DataFusionError::Internal(format!(
+                "mismatched result count: {} != {}",
+                answers.len(),
+                vals.len()
+            )));
+        }
+        Ok(answers)
+    }
+}
+
+impl ScalarUDFImpl for AskLlamaWithConfidence {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+    fn return_type(&self, args: &[DataType]) -> Result<DataType> {
+        if !matches!(args.first(), Some(&DataType::Utf8)) {
+            return plan_err!("ask_llm_confidence only accepts Utf8 arguments");
+        }
+        Ok(DataType::Struct(Self::output_fields()))
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        let ScalarFunctionArgs { args, .. } = args;
+        let (instruction, col_values) = match (args.first(), args.get(1)) {
+            (
+                Some(ColumnarValue::Scalar(ScalarValue::Utf8(instruction))),
+                Some(ColumnarValue::Array(col_values)),
+            ) => (instruction.clone().unwrap_or_default(), col_values),
+            _ => {
+                return plan_err!(
+                    "ask_llm_confidence only accepts 2 arguments in the form of 'instruction' (string), 'column_value' (column)"
+                );
+            }
+        };
+        let col_values = as_string_array(col_values.as_ref())?;
+        let values: Vec<String> = col_values
+            .iter()
+            .map(|opt| opt.unwrap_or_default().to_string())
+            .collect();
+
+        // the llama.cpp model sits behind a global lock, so by default the pool has a
+        // single worker and chunks run one at a time (see `Backend::Llama`)
+        let chunks: Vec<&[String]> = values.chunks(self.items_per_prompt).collect();
+        let retry_budget = RetryBudget::new(self.retry_budget);
+        let rows: Vec<(Option<String>, Option<f64>)> = self.thread_pool().install(|| {
+            chunks
+                .par_iter()
+                .flat_map_iter(|chunk| self.answer_chunk(&instruction, chunk, &retry_budget))
+                .collect()
+        });
+        let (answers, confidences): (Vec<Option<String>>, Vec<Option<f64>>) =
+            rows.into_iter().unzip();
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(answers)),
+            Arc::new(Float64Array::from(confidences)),
+        ];
+        let result = StructArray::try_new(Self::output_fields(), columns, None)?;
+        Ok(ColumnarValue::Array(Arc::new(result)))
+    }
+
+    fn documentation(&self) -> Option<&Documentation> {
+        self.doc()
+    }
+}
@@ -1,78 +1,2550 @@
-use datafusion::arrow::array::StringArray;
-use datafusion::arrow::datatypes::DataType;
+use datafusion::arrow::array::{
+    Array, ArrayRef, AsArray, Float64Array, RunArray, StringArray, StructArray,
+};
+use datafusion::arrow::compute::can_cast_types;
+use datafusion::arrow::datatypes::{
+    DataType, Field, Fields, Int16Type, Int32Type, Int64Type, RunEndIndexType,
+};
 use datafusion_common::cast::as_string_array;
-use datafusion_common::{DataFusionError, Result, ScalarValue, plan_err};
+use datafusion_common::{DataFusionError, Result, ScalarValue, exec_err, plan_err};
 use datafusion_doc::Documentation;
 use datafusion_expr::ScalarUDFImpl;
-use datafusion_expr::{ColumnarValue, ScalarFunctionArgs, Signature, Volatility};
+use datafusion_expr::{
+    ColumnarValue, ReturnInfo, ReturnTypeArgs, ScalarFunctionArgs, Signature, Volatility,
+};
 use datafusion_macros::user_doc;
 use rayon::prelude::*;
+use regex::Regex;
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
 use std::any::Any;
-use std::sync::Arc;
-use std::time::Instant;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
-use crate::ollama_utils::OllamaApp;
+use crate::audit::AuditLog;
+use crate::autotune::{AutoTuneConfig, BatchTuner};
+use crate::balancer::{EndpointPool, LoadBalancing};
+use crate::cache::ShardedCache;
+use crate::cache_file::{CacheEntry, read_entries, write_entries};
+use crate::cancellation::Cancellation;
+use crate::categorical::CategoricalGate;
+use crate::chat_template::ChatTemplate;
+use crate::coalesce::Coalescer;
+use crate::config::{AiConfig, Backend};
+use crate::consistency::SelfConsistency;
+use crate::cost::{CostCallback, RowCost, TokenPricing};
+use crate::dry_run::{COMPLETION_TOKENS_PER_ROW, CallEstimate, DryRun};
+use crate::few_shot::{FewShotTable, render_examples};
+use crate::input_format::InputFormat;
+use crate::input_guard::{GuardAction, InputGuard};
+use crate::length_estimator::LengthEstimator;
+use crate::llm_utils::get_prompt_overhead;
+use crate::normalize::Normalization;
+use crate::ollama_utils::{
+    ChatResponse, ErrorClass, HttpSettings, NO_RESPONSE_CONTENT, OllamaApp, TokenUsage,
+    classify_error, is_unreachable, is_unsupported_format,
+};
+use crate::ordering::Scatter;
+use crate::output_builder::OutputBuilder;
+use crate::progress::ProgressLog;
+use crate::rate_limit::RateLimiter;
+use crate::retry::{RetryBudget, attempt_seed};
+use crate::routing::Routing;
+use crate::row_error::RowError;
+use crate::runtime::RuntimeConfig;
+use crate::shared_backend::SharedBackend;
+use crate::telemetry;
+use crate::windowing::WindowingConfig;
 
-// Thread-local runtime creator function so that we can use async calls in sync contexts
-fn create_tokio_runtime() -> tokio::runtime::Runtime {
-    tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime")
+/// The default system prompt of `ask_llm`, which asks for one short `N -> answer` line per
+/// item.
+pub const ASK_LLM_SYSTEM_PROMPT: &str = "\
+    You evaluate a numbered list of items according to the user's instruction. Answer \
+    every item, in order, on its own line formatted as 'N -> answer' where N is the item \
+    number. Keep each answer short and write nothing else.";
+
+/// The system prompt [`AskLLM::with_indexed_answers`] uses instead of the default,
+/// which asks for one `i: answer` line per item.
+pub const ASK_LLM_INDEXED_SYSTEM_PROMPT: &str = "\
+    You evaluate a numbered list of items according to the user's instruction. Return \
+    exactly one line per item, formatted as 'i: answer' where i is the item number, and \
+    no extra text. Keep each answer short.";
+
+/// The placeholder answers `ask_llm` returns as NULL by default, see
+/// [`AskLLM::with_null_sentinels`].
+pub const DEFAULT_NULL_SENTINELS: [&str; 1] = [NO_RESPONSE_CONTENT];
+
+// prefix of the value emitted for every row of a chunk whose answer count did not match
+const MISMATCH_ERROR_PREFIX: &str = "Error: mismatched result count";
+
+// how much of an unparsable response is logged, see response_preview
+const RESPONSE_PREVIEW_CHARS: usize = 200;
+
+/// What to do when the model answers fewer rows than it was given, which usually
+/// means the output hit the token limit (Ollama reports `done_reason: "length"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TruncationMode {
+    /// Every row of the chunk gets the count-mismatch error.
+    #[default]
+    Fail,
+    /// When the response was cut off (`done_reason: "length"`), keeps the answers for
+    /// the leading rows and sends only the unanswered tail again, until every row is
+    /// answered or a response answers none of them; the rows left then get the
+    /// count-mismatch error. A response that ended normally but skipped rows is
+    /// handled as with [`TruncationMode::Fail`].
+    KeepLeading,
+}
+
+/// Which inputs [`AskLLM`] treats as missing. Missing rows are never sent to the model
+/// and produce NULL, which helps with CSVs that encode missing data as empty strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlankInput {
+    /// Every row is sent to the model, NULLs as empty strings.
+    #[default]
+    Keep,
+    /// NULL and empty strings are missing.
+    Empty,
+    /// NULL, empty and whitespace-only strings are missing.
+    Whitespace,
+}
+
+impl BlankInput {
+    fn is_missing(&self, value: Option<&str>) -> bool {
+        match (self, value) {
+            (BlankInput::Keep, _) => false,
+            (_, None) => true,
+            (BlankInput::Empty, Some(value)) => value.is_empty(),
+            (BlankInput::Whitespace, Some(value)) => value.trim().is_empty(),
+        }
+    }
+}
+
+/// How answers are extracted from the model output, see [`AskLLM::with_response_parsing`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResponseParsing {
+    /// Every line containing `->` is an answer: the text after the first `->`.
+    #[default]
+    Arrow,
+    /// Also accepts lines where the model restates the input after the row number,
+    /// like `1. "the original text" -> answer` or `1. the original text: answer`.
+    /// The echoed input is recognized by comparing it with the value sent for that
+    /// row, so echoes that contain `->` or `:` themselves are discarded correctly.
+    EchoTolerant,
+    /// Every line starting with a row number (`i: answer`, also `i -> answer`,
+    /// `i. answer` or `i) answer`) answers the row with that number, wherever the line
+    /// is, so answers the model reordered are matched to their rows and a row it
+    /// skipped in the middle is NULL. Rows missing after the last labelled one are a
+    /// count mismatch, as the output was probably cut off (see [`TruncationMode`]).
+    /// Each request states the expected lines, see [`AskLLM::with_indexed_answers`].
+    Indexed,
+}
+
+/// Extracts the per-row answers from a raw model response, see
+/// [`AskLLM::with_response_parser`].
+pub trait ResponseParser: std::fmt::Debug + Send + Sync {
+    /// Returns the answers of a response to a prompt with `expected` rows, in row
+    /// order, with `None` for a row the parser knows was skipped (answered NULL). A
+    /// result of a different length than `expected` is a count mismatch, handled
+    /// like any other (see [`TruncationMode`]); an empty result triggers the format
+    /// re-prompt.
+    fn parse(&self, raw: &str, expected: usize) -> Vec<Option<String>>;
+}
+
+/// The default [`ResponseParser`]: every line containing `->` is an answer, the
+/// text after the first `->`, up to the expected number of answers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArrowParser;
+
+impl ResponseParser for ArrowParser {
+    fn parse(&self, raw: &str, expected: usize) -> Vec<Option<String>> {
+        parse_llm_response(raw, expected)
+            .into_iter()
+            .map(Some)
+            .collect()
+    }
+}
+
+/// A [`ResponseParser`] for output formats that a regex describes precisely: every
+/// match of the pattern is an answer, its trimmed `value` capture group. Matches are
+/// searched in the whole response, so use `(?m)` with `^`/`$` to match per line, e.g.
+/// `(?m)^\d+\.\s*(?P<value>\w+)$` for `1. positive`. A match whose `value` group did
+/// not participate is a skipped row. Parsing stops after the expected number of answers.
+#[derive(Debug, Clone)]
+pub struct RegexParser {
+    pattern: Regex,
+}
+
+impl RegexParser {
+    /// Fails if `pattern` is not a valid regex or has no `value` group.
+    pub fn new(pattern: &str) -> Result<Self> {
+        let pattern = Regex::new(pattern).map_err(|e| {
+            DataFusionError::Configuration(format!("invalid answer pattern: {}", e))
+        })?;
+        if !pattern.capture_names().any(|name| name == Some("value")) {
+            return Err(DataFusionError::Configuration(format!(
+                "answer pattern {} has no `value` capture group",
+                pattern
+            )));
+        }
+        Ok(Self { pattern })
+    }
+}
+
+impl ResponseParser for RegexParser {
+    fn parse(&self, raw: &str, expected: usize) -> Vec<Option<String>> {
+        self.pattern
+            .captures_iter(raw)
+            .map(|captures| {
+                captures
+                    .name("value")
+                    .map(|value| value.as_str().trim().to_string())
+            })
+            .take(expected)
+            .collect()
+    }
+}
+
+/// A per-row rule that answers a value without the model, see [`AskLLM::with_pre_filter`].
+#[derive(Clone)]
+struct PreFilter(Arc<dyn Fn(&str) -> Option<String> + Send + Sync>);
+
+impl std::fmt::Debug for PreFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreFilter").finish_non_exhaustive()
+    }
+}
+
+/// A domain check of a chunk's answers against its inputs, see
+/// [`AskLLM::with_chunk_validator`].
+#[derive(Clone)]
+struct ChunkValidator(Arc<dyn Fn(&[String], &[String]) -> bool + Send + Sync>);
+
+impl std::fmt::Debug for ChunkValidator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkValidator").finish_non_exhaustive()
+    }
+}
+
+/// The maximum estimated prompt length of a chunk, see [`AskLLM::with_max_prompt_tokens`].
+#[derive(Debug, Clone)]
+struct PromptLimit {
+    max_len: usize,
+    estimator: LengthEstimator,
+}
+
+impl PromptLimit {
+    fn chars(max_chars: usize) -> Self {
+        Self {
+            max_len: max_chars.max(1),
+            estimator: LengthEstimator::chars(),
+        }
+    }
+}
+
+/// A per-row answer rewrite, see [`AskLLM::with_post_process`].
+#[derive(Clone)]
+struct PostProcess(Arc<dyn Fn(&str, &str) -> String + Send + Sync>);
+
+impl std::fmt::Debug for PostProcess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PostProcess").finish_non_exhaustive()
+    }
+}
+
+// splits a parsed answer into the answer proper and a detail returned next to it, see
+// AskLLM::with_detail_split
+#[derive(Clone)]
+struct DetailSplit(Arc<dyn Fn(&str) -> (String, Option<String>) + Send + Sync>);
+
+impl std::fmt::Debug for DetailSplit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DetailSplit").finish_non_exhaustive()
+    }
+}
+
+// a row's detail travels with its answer as one string, through the caches and
+// everything else that handles answers; a NULL answer with a detail starts with
+// NULL_DETAIL instead
+const DETAIL_SEPARATOR: char = '\u{1f}';
+const NULL_DETAIL: char = '\u{1e}';
+
+fn join_detail(answer: Option<String>, detail: Option<String>) -> Option<String> {
+    match (answer, detail) {
+        (Some(answer), Some(detail)) => Some(format!("{answer}{DETAIL_SEPARATOR}{detail}")),
+        (None, Some(detail)) => Some(format!("{NULL_DETAIL}{detail}")),
+        (answer, None) => answer,
+    }
+}
+
+fn split_detail(answer: Option<String>) -> (Option<String>, Option<String>) {
+    let Some(answer) = answer else {
+        return (None, None);
+    };
+    if let Some(detail) = answer.strip_prefix(NULL_DETAIL) {
+        return (None, Some(detail.to_string()));
+    }
+    match answer.split_once(DETAIL_SEPARATOR) {
+        Some((answer, detail)) => (Some(answer.to_string()), Some(detail.to_string())),
+        None => (Some(answer), None),
+    }
 }
 
 #[user_doc(
     doc_section(label = "AI functions"),
-    description = "Ask LLM",
-    syntax_example = "ask_llm('instruction', 'column_value')"
+    description = "Ask LLM. With the optional `task` argument, each row uses the instruction registered for its task via `AskLLM::with_task_template`; rows with a NULL or unregistered task use `instruction`. With `AskLLM::with_instruction_files`, an instruction of the form `file://path` or `@path` is read from that file.",
+    syntax_example = "ask_llm('instruction', 'column_value' [, 'task'])"
 )]
 #[derive(Debug)]
 pub struct AskLLM {
+    name: String,
     signature: Signature,
-    ollama_model: String,
-    ollama_url: String,
+    ollama_model: Arc<str>,
+    endpoints: EndpointPool,
+    fallback: Option<Fallback>,
+    routed: Option<Routed>,
+    system_prompt: String,
+    temperature: Option<f32>,
+    ollama_options: Map<String, Value>,
+    seed: Option<u32>,
+    seed_variation: bool,
+    thinking: bool,
+    cancellation: Cancellation,
+    windowing: Option<WindowingConfig>,
+    items_per_prompt: usize,
+    single_request_rows: Option<usize>,
+    concurrent_prompts: usize,
+    thread_pool: OnceLock<rayon::ThreadPool>,
+    max_chunk_retries: usize,
+    retry_budget: Option<usize>,
+    task_templates: HashMap<String, String>,
+    instruction_files: Option<PathBuf>,
+    output: OutputBuilder,
+    auto_tune: Option<BatchTuner>,
+    pre_filter: Option<PreFilter>,
+    post_process: Option<PostProcess>,
+    detail_split: Option<DetailSplit>,
+    chunk_validator: Option<ChunkValidator>,
+    truncation_mode: TruncationMode,
+    blank_input: BlankInput,
+    input_guard: Option<InputGuard>,
+    format_reprompt: bool,
+    response_parsing: ResponseParsing,
+    response_parser: Arc<dyn ResponseParser>,
+    response_format: Option<Value>,
+    latency_column: bool,
+    error_column: bool,
+    consistency: Option<SelfConsistency>,
+    categories: Option<CategoricalGate>,
+    input_format: InputFormat,
+    few_shot: Option<FewShotTable>,
+    coalescer: Option<Arc<Coalescer<RowAnswer>>>,
+    global_row_numbers: bool,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    http: HttpSettings,
+    own_backend: OnceLock<SharedBackend>,
+    model_column: bool,
+    provenance: bool,
+    cost_callback: Option<CostCallback>,
+    answer_cache: Option<Arc<ShardedCache<(String, String), String>>>,
+    response_cache: Option<Arc<ShardedCache<[u8; 32], ChatResponse>>>,
+    audit_log: Option<Arc<AuditLog>>,
+    instruction_prefix: String,
+    instruction_suffix: String,
+    instruction_hint: String,
+    chunk_deadline: Option<Duration>,
+    prompt_limit: Option<PromptLimit>,
+    chat_template: Option<ChatTemplate>,
+    fail_fast: bool,
+    reconcile_duplicates: bool,
+    normalization: Option<Normalization>,
+    null_sentinels: Vec<String>,
+    dry_run: Option<DryRun>,
+    progress: Option<ProgressLog>,
+    runtime: RuntimeConfig,
+    shared_backend: Option<Arc<SharedBackend>>,
+}
+
+// the answer of a row, with the latency of the backend call that produced it split
+// evenly over the rows of its chunk and, with self-consistency, the share of samples
+// agreeing with it; rows answered without the backend have neither. Failed rows
+// carry their error, and its message as the answer
+#[derive(Debug, Clone, Default, PartialEq)]
+struct RowAnswer {
+    answer: Option<String>,
+    latency_ms: Option<f64>,
+    confidence: Option<f64>,
+    error: Option<RowError>,
+    served_by: Option<ServedBy>,
 }
 
-impl AskLLM {
-    pub fn new() -> Self {
-        Self {
-            signature: Signature::exact(
-                vec![DataType::Utf8, DataType::Utf8],
-                Volatility::Immutable,
-            ),
-            ollama_model: "llama32-df:latest".to_string(),
-            ollama_url: "http://localhost:11434/api/chat".to_string(),
+impl RowAnswer {
+    fn local(answer: Option<String>) -> Self {
+        Self {
+            answer,
+            ..Self::default()
+        }
+    }
+
+    fn timed(answers: Vec<Option<String>>, latency: Duration) -> Vec<Self> {
+        let latency_ms = latency.as_secs_f64() * 1000.0 / answers.len().max(1) as f64;
+        answers
+            .into_iter()
+            .map(|answer| Self {
+                answer,
+                latency_ms: Some(latency_ms),
+                ..Self::default()
+            })
+            .collect()
+    }
+
+    fn failed(mut self, error: RowError) -> Self {
+        self.answer = error.answer();
+        self.error = Some(error);
+        self
+    }
+}
+
+// a request error retrying cannot fix, see classify_error
+#[derive(Debug)]
+struct TerminalRequestError(String);
+
+impl std::fmt::Display for TerminalRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TerminalRequestError {}
+
+// keeps whether a failed chat request is worth retrying in the returned error
+fn request_error(error: anyhow::Error) -> DataFusionError {
+    match classify_error(&error) {
+        ErrorClass::Retryable => DataFusionError::Internal(error.to_string()),
+        ErrorClass::Terminal => {
+            DataFusionError::External(Box::new(TerminalRequestError(error.to_string())))
+        }
+    }
+}
+
+fn is_terminal(error: &DataFusionError) -> bool {
+    matches!(error, DataFusionError::External(e) if e.is::<TerminalRequestError>())
+}
+
+// outside of run_prompt (e.g. in tests) there is no chunk to record it for
+fn record_usage(tokens: Option<TokenUsage>, backend_time: Duration) {
+    let _ = CHUNK_USAGE.try_with(|usage| {
+        let mut usage = usage.borrow_mut();
+        usage.tokens += tokens.unwrap_or_default();
+        usage.backend_time += backend_time;
+    });
+}
+
+// splits a chunk's usage evenly over its rows
+fn row_costs(
+    pricing: TokenPricing,
+    usage: ChunkUsage,
+    served_by: Option<ServedBy>,
+    rows: usize,
+) -> Vec<RowCost> {
+    if rows == 0 {
+        return vec![];
+    }
+    let share = rows as f64;
+    let prompt_tokens = usage.tokens.prompt_tokens as f64 / share;
+    let completion_tokens = usage.tokens.completion_tokens as f64 / share;
+    let cost = RowCost {
+        model: served_by.map(|served_by| served_by.model.to_string()),
+        prompt_tokens,
+        completion_tokens,
+        backend_time: usage.backend_time / rows as u32,
+        cost: pricing.cost(prompt_tokens, completion_tokens),
+    };
+    vec![cost; rows]
+}
+
+// the model chunks are sent to once they still fail after their retries
+#[derive(Debug)]
+struct Fallback {
+    model: Arc<str>,
+    endpoints: EndpointPool,
+}
+
+// the UDF answering the rows selected by `routing` instead of this one
+#[derive(Debug)]
+struct Routed {
+    premium: Arc<AskLLM>,
+    routing: Routing,
+}
+
+// the model, and endpoint unless the response was replayed from the response cache,
+// that answered a chunk
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ServedBy {
+    model: Arc<str>,
+    endpoint: Option<String>,
+}
+
+tokio::task_local! {
+    // set by request_chunk for the chunk run_prompt is driving, so the identity does
+    // not have to be threaded through every layer in between
+    static SERVED_BY: RefCell<Option<ServedBy>>;
+    // the backend usage of the chunk run_prompt is driving, summed over its requests
+    static CHUNK_USAGE: RefCell<ChunkUsage>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ChunkUsage {
+    tokens: TokenUsage,
+    backend_time: Duration,
+}
+
+// state shared by all chunks of a single invoke_with_args call
+#[derive(Debug)]
+struct Invocation {
+    retry_budget: RetryBudget,
+    timeouts: AtomicUsize,
+    // the first chunk error, only recorded with fail_fast
+    failure: Mutex<Option<String>>,
+}
+
+impl Invocation {
+    fn new(retry_budget: Option<usize>) -> Self {
+        Self {
+            retry_budget: RetryBudget::new(retry_budget),
+            timeouts: AtomicUsize::new(0),
+            failure: Mutex::new(None),
+        }
+    }
+
+    fn fail(&self, error: String) {
+        self.failure.lock().unwrap().get_or_insert(error);
+    }
+
+    fn has_failed(&self) -> bool {
+        self.failure.lock().unwrap().is_some()
+    }
+}
+
+impl AskLLM {
+    pub fn new() -> Self {
+        Self::with_config(&AiConfig::default())
+    }
+
+    /// Creates the UDF using the chat model from `config`.
+    pub fn with_config(config: &AiConfig) -> Self {
+        Self {
+            name: "ask_llm".to_string(),
+            signature: Signature::user_defined(Volatility::Volatile),
+            ollama_model: config.chat_model.as_str().into(),
+            endpoints: EndpointPool::new(config.chat_urls()),
+            fallback: config.fallback_chat().map(|(model, url)| Fallback {
+                model: model.into(),
+                endpoints: EndpointPool::new([url]),
+            }),
+            routed: None,
+            system_prompt: ASK_LLM_SYSTEM_PROMPT.to_string(),
+            temperature: config.temperature,
+            ollama_options: config.ollama_options.clone(),
+            seed: None,
+            seed_variation: true,
+            thinking: false,
+            cancellation: Cancellation::new(),
+            windowing: config.windowing.clone(),
+            items_per_prompt: config.items_per_prompt.max(1),
+            single_request_rows: None,
+            // the chat requests always go to Ollama, whatever config.backend adds
+            concurrent_prompts: Backend::Ollama.resolve_concurrency(config.max_concurrent_prompts),
+            thread_pool: OnceLock::new(),
+            max_chunk_retries: config.max_chunk_retries,
+            retry_budget: config.retry_budget,
+            task_templates: HashMap::new(),
+            instruction_files: None,
+            output: OutputBuilder::default(),
+            auto_tune: None,
+            pre_filter: None,
+            post_process: None,
+            detail_split: None,
+            chunk_validator: None,
+            truncation_mode: TruncationMode::default(),
+            blank_input: BlankInput::default(),
+            input_guard: None,
+            format_reprompt: true,
+            response_parsing: ResponseParsing::default(),
+            response_parser: Arc::new(ArrowParser),
+            response_format: None,
+            latency_column: false,
+            error_column: false,
+            consistency: None,
+            categories: None,
+            input_format: InputFormat::default(),
+            few_shot: None,
+            coalescer: None,
+            global_row_numbers: false,
+            model_column: false,
+            provenance: false,
+            cost_callback: None,
+            http: config.http.clone(),
+            own_backend: OnceLock::new(),
+            rate_limiter: config
+                .max_requests_per_second
+                .map(|rps| Arc::new(RateLimiter::new(rps))),
+            answer_cache: None,
+            response_cache: None,
+            audit_log: None,
+            instruction_prefix: String::new(),
+            instruction_suffix: String::new(),
+            instruction_hint: String::new(),
+            chunk_deadline: config.chunk_deadline,
+            prompt_limit: config.max_prompt_chars.map(PromptLimit::chars),
+            chat_template: None,
+            fail_fast: false,
+            reconcile_duplicates: false,
+            normalization: None,
+            null_sentinels: DEFAULT_NULL_SENTINELS.map(String::from).to_vec(),
+            dry_run: None,
+            progress: config.progress_interval.map(ProgressLog::new),
+            runtime: RuntimeConfig::default(),
+            shared_backend: None,
+        }
+    }
+
+    /// Bounds the total time spent on a chunk, including retries and re-requests.
+    /// A chunk that misses the deadline returns NULL for all its rows, and the number
+    /// of such chunks is logged at the end of the invocation. Disabled by default.
+    pub fn with_chunk_deadline(mut self, deadline: Duration) -> Self {
+        self.chunk_deadline = Some(deadline);
+        self
+    }
+
+    /// Wraps every instruction passed in SQL (or resolved from a task template) as
+    /// `prefix + instruction + suffix` before it is sent. No separator is added, so
+    /// include newlines or spaces in the prefix and suffix themselves.
+    pub fn with_instruction_wrapper(
+        mut self,
+        prefix: impl Into<String>,
+        suffix: impl Into<String>,
+    ) -> Self {
+        self.instruction_prefix = prefix.into();
+        self.instruction_suffix = suffix.into();
+        self
+    }
+
+    // sent after the wrapped instruction, for UDFs built on AskLLM
+    pub(crate) fn with_instruction_hint(mut self, hint: impl Into<String>) -> Self {
+        self.instruction_hint = hint.into();
+        self
+    }
+
+    /// Appends every prompt and raw response to `audit_log`, see [`AuditLog`].
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(Arc::new(audit_log));
+        self
+    }
+
+    /// Sets how a response with fewer answers than rows is handled, see [`TruncationMode`].
+    pub fn with_truncation_mode(mut self, truncation_mode: TruncationMode) -> Self {
+        self.truncation_mode = truncation_mode;
+        self
+    }
+
+    /// Sets a hook that rewrites each parsed answer, given the row input and the raw
+    /// answer, e.g. to map synonyms or apply business rules. It runs only on answers
+    /// parsed from a well-formed response, never on error values. With windowing, it
+    /// runs per window, with the window text as the input.
+    pub fn with_post_process(
+        mut self,
+        post_process: impl Fn(&str, &str) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.post_process = Some(PostProcess(Arc::new(post_process)));
+        self
+    }
+
+    // splits every parsed answer into the answer proper, which is post-processed as
+    // usual, and a detail that answer_with_details returns next to it, for
+    // ask_llm_explain and ask_llm_raw
+    pub(crate) fn with_detail_split(
+        mut self,
+        split: impl Fn(&str) -> (String, Option<String>) + Send + Sync + 'static,
+    ) -> Self {
+        self.detail_split = Some(DetailSplit(Arc::new(split)));
+        self
+    }
+
+    /// Checks every parsed and post-processed chunk with `validator`, called with the
+    /// chunk's inputs and answers (unparsed answers as empty strings). A chunk it
+    /// rejects fails like a backend error, so it is retried within
+    /// [`AskLLM::with_max_chunk_retries`] and the retry budget. Use it for invariants
+    /// only visible across rows, e.g. scores that must rise with the inputs.
+    pub fn with_chunk_validator(
+        mut self,
+        validator: impl Fn(&[String], &[String]) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.chunk_validator = Some(ChunkValidator(Arc::new(validator)));
+        self
+    }
+
+    /// Remembers the answer for every (instruction, value) pair, so repeated values are
+    /// only sent to the model once for the lifetime of this UDF. Failed rows are not
+    /// cached. The cache is sharded by the size of the thread pool and resharded if
+    /// [`AskLLM::with_max_concurrent_prompts`] changes it later.
+    pub fn with_answer_cache(mut self) -> Self {
+        let workers = self.concurrent_prompts;
+        self.answer_cache = Some(Arc::new(ShardedCache::for_workers(workers)));
+        self
+    }
+
+    /// Fills the answer cache, enabling it if needed, from a file written by
+    /// [`AskLLM::export_answer_cache`], so a rerun over the same data starts warm.
+    /// Entries of another model, or whose prompt or answer settings differ from this
+    /// UDF's (see [`CacheEntry`]), are stale and skipped. Entries for other
+    /// instructions are loaded but only answer queries with that instruction, as the
+    /// instruction is part of the cache key.
+    pub fn with_answer_cache_preload(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        if self.answer_cache.is_none() {
+            self = self.with_answer_cache();
+        }
+        let entries = read_entries(path)?;
+        let prompt_sha256 = self.prompt_sha256();
+        let cache = self
+            .answer_cache
+            .as_ref()
+            .expect("answer cache enabled above");
+        let mut stale = 0;
+        for entry in entries {
+            if *entry.model != *self.ollama_model || entry.prompt_sha256 != prompt_sha256 {
+                stale += 1;
+                continue;
+            }
+            cache.insert((entry.instruction, entry.value), entry.answer);
+        }
+        log::info!(
+            "answer cache preloaded with {} answers, {} stale entries skipped",
+            cache.len(),
+            stale
+        );
+        Ok(self)
+    }
+
+    /// Writes every answer in the answer cache to `path` as JSON lines, see
+    /// [`CacheEntry`], for [`AskLLM::with_answer_cache_preload`] in a later run.
+    /// Returns the number of answers written. Fails without an answer cache.
+    pub fn export_answer_cache(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let Some(cache) = &self.answer_cache else {
+            return Err(DataFusionError::Configuration(
+                "export_answer_cache needs AskLLM::with_answer_cache".to_string(),
+            ));
+        };
+        let prompt_sha256 = self.prompt_sha256();
+        let entries: Vec<CacheEntry> = cache
+            .entries()
+            .into_iter()
+            .map(|((instruction, value), answer)| CacheEntry {
+                model: self.ollama_model.to_string(),
+                prompt_sha256: prompt_sha256.clone(),
+                instruction,
+                value,
+                answer,
+            })
+            .collect();
+        write_entries(path, &entries)?;
+        Ok(entries.len())
+    }
+
+    // the parts of the prompt and the settings an answer depends on besides the model
+    // and the cache key
+    fn prompt_sha256(&self) -> String {
+        let mut hasher = Sha256::new();
+        let parts = [
+            self.system_prompt.clone(),
+            self.instruction_prefix.clone(),
+            self.instruction_suffix.clone(),
+            self.instruction_hint.clone(),
+            format!("{:?}", self.temperature),
+            Value::Object(self.ollama_options.clone()).to_string(),
+            self.output.data_type().to_string(),
+            format!("{:?}", self.response_parsing),
+            format!("{:?}", self.normalization),
+        ];
+        for part in parts {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+        let digest = hasher.finalize();
+        digest.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// Captures the reasoning of thinking models and answers from it when the message
+    /// content is empty, see [`OllamaApp::with_thinking`]. Off by default.
+    pub fn with_thinking(mut self, thinking: bool) -> Self {
+        self.thinking = thinking;
+        self
+    }
+
+    /// Returns a struct `{ answer, latency_ms }` instead of the bare answer, where
+    /// `latency_ms` is the time spent on the row's chunk (including retries) divided
+    /// by the number of rows in the chunk, so slow batches can be found with SQL. Rows
+    /// answered without the backend (NULL inputs, pre-filter, cache) have a NULL latency.
+    pub fn with_latency_column(mut self) -> Self {
+        self.latency_column = true;
+        self
+    }
+
+    /// Sets how numeric, temporal and boolean `column_value`s are turned into prompt
+    /// text, Arrow's display formatting by default, see [`InputFormat`]. Other
+    /// types are still cast to Utf8 by the planner.
+    pub fn with_input_format(mut self, input_format: InputFormat) -> Self {
+        self.input_format = input_format;
+        self
+    }
+
+    /// Adds `model` and `endpoint` fields to the returned struct, naming the model and
+    /// the chat endpoint that produced each row's answer, e.g. to compare the hosts of
+    /// [`AiConfig::chat_urls`]. With retries, windowing or self-consistency it is the
+    /// endpoint of the chunk's last request. Answers replayed from the response
+    /// cache have a NULL `endpoint`, and rows answered without the model have neither.
+    pub fn with_model_column(mut self) -> Self {
+        self.model_column = true;
+        self
+    }
+
+    /// Returns a struct `{ answer }` instead of the bare answer, as with the other
+    /// sidecar columns, so queries select the answer with `ask_llm(...)['answer']`
+    /// (`['value']` with [`AskLLM::with_error_column`]). That field carries provenance
+    /// metadata: the model, a SHA-256 of the instruction argument and this crate's
+    /// version, see [`AskLLM::provenance_metadata`]. Field metadata travels with the
+    /// schema through the plan and into files such as Parquet; the struct is needed
+    /// because DataFusion does not let a scalar function put metadata on its own
+    /// output field. The instruction must be a literal. Off by default.
+    pub fn with_provenance_metadata(mut self) -> Self {
+        self.provenance = true;
+        self
+    }
+
+    /// The metadata [`AskLLM::with_provenance_metadata`] attaches for `instruction`,
+    /// under the keys `datafusion_ai.model`, `datafusion_ai.instruction_sha256` and
+    /// `datafusion_ai.version`.
+    pub fn provenance_metadata(&self, instruction: &str) -> HashMap<String, String> {
+        let digest = Sha256::digest(instruction.as_bytes());
+        let digest: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+        HashMap::from([
+            (
+                "datafusion_ai.model".to_string(),
+                self.ollama_model.to_string(),
+            ),
+            ("datafusion_ai.instruction_sha256".to_string(), digest),
+            (
+                "datafusion_ai.version".to_string(),
+                env!("CARGO_PKG_VERSION").to_string(),
+            ),
+        ])
+    }
+
+    /// Calls `callback` once for every row of every chunk that completed, from the
+    /// worker thread that ran the chunk, with the row's share of the chunk's estimated
+    /// cost, see [`RowCost`]. The chunk's token usage, summed over all its requests
+    /// (retries, re-prompts, samples, windows and the fallback), is priced with
+    /// `pricing` and divided evenly over its rows, so summing the calls gives the
+    /// query's spend. Chunks that hit [`AskLLM::with_chunk_deadline`] or were skipped
+    /// are not reported; chunks that failed are, as their requests were paid for.
+    ///
+    /// For a local Ollama server the tokens are free: price them at zero (the default
+    /// [`TokenPricing`]) and budget on [`RowCost::backend_time`] instead, or price the
+    /// tokens at the machine's cost per token. Rows routed with
+    /// [`AskLLM::with_routing`] are reported by the premium `AskLLM`'s callback.
+    pub fn with_cost_callback(
+        mut self,
+        pricing: TokenPricing,
+        callback: impl Fn(&RowCost) + Send + Sync + 'static,
+    ) -> Self {
+        self.cost_callback = Some(CostCallback {
+            pricing,
+            callback: Arc::new(callback),
+        });
+        self
+    }
+
+    /// Sends at most `requests_per_second` chat requests per second, across all
+    /// chunks, retries and invocations of this UDF, see [`RateLimiter`]. Chunks wait
+    /// for their turn, so a chunk deadline also covers the wait. Unlimited by default.
+    pub fn with_max_requests_per_second(mut self, requests_per_second: f64) -> Self {
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(requests_per_second)));
+        self
+    }
+
+    /// Numbers the rows in the prompt by their position among the rows of the
+    /// invocation sent to the model, instead of from 1 in every chunk, so a chunk of
+    /// rows 6 to 10 is sent as `6. ...` to `10. ...`. Useful for position-sensitive
+    /// instructions such as rankings. Each chunk is still a separate prompt, so the
+    /// model cannot compare rows of different chunks; raise the chunk size for that.
+    /// Rows answered without the model (NULLs, pre-filter, answer cache) are not
+    /// numbered. Off by default.
+    pub fn with_global_row_numbers(mut self, global_row_numbers: bool) -> Self {
+        self.global_row_numbers = global_row_numbers;
+        self
+    }
+
+    /// Merges the rows of invocations running at the same time (e.g. of different
+    /// partitions) into one evaluation, so small record batches share prompts. An
+    /// invocation waits until `min_rows` rows from all overlapping invocations with the
+    /// same instruction have arrived, or for at most `window`, which is added to its
+    /// latency when it runs alone. See [`Coalescer`] for the trade-offs. Only
+    /// invocations without a task column are merged, and the merged rows share the
+    /// retry budget and fail-fast state of the invocation that evaluates them. Off by
+    /// default.
+    pub fn with_coalescing(mut self, window: Duration, min_rows: usize) -> Self {
+        self.coalescer = Some(Arc::new(Coalescer::new(window, min_rows)));
+        self
+    }
+
+    /// Appends few-shot examples read from `few_shot` to the instruction argument, see
+    /// [`FewShotTable`] for how they are selected and when the table is read. An
+    /// unreadable table fails the invocation.
+    pub fn with_few_shot_table(mut self, few_shot: FewShotTable) -> Self {
+        self.few_shot = Some(few_shot);
+        self
+    }
+
+    /// Sends every chunk `consistency.samples` times and answers each row with its
+    /// majority answer, see [`SelfConsistency`]. The function then returns a struct
+    /// with a `confidence` field, the share of samples agreeing with the answer, next to
+    /// `answer` (and `latency_ms` with [`AskLLM::with_latency_column`]). This multiplies
+    /// the calls and tokens of a query by the number of samples. The response cache is
+    /// bypassed, since replayed responses would always agree.
+    pub fn with_self_consistency(mut self, consistency: SelfConsistency) -> Self {
+        self.consistency = Some(consistency);
+        self
+    }
+
+    /// Only emits answers that are one of the labels of `gate`, agreed on by enough
+    /// samples with [`AskLLM::with_self_consistency`]; every other row is NULL. See
+    /// [`CategoricalGate`] for the checks and their order.
+    pub fn with_strict_categories(mut self, gate: CategoricalGate) -> Self {
+        self.categories = Some(gate);
+        self
+    }
+
+    /// Returns a struct `{ value, error }` instead of the bare answer, where a failed
+    /// row (see [`RowError`]) has a NULL `value` and its error message in `error`, and
+    /// every other row a NULL `error`. Failures can then be isolated with
+    /// `WHERE error IS NOT NULL` instead of matching `Error ...` answers. Combines
+    /// with the latency and confidence columns, which follow `error`.
+    pub fn with_error_column(mut self) -> Self {
+        self.error_column = true;
+        self
+    }
+
+    // the fields of the struct returned with an error, latency or confidence column
+    // `instruction` is the literal instruction argument, for the provenance metadata;
+    // without it the fields carry none
+    fn struct_fields(&self, instruction: Option<&str>) -> Option<Fields> {
+        if !self.error_column
+            && !self.latency_column
+            && self.consistency.is_none()
+            && !self.model_column
+            && !self.provenance
+        {
+            return None;
+        }
+        let name = if self.error_column { "value" } else { "answer" };
+        let mut answer = Field::new(name, self.output.data_type().clone(), true);
+        if let Some(instruction) = instruction.filter(|_| self.provenance) {
+            answer = answer.with_metadata(self.provenance_metadata(instruction));
+        }
+        let mut fields = vec![answer];
+        if self.error_column {
+            fields.push(Field::new("error", DataType::Utf8, true));
+        }
+        if self.latency_column {
+            fields.push(Field::new("latency_ms", DataType::Float64, true));
+        }
+        if self.consistency.is_some() {
+            fields.push(Field::new("confidence", DataType::Float64, true));
+        }
+        if self.model_column {
+            fields.push(Field::new("model", DataType::Utf8, true));
+            fields.push(Field::new("endpoint", DataType::Utf8, true));
+        }
+        Some(Fields::from(fields))
+    }
+
+    fn output_type(&self, args: &[DataType], instruction: Option<&str>) -> Result<DataType> {
+        if !matches!(args.first(), Some(&DataType::Utf8)) {
+            return plan_err!("ask_llm only accepts Utf8 arguments");
+        }
+        if let Some(fields) = self.struct_fields(instruction) {
+            return Ok(DataType::Struct(fields));
+        }
+        Ok(self.output.data_type().clone())
+    }
+
+    // replayed responses would make every sample agree
+    fn response_cache(&self) -> Option<&Arc<ShardedCache<[u8; 32], ChatResponse>>> {
+        self.response_cache
+            .as_ref()
+            .filter(|_| self.consistency.is_none())
+    }
+
+    /// Splits a chunk into smaller chunks when its estimated prompt is longer than
+    /// `max_prompt_chars` characters, so a few long rows cannot overflow the model's
+    /// context. Rows are kept in order; a single row over the limit is still sent on
+    /// its own, see [`AiConfig::with_windowing`] for splitting long values.
+    ///
+    /// The prompt is counted as the system prompt, the instruction with its prefix,
+    /// suffix and few-shot examples, and one `n. value` line per row. With
+    /// [`AskLLM::with_chat_template`] the template's special tokens count too, see
+    /// [`get_prompt_overhead`]; the rows fill whatever the rest leaves, so a long
+    /// system prompt means smaller chunks.
+    pub fn with_max_prompt_chars(mut self, max_prompt_chars: usize) -> Self {
+        self.prompt_limit = Some(PromptLimit::chars(max_prompt_chars));
+        self
+    }
+
+    /// Like [`AskLLM::with_max_prompt_chars`], but measures the prompt with `estimator`,
+    /// e.g. [`LengthEstimator::llama`] or a tokenizer, so the limit is in tokens.
+    pub fn with_max_prompt_tokens(mut self, max_tokens: usize, estimator: LengthEstimator) -> Self {
+        self.prompt_limit = Some(PromptLimit {
+            max_len: max_tokens.max(1),
+            estimator,
+        });
+        self
+    }
+
+    /// Counts the special tokens of `chat_template` towards the prompt limit. Ollama
+    /// applies the model's template on the server, so by default only the message
+    /// contents count; [`crate::register_ai_udfs`] sets the template of the model
+    /// profile.
+    pub fn with_chat_template(mut self, chat_template: ChatTemplate) -> Self {
+        self.chat_template = Some(chat_template);
+        self
+    }
+
+    // the estimated length of the part of every prompt for `instruction` that does
+    // not depend on the rows
+    fn prompt_overhead(&self, instruction: &str, estimator: &LengthEstimator) -> usize {
+        let instruction = format!(
+            "{}{}{}{}",
+            self.instruction_prefix, instruction, self.instruction_suffix, self.instruction_hint
+        );
+        match self.chat_template {
+            Some(template) => estimator.estimate(&get_prompt_overhead(
+                template,
+                &self.system_prompt,
+                &instruction,
+            )),
+            None => {
+                estimator.estimate(&self.system_prompt)
+                    + estimator.estimate(&format!("{instruction}:"))
+            }
+        }
+    }
+
+    // greedily cuts a batch into consecutive sub-batches whose prompt fits the limit;
+    // each part is estimated the way `format_content` renders it
+    fn subdivide<'b, 'a>(
+        &self,
+        instruction: &str,
+        batch: &'b [Option<&'a str>],
+    ) -> Vec<&'b [Option<&'a str>]> {
+        let Some(PromptLimit { max_len, estimator }) = &self.prompt_limit else {
+            return vec![batch];
+        };
+        let fixed = self.prompt_overhead(instruction, estimator);
+        let mut parts = Vec::new();
+        let (mut start, mut len) = (0, fixed);
+        for (i, value) in batch.iter().enumerate() {
+            let row = |number: usize| estimator.estimate_row(number, value.unwrap_or_default());
+            if i > start && len + row(i - start + 1) > *max_len {
+                parts.push(&batch[start..i]);
+                (start, len) = (i, fixed);
+            }
+            len += row(i - start + 1);
+        }
+        parts.push(&batch[start..]);
+        if parts.len() > 1 {
+            println!(
+                "chunk of {} rows exceeds the prompt limit of {} ({:?}), subdivided into {} chunks",
+                batch.len(),
+                max_len,
+                estimator,
+                parts.len()
+            );
+        }
+        parts
+    }
+
+    /// Remembers the raw backend response for every chunk that was fully answered,
+    /// keyed on a SHA-256 of the model, system prompt, instruction, the ordered chunk
+    /// values and the generation settings (temperature, Ollama options, seed, thinking
+    /// and response parsing). When the exact same chunk is requested again, e.g. by a repeated
+    /// dashboard query, the response is replayed without a round-trip. Unlike
+    /// [`AskLLM::with_answer_cache`] this only helps when chunk boundaries are stable.
+    pub fn with_response_cache(mut self) -> Self {
+        let workers = self.concurrent_prompts;
+        self.response_cache = Some(Arc::new(ShardedCache::for_workers(workers)));
+        self
+    }
+
+    // identifies a request by everything that shapes its response; every part is
+    // length-prefixed so that different splits of the same text hash differently
+    fn response_key(
+        &self,
+        model: &str,
+        instruction: &str,
+        first_row: usize,
+        vals: &[String],
+    ) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        let first_row = first_row.to_string();
+        let settings = self.generation_settings();
+        let parts = [
+            model,
+            self.system_prompt.as_str(),
+            settings.as_str(),
+            instruction,
+            first_row.as_str(),
+        ];
+        for part in parts.into_iter().chain(vals.iter().map(String::as_str)) {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+        hasher.finalize().into()
+    }
+
+    // the settings besides the prompt that shape a response, so cached responses are
+    // only replayed to a UDF that would have made the same request
+    fn generation_settings(&self) -> String {
+        json!({
+            "temperature": self.temperature,
+            "options": self.ollama_options,
+            "seed": self.seed,
+            "think": self.thinking,
+            "parsing": format!("{:?}", self.response_parsing),
+            "format": self.response_format,
+        })
+        .to_string()
+    }
+
+    /// Selects how answers are extracted from the model output. Defaults to
+    /// [`ResponseParsing::Arrow`].
+    pub fn with_response_parsing(mut self, response_parsing: ResponseParsing) -> Self {
+        self.response_parsing = response_parsing;
+        self
+    }
+
+    /// Asks for exactly one `i: answer` line per row and matches the answers to their
+    /// rows by index rather than by position, see [`ResponseParsing::Indexed`]: replaces
+    /// the default [`ASK_LLM_SYSTEM_PROMPT`] with [`ASK_LLM_INDEXED_SYSTEM_PROMPT`] and
+    /// adds "Return exactly N lines. Line i must be 'i: answer' ..." with the chunk's
+    /// row numbers to every request. A prompt set with [`AskLLM::with_system_prompt`],
+    /// before or after this call, is kept. As the format is always spelled out, the
+    /// format re-prompt repeats the same request.
+    pub fn with_indexed_answers(mut self) -> Self {
+        self.response_parsing = ResponseParsing::Indexed;
+        if self.system_prompt == ASK_LLM_SYSTEM_PROMPT {
+            self.system_prompt = ASK_LLM_INDEXED_SYSTEM_PROMPT.to_string();
+        }
+        self
+    }
+
+    /// Replaces the parser of [`ResponseParsing::Arrow`], [`ArrowParser`] by default,
+    /// e.g. for a model whose output needs custom extraction. Count matching,
+    /// truncation handling and the format re-prompt work on the parser's output.
+    pub fn with_response_parser(mut self, response_parser: impl ResponseParser + 'static) -> Self {
+        self.response_parser = Arc::new(response_parser);
+        self.response_parsing = ResponseParsing::Arrow;
+        self
+    }
+
+    // sends `format` as Ollama's structured output format with every chunk request, for
+    // ask_llm_json; servers that reject it are asked again without it, so the response
+    // parser must also read the line format
+    pub(crate) fn with_response_format(mut self, format: Value) -> Self {
+        self.response_format = Some(format);
+        self
+    }
+
+    /// When a response contains no parsable `N -> value` line at all, the rows are
+    /// requested once more with a stricter format reminder appended to the instruction
+    /// ("Output exactly N lines, each formatted as 'N -> value'"). Enabled by default.
+    pub fn with_format_reprompt(mut self, format_reprompt: bool) -> Self {
+        self.format_reprompt = format_reprompt;
+        self
+    }
+
+    /// Emits NULL without asking the model for rows that `blank_input` considers
+    /// missing. Defaults to [`BlankInput::Keep`].
+    pub fn with_blank_input(mut self, blank_input: BlankInput) -> Self {
+        self.blank_input = blank_input;
+        self
+    }
+
+    /// Flags inputs that look non-textual (e.g. base64 or binary blobs) before they
+    /// are sent, and either warns about them or answers them with NULL, see
+    /// [`InputGuard`]. Disabled by default.
+    pub fn with_input_guard(mut self, input_guard: InputGuard) -> Self {
+        self.input_guard = Some(input_guard);
+        self
+    }
+
+    /// Sets a cheap per-row rule that runs before the model. Rows for which it returns
+    /// `Some(answer)` use that answer and are never sent to the model; only rows it
+    /// returns `None` for are batched into prompts. NULL rows are not passed to it.
+    pub fn with_pre_filter(
+        mut self,
+        pre_filter: impl Fn(&str) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.pre_filter = Some(PreFilter(Arc::new(pre_filter)));
+        self
+    }
+
+    /// Sends all rows of an invocation in a single prompt when there are at most
+    /// `max_rows` of them and the prompt fits the prompt limit (see
+    /// [`AskLLM::with_max_prompt_chars`]), instead of splitting them into chunks of
+    /// `items_per_prompt` spread over the thread pool. Rows answered without the model
+    /// (blank, cached or pre-filtered) do not count. For small tables one request
+    /// avoids the per-chunk overhead; since a count mismatch then fails every row,
+    /// keep `max_rows` to lists the model answers reliably.
+    pub fn with_single_request_threshold(mut self, max_rows: usize) -> Self {
+        self.single_request_rows = Some(max_rows);
+        self
+    }
+
+    // whether the rows go to the model in one request, see with_single_request_threshold
+    fn fits_single_request(&self, instruction: &str, values: &[Option<&str>]) -> bool {
+        self.single_request_rows
+            .is_some_and(|max_rows| values.len() <= max_rows)
+            && self.subdivide(instruction, values).len() == 1
+    }
+
+    /// Lets the number of rows per prompt adapt to observed latency and count
+    /// mismatches within the given bounds, instead of using `items_per_prompt`.
+    /// See [`BatchTuner`] for the rules.
+    pub fn with_auto_tune(mut self, config: AutoTuneConfig) -> Self {
+        self.auto_tune = Some(BatchTuner::new(config));
+        self
+    }
+
+    /// Sets the type answers are parsed into, e.g. `Int64` or `Boolean`. Any type
+    /// Arrow can cast `Utf8` to is supported; answers that fail to parse become NULL.
+    /// Defaults to `Utf8`, which returns the answers unchanged.
+    pub fn with_output_type(mut self, output_type: DataType) -> Self {
+        self.output = OutputBuilder::cast(output_type);
+        self
+    }
+
+    /// Builds the output column with `output`, e.g. [`OutputBuilder::decimal`] to
+    /// read prices or [`OutputBuilder::new`] for a custom parser. The return type is
+    /// the builder's type. Replaces [`AskLLM::with_output_type`].
+    pub fn with_output_builder(mut self, output: OutputBuilder) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Reads instructions given as `file://path` or `@path` from files under
+    /// `base_dir`, so long prompts can live outside the query. Off by default, since
+    /// any SQL caller could otherwise read files on the server; while off, such
+    /// instructions are sent as they are. Relative paths are resolved against
+    /// `base_dir`, and paths leading outside it (through `..` or a symlink) fail.
+    pub fn with_instruction_files(mut self, base_dir: impl Into<PathBuf>) -> Self {
+        self.instruction_files = Some(base_dir.into());
+        self
+    }
+
+    /// Registers the instruction used for rows whose `task` argument equals `task`.
+    pub fn with_task_template(
+        mut self,
+        task: impl Into<String>,
+        instruction: impl Into<String>,
+    ) -> Self {
+        self.task_templates.insert(task.into(), instruction.into());
+        self
+    }
+
+    /// Sets how many times a failed chunk request is retried. Requests that would fail
+    /// the same way again, such as a rejected request or an undecodable response, are
+    /// not retried, see [`crate::ollama_utils::classify_error`].
+    pub fn with_max_chunk_retries(mut self, max_chunk_retries: usize) -> Self {
+        self.max_chunk_retries = max_chunk_retries;
+        self
+    }
+
+    /// Caps the total number of retries across all chunks of one invocation.
+    pub fn with_retry_budget(mut self, retry_budget: usize) -> Self {
+        self.retry_budget = Some(retry_budget);
+        self
+    }
+
+    /// Sets how many rows are sent to the model in a single prompt.
+    pub fn with_items_per_prompt(mut self, items_per_prompt: usize) -> Self {
+        self.items_per_prompt = items_per_prompt.max(1);
+        self
+    }
+
+    /// Sets how many prompts run in parallel, overriding the Ollama default.
+    /// See [`Backend`] for the defaults and limits.
+    pub fn with_max_concurrent_prompts(mut self, max_concurrent_prompts: usize) -> Self {
+        self.concurrent_prompts = Backend::Ollama.resolve_concurrency(Some(max_concurrent_prompts));
+        if let Some(cache) = &self.answer_cache {
+            let cache = cache.resized_for_workers(self.concurrent_prompts);
+            self.answer_cache = Some(Arc::new(cache));
+        }
+        self
+    }
+
+    // built on first use, so the builders can change the concurrency without building
+    // a pool that is thrown away
+    fn thread_pool(&self) -> &rayon::ThreadPool {
+        self.thread_pool.get_or_init(|| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(self.concurrent_prompts)
+                .build()
+                .expect("Failed to create rayon thread pool")
+        })
+    }
+
+    // the runtime the chunks are driven by and the HTTP client they send with: those of
+    // the shared backend, or a pair this UDF builds on first use and keeps, so pooled
+    // connections stay with the runtime that drives them and are reused across chunks
+    fn backend(&self) -> &SharedBackend {
+        match &self.shared_backend {
+            Some(backend) => backend,
+            None => self.own_backend.get_or_init(|| {
+                SharedBackend::with_settings(self.runtime, &self.http)
+                    .expect("Failed to create the ask_llm backend")
+            }),
+        }
+    }
+
+    /// Registers the function under `name` instead of `ask_llm`, so several differently
+    /// configured instances (e.g. `ask_llm_fast` and `ask_llm_accurate`) can share a context.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Merges `options` into the `options` object of every chat request, see
+    /// [`OllamaApp::with_options`].
+    pub fn with_ollama_options(mut self, options: Map<String, Value>) -> Self {
+        self.ollama_options = options;
+        self
+    }
+
+    /// Sends `seed` as Ollama's `options.seed`, so answers are reproducible at a
+    /// non-zero temperature; a `seed` in [`AskLLM::with_ollama_options`] works the
+    /// same. Only the first request of a chunk uses the seed itself: a retry would
+    /// otherwise sample the same failing output again, so each retry uses another seed
+    /// derived from it, see [`attempt_seed`]. A run stays reproducible as long as the
+    /// same chunks fail. Without a seed the server samples randomly anyway.
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Whether retries vary the seed, see [`AskLLM::with_seed`]. On by default; turn it
+    /// off to retry only transient backend errors with the exact same request.
+    pub fn with_seed_variation(mut self, seed_variation: bool) -> Self {
+        self.seed_variation = seed_variation;
+        self
+    }
+
+    // the options of a chunk's `attempt`, with the seed varied on retries
+    fn attempt_options(&self, attempt: usize) -> Map<String, Value> {
+        let mut options = self.ollama_options.clone();
+        let seed = self.seed.or_else(|| {
+            options
+                .get("seed")
+                .and_then(Value::as_u64)
+                .and_then(|seed| u32::try_from(seed).ok())
+        });
+        if let Some(seed) = seed {
+            let seed = if self.seed_variation {
+                attempt_seed(seed, attempt)
+            } else {
+                seed
+            };
+            options.insert("seed".to_string(), seed.into());
+        }
+        options
+    }
+
+    /// Sends a chunk that still fails after its retries once more to `model` on the
+    /// Ollama server at `host`, e.g. a smaller model or a second machine, with the same
+    /// prompt and parsing. Only backend errors fall back; a chunk with a mismatched
+    /// answer count was answered. [`AskLLM::with_model_column`] shows which model
+    /// answered each row.
+    pub fn with_fallback(mut self, model: impl Into<String>, host: &str) -> Self {
+        let model: String = model.into();
+        self.fallback = Some(Fallback {
+            model: model.into(),
+            endpoints: EndpointPool::new([format!("{}/api/chat", host.trim_end_matches('/'))]),
+        });
+        self
+    }
+
+    /// Sends the rows selected by `routing` to `premium`, typically a larger and more
+    /// expensive model, and the other rows to this UDF's model. `premium`'s model,
+    /// hosts, system prompt and chunking apply to its rows; the output type and
+    /// columns are this UDF's. Unlike [`AskLLM::with_fallback`], which only kicks in
+    /// after failures, routing decides per row up front. [`AskLLM::with_model_column`]
+    /// shows which model answered each row. See [`Routing`] for the policies.
+    pub fn with_routing(mut self, premium: AskLLM, routing: Routing) -> Self {
+        self.routed = Some(Routed {
+            premium: Arc::new(premium),
+            routing,
+        });
+        self
+    }
+
+    /// Selects how chunk requests are spread over the Ollama servers of
+    /// [`AiConfig::ollama_hosts`], round-robin by default.
+    pub fn with_load_balancing(mut self, load_balancing: LoadBalancing) -> Self {
+        self.endpoints = self.endpoints.with_strategy(load_balancing);
+        self
+    }
+
+    /// Returns NULL for rows whose answer is one of `sentinels` (compared trimmed and
+    /// ignoring case), so placeholder text a backend emits in place of an answer is not
+    /// mistaken for data. Defaults to [`DEFAULT_NULL_SENTINELS`], the placeholders this
+    /// crate produces; an empty list turns it off.
+    pub fn with_null_sentinels(
+        mut self,
+        sentinels: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.null_sentinels = sentinels.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Normalizes the casing and whitespace of every parsed answer, before
+    /// [`AskLLM::with_post_process`] and the cast to the output type, e.g.
+    /// [`Normalization::categorical`] to collapse `Positive` and `POSITIVE` into one label.
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = Some(normalization);
+        self
+    }
+
+    /// Fails the whole query with an execution error when a chunk fails after its
+    /// retries (a backend error, a panic or a count mismatch), instead of answering
+    /// its rows with an `Error ...` string. Chunks not yet started when the first one
+    /// fails are skipped. Off by default, which keeps the remaining rows' answers.
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// After all chunks of an invocation are answered, gives every occurrence of an
+    /// input value the answer most of its occurrences received (the earliest one on a
+    /// tie), so identical inputs answered in different chunks get the same label.
+    /// Unlike [`AskLLM::with_answer_cache`], which avoids sending a repeated value
+    /// twice, this reconciles answers that were already computed. Rows with different
+    /// task instructions are reconciled separately. Off by default.
+    pub fn with_duplicate_reconciliation(mut self, reconcile_duplicates: bool) -> Self {
+        self.reconcile_duplicates = reconcile_duplicates;
+        self
+    }
+
+    fn reconciled(&self, values: &[Option<&str>], mut answers: Vec<RowAnswer>) -> Vec<RowAnswer> {
+        if self.reconcile_duplicates {
+            let changed = reconcile_duplicates(values, &mut answers);
+            if changed > 0 {
+                println!(
+                    "ask_llm: reconciled {} answers of duplicate inputs",
+                    changed
+                );
+            }
+        }
+        answers
+    }
+
+    // answers every column as its own task with `instruction`, for ask_llm_columns. The
+    // columns are evaluated concurrently on this UDF's pool, so their chunks share the
+    // concurrency, client, caches and retry budget of one invocation
+    pub(crate) fn answer_columns(
+        &self,
+        instruction: &str,
+        columns: &[Vec<Option<&str>>],
+    ) -> Result<Vec<ArrayRef>> {
+        self.answer_rows(instruction, columns)?
+            .into_iter()
+            .map(|answers| self.output.build(answers))
+            .collect()
+    }
+
+    // answers `values` like a single column of answer_columns, with the detail of every
+    // row (see AskLLM::with_detail_split) next to its answer
+    pub(crate) fn answer_with_details(
+        &self,
+        instruction: &str,
+        values: Vec<Option<&str>>,
+    ) -> Result<(ArrayRef, Vec<Option<String>>)> {
+        let answers = self.answer_rows(instruction, &[values])?.pop();
+        let (answers, details): (Vec<_>, Vec<_>) = answers
+            .unwrap_or_default()
+            .into_iter()
+            .map(split_detail)
+            .unzip();
+        Ok((self.output.build(answers)?, details))
+    }
+
+    fn answer_rows(
+        &self,
+        instruction: &str,
+        columns: &[Vec<Option<&str>>],
+    ) -> Result<Vec<Vec<Option<String>>>> {
+        let instruction = load_instruction(instruction, self.instruction_files.as_deref())?;
+        let instruction = match &self.few_shot {
+            Some(few_shot) => Cow::Owned(render_examples(&instruction, &few_shot.examples()?)),
+            None => instruction,
+        };
+        let invocation = Invocation::new(self.retry_budget);
+        let answers: Vec<Vec<RowAnswer>> = self.thread_pool().install(|| {
+            columns
+                .par_iter()
+                .map(|values| {
+                    let answers = self.evaluate(&instruction, values, &invocation)?;
+                    Ok(self.reconciled(values, answers))
+                })
+                .collect::<Result<_>>()
+        })?;
+        if let Some(error) = invocation.failure.into_inner().unwrap() {
+            return Err(DataFusionError::Execution(format!(
+                "ask_llm failed: {}",
+                error
+            )));
+        }
+        Ok(answers
+            .into_iter()
+            .map(|rows| rows.into_iter().map(|row| row.answer).collect())
+            .collect())
+    }
+
+    /// The type of each answer, see [`AskLLM::with_output_builder`].
+    pub fn answer_type(&self) -> &DataType {
+        self.output.data_type()
+    }
+
+    /// Sets how the function advertises itself to the planner, [`Volatility::Volatile`]
+    /// by default: answers come from a network call and sampling, so two calls with the
+    /// same arguments may differ and the planner must not fold, deduplicate or reorder
+    /// them. An LLM-derived column can still be used in GROUP BY and ORDER BY, as the
+    /// expression is evaluated once per row and its output is then grouped or sorted,
+    /// but repeated queries may group or order the rows differently.
+    ///
+    /// [`Volatility::Immutable`] lets the optimizer treat `ask_llm` like a pure function,
+    /// e.g. evaluating it once for constant arguments or sharing one evaluation between
+    /// identical expressions. Only use it with a deterministic setup, such as a zero
+    /// temperature or [`AskLLM::with_answer_cache`].
+    pub fn with_volatility(mut self, volatility: Volatility) -> Self {
+        self.signature = Signature::user_defined(volatility);
+        self
+    }
+
+    /// Replaces the system prompt, [`ASK_LLM_SYSTEM_PROMPT`] by default. The prompt is sent
+    /// as a system message, which Ollama uses instead of the `SYSTEM` prompt of the
+    /// model's Modelfile, so a model that sets its own prompt there (such as a custom
+    /// `llama32-df` build) no longer gets it by default. An empty prompt sends no system
+    /// message, so the model's own system prompt applies.
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.system_prompt = system_prompt.into();
+        self
+    }
+
+    /// Returns a handle that can be used to stop this UDF gracefully.
+    /// See [`Cancellation`] for the guarantees given to in-flight chunks.
+    pub fn cancellation(&self) -> Cancellation {
+        self.cancellation.clone()
+    }
+
+    /// Shares `cancellation` with the UDF, so one handle stops several UDFs, e.g. all
+    /// the ones registered by [`crate::register_ai_udfs`].
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    // missing rows and rows resolved by the pre-filter are answered directly,
+    // the rest go to the model
+    fn evaluate(
+        &self,
+        instruction: &str,
+        values: &[Option<&str>],
+        invocation: &Invocation,
+    ) -> Result<Vec<RowAnswer>> {
+        if let Some(dry_run) = &self.dry_run {
+            dry_run.record(self.dry_run_plan(instruction, values));
+            return Ok(vec![RowAnswer::default(); values.len()]);
+        }
+        if log::log_enabled!(log::Level::Debug) {
+            log::debug!("{}", self.plan_summary(instruction, values));
+        }
+        if let Some(progress) = &self.progress {
+            progress.add_received(values.len());
+        }
+        let non_textual: Vec<bool> = match &self.input_guard {
+            Some(guard) => values
+                .iter()
+                .map(|value| value.is_some_and(|value| guard.looks_non_textual(value)))
+                .collect(),
+            None => vec![false; values.len()],
+        };
+        let skip_non_textual = self
+            .input_guard
+            .is_some_and(|guard| guard.action == GuardAction::Skip);
+        let flagged = non_textual.iter().filter(|&&flagged| flagged).count();
+        if flagged > 0 {
+            log::warn!(
+                "{} of {} ask_llm inputs look non-textual{}",
+                flagged,
+                values.len(),
+                if skip_non_textual {
+                    " and are returned as NULL"
+                } else {
+                    ""
+                }
+            );
+        }
+        if self.pre_filter.is_none()
+            && self.blank_input == BlankInput::Keep
+            && !(skip_non_textual && flagged > 0)
+        {
+            return self.evaluate_routed(instruction, values, invocation);
+        }
+        let mut scatter = Scatter::new(values.len());
+        let mut unresolved: Vec<usize> = Vec::new();
+        for (i, &value) in values.iter().enumerate() {
+            if self.blank_input.is_missing(value) || (skip_non_textual && non_textual[i]) {
+                scatter.fill_one(i, RowAnswer::local(None))?;
+                continue;
+            }
+            let pre_filtered = self.pre_filter.as_ref().zip(value);
+            match pre_filtered.and_then(|(pre_filter, value)| (pre_filter.0)(value)) {
+                Some(answer) => scatter.fill_one(i, RowAnswer::local(Some(answer)))?,
+                None => unresolved.push(i),
+            }
+        }
+        if let Some(progress) = &self.progress {
+            progress.add_processed(values.len() - unresolved.len());
+        }
+        let unresolved_values: Vec<Option<&str>> = unresolved.iter().map(|&i| values[i]).collect();
+        let answers = self.evaluate_routed(instruction, &unresolved_values, invocation)?;
+        scatter.fill(&unresolved, answers)?;
+        scatter.finish()
+    }
+
+    // splits the rows between this UDF's model and the premium model of with_routing
+    fn evaluate_routed(
+        &self,
+        instruction: &str,
+        values: &[Option<&str>],
+        invocation: &Invocation,
+    ) -> Result<Vec<RowAnswer>> {
+        let Some(Routed { premium, routing }) = &self.routed else {
+            return Ok(self.evaluate_batches(instruction, values, invocation));
+        };
+        let (routed, kept): (Vec<usize>, Vec<usize>) =
+            (0..values.len()).partition(|&i| values[i].is_some_and(|value| routing.routes(value)));
+        let routed_values: Vec<Option<&str>> = routed.iter().map(|&i| values[i]).collect();
+        let kept_values: Vec<Option<&str>> = kept.iter().map(|&i| values[i]).collect();
+        let mut scatter = Scatter::new(values.len());
+        scatter.fill(
+            &routed,
+            premium.evaluate_batches(instruction, &routed_values, invocation),
+        )?;
+        // the premium UDF reports to its own progress log, if any
+        if let Some(progress) = &self.progress {
+            progress.add_processed(routed.len());
+        }
+        scatter.fill(
+            &kept,
+            self.evaluate_batches(instruction, &kept_values, invocation),
+        )?;
+        scatter.finish()
+    }
+
+    /// Estimates the backend calls and tokens needed to answer `values` with
+    /// `instruction`, without calling the model. Follows the configured input
+    /// handling (blank inputs, input guard, pre-filter), answer cache, chunk size
+    /// (or the current auto-tuned size), prompt limit and windowing. With the answer
+    /// cache, repeated values count as cache hits, which is optimistic when repeats
+    /// are evaluated concurrently.
+    pub fn dry_run_plan(&self, instruction: &str, values: &[Option<&str>]) -> CallEstimate {
+        let tokens = LengthEstimator::approx_tokens();
+        let skip_non_textual = self
+            .input_guard
+            .filter(|guard| guard.action == GuardAction::Skip);
+        let mut estimate = CallEstimate {
+            rows: values.len(),
+            ..CallEstimate::default()
+        };
+        let mut seen = HashSet::new();
+        let mut prompted: Vec<Option<&str>> = Vec::new();
+        for &value in values {
+            let skipped = skip_non_textual
+                .zip(value)
+                .is_some_and(|(guard, value)| guard.looks_non_textual(value));
+            let pre_filtered = self
+                .pre_filter
+                .as_ref()
+                .zip(value)
+                .is_some_and(|(pre_filter, value)| (pre_filter.0)(value).is_some());
+            if self.blank_input.is_missing(value) || skipped || pre_filtered {
+                estimate.local_rows += 1;
+                continue;
+            }
+            if let (Some(cache), Some(value)) = (&self.answer_cache, value)
+                && (!seen.insert(value)
+                    || cache
+                        .get(&(instruction.to_string(), value.to_string()))
+                        .is_some())
+            {
+                estimate.cached_rows += 1;
+                continue;
+            }
+            prompted.push(value);
+        }
+        estimate.prompted_rows = prompted.len();
+        let samples = self
+            .consistency
+            .map_or(1, |consistency| consistency.samples);
+        estimate.completion_tokens = prompted.len() * COMPLETION_TOKENS_PER_ROW * samples;
+
+        let fixed = self.prompt_overhead(instruction, &tokens);
+        let size = if self.fits_single_request(instruction, &prompted) {
+            prompted.len().max(1)
+        } else {
+            self.auto_tune
+                .as_ref()
+                .map_or(self.items_per_prompt, BatchTuner::current)
+        };
+        for batch in prompted.chunks(size) {
+            for batch in self.subdivide(instruction, batch) {
+                // oversized values are sent as a prompt of their own, see `process_values`
+                let (oversized, regular): (Vec<&str>, Vec<&str>) = batch
+                    .iter()
+                    .map(|value| value.unwrap_or_default())
+                    .partition(|value| {
+                        self.windowing
+                            .as_ref()
+                            .is_some_and(|windowing| windowing.is_oversized(value))
+                    });
+                if !regular.is_empty() {
+                    estimate.calls += 1;
+                    estimate.prompt_tokens += fixed;
+                }
+                estimate.calls += oversized.len();
+                estimate.prompt_tokens += oversized.len() * fixed;
+                estimate.prompt_tokens += regular
+                    .iter()
+                    .chain(&oversized)
+                    .map(|value| tokens.estimate(value) + 2)
+                    .sum::<usize>();
+            }
+        }
+        estimate.calls *= samples;
+        estimate.prompt_tokens *= samples;
+        estimate
+    }
+
+    /// Summarizes the effective settings and how `values` will be processed with
+    /// `instruction`: distinct values, chunk size and concurrency, and the
+    /// [`AskLLM::dry_run_plan`] estimate of rows answered locally or from the cache and
+    /// of the backend calls.
+    ///
+    /// The summary is logged at debug level before each batch of rows is evaluated,
+    /// to see what the layered settings amount to; unlike [`AskLLM::with_dry_run`] the
+    /// rows are still evaluated. It is only computed when debug logging is enabled.
+    pub fn plan_summary(&self, instruction: &str, values: &[Option<&str>]) -> String {
+        let present: Vec<&str> = values.iter().flatten().copied().collect();
+        let distinct = present.iter().collect::<HashSet<_>>().len();
+        let duplicates = match present.len() {
+            0 => 0.0,
+            n => 100.0 * (n - distinct) as f64 / n as f64,
+        };
+        let chunk_size = match &self.auto_tune {
+            Some(tuner) => format!("{} rows per prompt (auto-tuned)", tuner.current()),
+            None => format!("{} rows per prompt", self.items_per_prompt),
+        };
+        let mut summary = format!(
+            "ask_llm plan: model {} on {} endpoint(s), {}, {} concurrent prompts, \
+             answer cache {}; {} distinct of {} values ({:.0}% duplicates)",
+            self.ollama_model,
+            self.endpoints.len(),
+            chunk_size,
+            self.concurrent_prompts,
+            if self.answer_cache.is_some() {
+                "on"
+            } else {
+                "off"
+            },
+            distinct,
+            present.len(),
+            duplicates
+        );
+        if let Some(fallback) = &self.fallback {
+            summary.push_str(&format!("; falls back to {}", fallback.model));
+        }
+        if let Some(routed) = &self.routed {
+            summary.push_str(&format!("; routes to {}", routed.premium.ollama_model));
+        }
+        summary.push_str(&format!(
+            "; estimate: {}",
+            self.dry_run_plan(instruction, values)
+        ));
+        summary
+    }
+
+    /// Sets how the tokio runtime driving the chunks is built. It is built on first use
+    /// and kept for the lifetime of this UDF, together with the HTTP client whose
+    /// pooled connections it drives. For the I/O-bound chunk requests
+    /// [`RuntimeConfig::current_thread`] is recommended, see [`RuntimeConfig`].
+    pub fn with_runtime(mut self, runtime: RuntimeConfig) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Sends requests through the client and runtime of `backend` and uses its response
+    /// cache, so they outlive this UDF and the query, see [`SharedBackend`]. The
+    /// runtime replaces the one set with [`AskLLM::with_runtime`].
+    pub fn with_shared_backend(mut self, backend: Arc<SharedBackend>) -> Self {
+        self.response_cache = Some(backend.response_cache());
+        self.shared_backend = Some(backend);
+        self
+    }
+
+    /// Logs a progress summary (processed rows, cache hits and retries) every
+    /// `interval` processed rows, see [`ProgressLog`]. Off by default.
+    pub fn with_progress_log(mut self, interval: usize) -> Self {
+        self.progress = Some(ProgressLog::new(interval));
+        self
+    }
+
+    /// Puts the function in dry-run mode: invocations record their [`CallEstimate`]
+    /// in `dry_run` and return NULLs without calling the model, so a query can be
+    /// run once to see what it would cost.
+    pub fn with_dry_run(mut self, dry_run: DryRun) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    // rows are first grouped into prompt-sized batches, which are then
+    // spread over this UDF's rayon pool (sized by max_concurrent_prompts), unless
+    // they are few enough to be sent at once
+    fn evaluate_batches(
+        &self,
+        instruction: &str,
+        values: &[Option<&str>],
+        invocation: &Invocation,
+    ) -> Vec<RowAnswer> {
+        if !values.is_empty() && self.fits_single_request(instruction, values) {
+            // on the pool like every other chunk, since the calling thread may be a
+            // tokio worker, where run_prompt cannot block on a runtime
+            let answers = self
+                .thread_pool()
+                .install(|| self.run_prompt_catching_panics(instruction, values, 1, invocation));
+            if let Some(progress) = &self.progress {
+                progress.add_processed(values.len());
+            }
+            return answers;
+        }
+        let Some(tuner) = &self.auto_tune else {
+            let batches: Vec<&[Option<&str>]> = values
+                .chunks(self.items_per_prompt)
+                .flat_map(|batch| self.subdivide(instruction, batch))
+                .collect();
+            return self.run_batches(instruction, &batches, 1, invocation);
+        };
+        // with auto-tuning, rows are processed in rounds of one batch per worker so
+        // each round can use the size learned from the previous ones
+        let parallelism = self.concurrent_prompts;
+        let mut result = Vec::with_capacity(values.len());
+        let mut remaining = values;
+        let mut first_row = 1;
+        while !remaining.is_empty() {
+            let size = tuner.current();
+            let (round, rest) = remaining.split_at((size * parallelism).min(remaining.len()));
+            let batches: Vec<&[Option<&str>]> = round
+                .chunks(size)
+                .flat_map(|batch| self.subdivide(instruction, batch))
+                .collect();
+            result.extend(self.run_batches(instruction, &batches, first_row, invocation));
+            first_row += round.len();
+            remaining = rest;
+        }
+        result
+    }
+
+    // `first_row` is the number of the first row of `batches` in the invocation
+    fn run_batches(
+        &self,
+        instruction: &str,
+        batches: &[&[Option<&str>]],
+        first_row: usize,
+        invocation: &Invocation,
+    ) -> Vec<RowAnswer> {
+        // the rows are only numbered from 1 in every chunk without global row numbers
+        let first_rows: Vec<usize> = batches
+            .iter()
+            .scan(first_row, |next, batch| {
+                let first = *next;
+                *next += batch.len();
+                Some(if self.global_row_numbers { first } else { 1 })
+            })
+            .collect();
+        self.thread_pool().install(|| {
+            batches
+                .par_iter()
+                .zip(first_rows)
+                .flat_map(|(chunk, first_row)| {
+                    let answers =
+                        self.run_prompt_catching_panics(instruction, chunk, first_row, invocation);
+                    if let Some(progress) = &self.progress {
+                        progress.add_processed(chunk.len());
+                    }
+                    answers
+                })
+                .collect()
+        })
+    }
+
+    // a panic anywhere in a chunk (backend, post-processing, ...) only fails that chunk
+    // instead of unwinding through rayon and aborting the query. The panic hook has
+    // already printed the message and location, and the backtrace with RUST_BACKTRACE=1.
+    fn run_prompt_catching_panics(
+        &self,
+        instruction: &str,
+        chunk: &[Option<&str>],
+        first_row: usize,
+        invocation: &Invocation,
+    ) -> Vec<RowAnswer> {
+        let run = || self.run_cached(instruction, chunk, first_row, invocation);
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(run)).unwrap_or_else(|panic| {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            println!("chunk of {} rows panicked: {}", chunk.len(), message);
+            let error = RowError::Panic(message);
+            if self.fail_fast {
+                invocation.fail(error.to_string());
+            }
+            vec![RowAnswer::default().failed(error); chunk.len()]
+        })
+    }
+
+    // answers the rows found in the answer cache directly and runs the rest
+    fn run_cached(
+        &self,
+        instruction: &str,
+        chunk: &[Option<&str>],
+        first_row: usize,
+        invocation: &Invocation,
+    ) -> Vec<RowAnswer> {
+        let Some(cache) = &self.answer_cache else {
+            return self.run_prompt(instruction, chunk, first_row, invocation);
+        };
+        let key = |value: &str| (instruction.to_string(), value.to_string());
+        let mut answers: Vec<Option<RowAnswer>> = chunk
+            .iter()
+            .map(|value| {
+                value
+                    .and_then(|value| cache.get(&key(value)))
+                    .map(|answer| RowAnswer::local(Some(answer)))
+            })
+            .collect();
+        let misses: Vec<usize> = (0..chunk.len()).filter(|&i| answers[i].is_none()).collect();
+        let hits = chunk.len() - misses.len();
+        if hits > 0 {
+            self.record_cache_hits(hits);
+        }
+        if !misses.is_empty() {
+            let missed: Vec<Option<&str>> = misses.iter().map(|&i| chunk[i]).collect();
+            let fresh = self.run_prompt(instruction, &missed, first_row, invocation);
+            for (&i, row) in misses.iter().zip(fresh) {
+                // failed rows carry an error message instead of an answer and are not cached
+                if let (Some(value), Some(answer)) = (chunk[i], &row.answer)
+                    && row.error.is_none()
+                {
+                    cache.insert(key(value), answer.clone());
+                }
+                answers[i] = Some(row);
+            }
+        }
+        answers.into_iter().flatten().collect()
+    }
+
+    // runs a single prompt-sized batch of rows, called in parallel using rayon
+    fn run_prompt(
+        &self,
+        instruction: &str,
+        chunk: &[Option<&str>],
+        first_row: usize,
+        invocation: &Invocation,
+    ) -> Vec<RowAnswer> {
+        // once cancelled, chunks that have not started yet are skipped and emitted as NULL
+        if self.cancellation.is_cancelled() {
+            return vec![RowAnswer::local(None); chunk.len()];
+        }
+        // once a chunk has failed the query fails anyway, so the rest is not sent
+        if self.fail_fast && invocation.has_failed() {
+            return vec![RowAnswer::local(None); chunk.len()];
+        }
+        // first we extract the column values from the chunk
+        let vals: Vec<String> = chunk
+            .iter()
+            .map(|opt| opt.unwrap_or_default().to_string())
+            .collect();
+        let rt = self.backend().runtime();
+        let chunk_start = Instant::now();
+        let process = SERVED_BY.scope(RefCell::new(None), async {
+            CHUNK_USAGE
+                .scope(RefCell::new(ChunkUsage::default()), async {
+                    let outcome = match &self.consistency {
+                        Some(consistency) => {
+                            self.sample_values(
+                                consistency,
+                                instruction,
+                                &vals,
+                                first_row,
+                                &invocation.retry_budget,
+                            )
+                            .await
+                        }
+                        None => self
+                            .process_values(instruction, &vals, first_row, &invocation.retry_budget)
+                            .await
+                            .map(|records| (records, None)),
+                    };
+                    (
+                        outcome,
+                        SERVED_BY.with(RefCell::take),
+                        CHUNK_USAGE.with(RefCell::take),
+                    )
+                })
+                .await
+        });
+        let (outcome, served_by, usage) = match self.chunk_deadline {
+            Some(deadline) => {
+                match rt.block_on(async { tokio::time::timeout(deadline, process).await }) {
+                    Ok(outcome) => outcome,
+                    Err(_) => {
+                        invocation.timeouts.fetch_add(1, Ordering::SeqCst);
+                        return RowAnswer::timed(vec![None; vals.len()], deadline)
+                            .into_iter()
+                            .map(|row| row.failed(RowError::Timeout(deadline)))
+                            .collect();
+                    }
+                }
+            }
+            None => rt.block_on(process),
+        };
+        let (outcome, confidences) = match outcome {
+            Ok((records, confidences)) => (Ok(records), confidences),
+            Err(e) => (Err(e), None),
+        };
+        if let Some(tuner) = &self.auto_tune {
+            // with windowing or merged sub-chunks only some rows may carry the mismatch
+            let mismatched = matches!(&outcome, Ok(records) if records
+                .iter()
+                .flatten()
+                .any(|record| record.starts_with(MISMATCH_ERROR_PREFIX)));
+            tuner.observe(vals.len(), chunk_start.elapsed(), mismatched);
+        }
+        if self.fail_fast {
+            match &outcome {
+                Err(e) => invocation.fail(e.to_string()),
+                Ok(records) => {
+                    if let Some(mismatch) = records
+                        .iter()
+                        .flatten()
+                        .find(|record| record.starts_with(MISMATCH_ERROR_PREFIX))
+                    {
+                        invocation.fail(mismatch.clone());
+                    }
+                }
+            }
+        }
+        let latency = chunk_start.elapsed();
+        let mut rows = match outcome {
+            Ok(records) => RowAnswer::timed(records, latency)
+                .into_iter()
+                .map(|row| match &row.answer {
+                    Some(answer) if answer.starts_with(MISMATCH_ERROR_PREFIX) => {
+                        let error = RowError::CountMismatch(answer.clone());
+                        row.failed(error)
+                    }
+                    _ => row,
+                })
+                .collect(),
+            Err(e) => {
+                let error = RowError::Backend(e.to_string());
+                RowAnswer::timed(vec![None; vals.len()], latency)
+                    .into_iter()
+                    .map(|row| row.failed(error.clone()))
+                    .collect()
+            }
+        };
+        for (row, confidence) in rows.iter_mut().zip(confidences.unwrap_or_default()) {
+            row.confidence = confidence;
+        }
+        for row in &mut rows {
+            row.served_by = served_by.clone();
+        }
+        if let Some(cost_callback) = &self.cost_callback {
+            for cost in row_costs(cost_callback.pricing, usage, served_by, rows.len()) {
+                (cost_callback.callback)(&cost);
+            }
+        }
+        rows
+    }
+
+    // sends the chunk once per sample and votes on each row's answer; mismatched
+    // samples do not vote, and rows no sample answered keep the first sample's outcome
+    async fn sample_values(
+        &self,
+        consistency: &SelfConsistency,
+        instruction: &str,
+        vals: &[String],
+        first_row: usize,
+        retry_budget: &RetryBudget,
+    ) -> Result<(Vec<Option<String>>, Option<Vec<Option<f64>>>)> {
+        let mut samples = Vec::with_capacity(consistency.samples);
+        let mut error = None;
+        for _ in 0..consistency.samples {
+            match self
+                .process_values(instruction, vals, first_row, retry_budget)
+                .await
+            {
+                Ok(records) => samples.push(records),
+                Err(e) => {
+                    println!("self-consistency sample failed: {}", e);
+                    error = Some(e);
+                }
+            }
+        }
+        let Some(first) = samples.first().cloned() else {
+            return Err(error.expect("at least one sample is taken"));
+        };
+        let votes: Vec<Vec<Option<String>>> = samples
+            .into_iter()
+            .map(|records| {
+                records
+                    .into_iter()
+                    .map(|record| record.filter(|r| !r.starts_with(MISMATCH_ERROR_PREFIX)))
+                    .collect()
+            })
+            .collect();
+        let consistency = match &self.categories {
+            Some(gate) => consistency.with_min_confidence(
+                consistency
+                    .min_confidence
+                    .map_or(gate.min_agreement(), |min| min.max(gate.min_agreement())),
+            ),
+            None => *consistency,
+        };
+        let (answers, confidences) = consistency.vote(&votes, vals.len());
+        let answers = answers
+            .into_iter()
+            .zip(&confidences)
+            .zip(first)
+            .map(|((answer, confidence), first)| match confidence {
+                Some(_) => answer,
+                None => first,
+            })
+            .collect();
+        Ok((answers, Some(confidences)))
+    }
+
+    // processes a chunk of rows, sending oversized values (if windowing is enabled)
+    // as one prompt per value where each window is an item, and combining the answers
+    async fn process_values(
+        &self,
+        instruction: &str,
+        vals: &[String],
+        first_row: usize,
+        retry_budget: &RetryBudget,
+    ) -> Result<Vec<Option<String>>> {
+        let Some(windowing) = &self.windowing else {
+            return self
+                .process_chunk(instruction, vals, first_row, retry_budget)
+                .await;
+        };
+        let (oversized, regular): (Vec<usize>, Vec<usize>) =
+            (0..vals.len()).partition(|&i| windowing.is_oversized(&vals[i]));
+        if oversized.is_empty() {
+            return self
+                .process_chunk(instruction, vals, first_row, retry_budget)
+                .await;
+        }
+
+        let mut records_outcome = Scatter::new(vals.len());
+        // with global row numbers, every run of regular rows between oversized ones is
+        // a prompt of its own, so that its rows keep their numbers
+        let runs: Vec<&[usize]> = if self.global_row_numbers {
+            regular.chunk_by(|a, b| *b == a + 1).collect()
+        } else {
+            vec![&regular]
+        };
+        for run in runs {
+            let run_vals: Vec<String> = run.iter().map(|&i| vals[i].clone()).collect();
+            let run_first_row = match run.first() {
+                Some(first) if self.global_row_numbers => first_row + first,
+                _ => first_row,
+            };
+            let run_records = self
+                .process_chunk(instruction, &run_vals, run_first_row, retry_budget)
+                .await?;
+            records_outcome.fill(run, run_records)?;
+        }
+        for i in oversized {
+            let windows = windowing.split(&vals[i]);
+            let answers = self
+                .process_chunk(instruction, &windows, 1, retry_budget)
+                .await?;
+            let answers: Vec<String> = answers.into_iter().flatten().collect();
+            records_outcome.fill_one(i, Some(windowing.combine(answers)))?;
+        }
+        records_outcome.finish()
+    }
+
+    // sends a chunk to the backend, retrying failed requests while both the per-chunk
+    // limit and the invocation-wide retry budget allow it, then to the fallback model
+    async fn process_chunk(
+        &self,
+        instruction: &str,
+        vals: &[String],
+        first_row: usize,
+        retry_budget: &RetryBudget,
+    ) -> Result<Vec<Option<String>>> {
+        let mut attempt = 0;
+        loop {
+            match self
+                .request_chunk(instruction, vals, first_row, attempt)
+                .await
+            {
+                Ok(records) => return Ok(records),
+                Err(e)
+                    if !is_terminal(&e)
+                        && attempt < self.max_chunk_retries
+                        && retry_budget.try_acquire() =>
+                {
+                    attempt += 1;
+                    log::warn!("retrying chunk (attempt {}) after error: {}", attempt, e);
+                    telemetry::record_retry(&self.ollama_model);
+                    if let Some(progress) = &self.progress {
+                        progress.add_retry();
+                    }
+                }
+                Err(e) => {
+                    let Some(fallback) = &self.fallback else {
+                        return Err(e);
+                    };
+                    log::warn!(
+                        "sending chunk to fallback {} after error: {}",
+                        fallback.model,
+                        e
+                    );
+                    telemetry::record_fallback(&self.ollama_model, &fallback.model);
+                    return self
+                        .request_chunk_from(
+                            &fallback.model,
+                            &fallback.endpoints,
+                            instruction,
+                            vals,
+                            first_row,
+                            0,
+                        )
+                        .await;
+                }
+            }
         }
     }
 
-    // this function process a chunk of rows and will be called in parallel using rayon
-    async fn process_chunk(&self, instruction: &str, vals: &[String]) -> Result<Vec<String>> {
-        let mut records_outcome: Vec<String> = Vec::with_capacity(vals.len());
+    async fn request_chunk(
+        &self,
+        instruction: &str,
+        vals: &[String],
+        first_row: usize,
+        attempt: usize,
+    ) -> Result<Vec<Option<String>>> {
+        self.request_chunk_from(
+            &self.ollama_model,
+            &self.endpoints,
+            instruction,
+            vals,
+            first_row,
+            attempt,
+        )
+        .await
+    }
+
+    // this function process a chunk of rows and will be called in parallel using rayon;
+    // `attempt` counts the retries before this request
+    async fn request_chunk_from(
+        &self,
+        model: &Arc<str>,
+        endpoints: &EndpointPool,
+        instruction: &str,
+        vals: &[String],
+        first_row: usize,
+        attempt: usize,
+    ) -> Result<Vec<Option<String>>> {
+        let mut records_outcome: Vec<Option<String>> = Vec::with_capacity(vals.len());
         if vals.is_empty() {
             println!("vals is empty");
             return Ok(records_outcome);
         }
-        let ollama_app = OllamaApp::new(&self.ollama_model, &self.ollama_url)
-            .map_err(|e| DataFusionError::Internal(e.to_string()))?;
+        let endpoint = endpoints.acquire();
+        let mut ollama_app = OllamaApp::new(model, endpoint.url())
+            .map_err(|e| DataFusionError::Internal(e.to_string()))?
+            .with_system_prompt(self.system_prompt.as_str())
+            .with_temperature(self.temperature)
+            .with_options(self.attempt_options(attempt))
+            .with_thinking(self.thinking);
+        ollama_app = ollama_app.with_client(self.backend().client().clone());
+        if let Some(rate_limiter) = &self.rate_limiter {
+            ollama_app = ollama_app.with_rate_limiter(rate_limiter.clone());
+        }
+        let instruction = &format!(
+            "{}{}{}{}",
+            self.instruction_prefix, instruction, self.instruction_suffix, self.instruction_hint
+        );
 
-        let llm_response = ollama_app
-            .generate_text(instruction, vals)
-            .await
-            .map_err(|e| DataFusionError::Internal(e.to_string()))?;
+        let mut reprompted = false;
+        // rows still waiting for an answer; only shrinks in TruncationMode::KeepLeading
+        let mut remaining = vals;
+        while !remaining.is_empty() {
+            // rows kept with TruncationMode::KeepLeading are not sent again, and with
+            // global row numbers the rest keep their numbers
+            let first_row = if self.global_row_numbers {
+                first_row + vals.len() - remaining.len()
+            } else {
+                first_row
+            };
+            let reminder;
+            let instruction = if self.response_parsing == ResponseParsing::Indexed {
+                reminder = indexed_instruction(instruction, first_row, remaining.len());
+                &reminder
+            } else if reprompted {
+                reminder = format_reminder(instruction, remaining.len());
+                &reminder
+            } else {
+                instruction
+            };
+            let cache_key = self
+                .response_cache()
+                .map(|_| self.response_key(model, instruction, first_row, remaining));
+            let cached = self
+                .response_cache()
+                .zip(cache_key.as_ref())
+                .and_then(|(cache, key)| cache.get(key));
+            let llm_response = match cached {
+                Some(llm_response) => {
+                    self.record_cache_hits(remaining.len());
+                    self.record_served_by(model, None);
+                    llm_response
+                }
+                None => {
+                    let request_start = Instant::now();
+                    let llm_response = match &self.response_format {
+                        Some(format) => match ollama_app
+                            .generate_structured_from(instruction, remaining, first_row, format)
+                            .await
+                        {
+                            Err(e) if is_unsupported_format(&e) => {
+                                log::warn!("{}, falling back to plain text output", e);
+                                ollama_app
+                                    .generate_chat_from(instruction, remaining, first_row)
+                                    .await
+                            }
+                            llm_response => llm_response,
+                        },
+                        None => {
+                            ollama_app
+                                .generate_chat_from(instruction, remaining, first_row)
+                                .await
+                        }
+                    };
+                    telemetry::record_request(model, llm_response.is_ok(), request_start.elapsed());
+                    record_usage(
+                        llm_response.as_ref().ok().and_then(|r| r.usage),
+                        request_start.elapsed(),
+                    );
+                    if let Err(e) = &llm_response
+                        && is_unreachable(e)
+                        && endpoints.len() > 1
+                    {
+                        endpoint.mark_down();
+                    }
+                    let llm_response = llm_response.map_err(request_error)?;
+                    if let Some(audit_log) = &self.audit_log {
+                        audit_log.record(model, instruction, remaining, &llm_response.content);
+                    }
+                    self.record_served_by(model, Some(endpoint.url()));
+                    llm_response
+                }
+            };
+
+            let evaluated_values: Vec<Option<String>> = match self.response_parsing {
+                ResponseParsing::Arrow => self
+                    .response_parser
+                    .parse(&llm_response.content, remaining.len()),
+                ResponseParsing::EchoTolerant => {
+                    parse_echoed_response(&llm_response.content, remaining, first_row)
+                        .into_iter()
+                        .map(Some)
+                        .collect()
+                }
+                ResponseParsing::Indexed => {
+                    parse_indexed_response(&llm_response.content, remaining.len(), first_row)
+                }
+            };
+            if reprompted {
+                log::debug!(
+                    "format re-prompt parsed {} of {} answers",
+                    evaluated_values.len(),
+                    remaining.len()
+                );
+            }
+            if evaluated_values.is_empty() && self.format_reprompt && !reprompted {
+                log::warn!("no answers could be parsed, re-prompting with a format reminder");
+                log::debug!(
+                    "unparsable response: {:?}",
+                    response_preview(&llm_response.content)
+                );
+                reprompted = true;
+                continue;
+            }
+            // sanity check that the number of results is the same as the number of input values
+            if evaluated_values.len() == remaining.len() {
+                let answers = self.post_process_answers(remaining, evaluated_values);
+                self.validate_chunk(remaining, &answers)?;
+                // only responses that answer every row are worth replaying
+                if let (Some(cache), Some(key)) = (self.response_cache(), cache_key) {
+                    cache.insert(key, llm_response.clone());
+                }
+                records_outcome.extend(answers);
+                break;
+            }
+
+            telemetry::record_count_mismatch(model);
+            let answered = evaluated_values.len();
+            if self.truncation_mode == TruncationMode::KeepLeading
+                && llm_response.done_reason.as_deref() == Some("length")
+                && answered > 0
+                && answered < remaining.len()
+            {
+                println!(
+                    "kept {} leading answers of {} rows (done_reason: {:?}), re-requesting the rest",
+                    answered,
+                    remaining.len(),
+                    llm_response.done_reason
+                );
+                let (leading, tail) = remaining.split_at(answered);
+                let answers = self.post_process_answers(leading, evaluated_values);
+                self.validate_chunk(leading, &answers)?;
+                records_outcome.extend(answers);
+                remaining = tail;
+                continue;
+            }
 
-        let evaluated_values: Vec<String> = parse_llm_response(&llm_response);
-        // sanity check that the number of results is the same as the number of input values
-        if evaluated_values.len() == vals.len() {
-            records_outcome.extend(evaluated_values);
-        } else {
             let error_message = format!(
-                "Error: mismatched result count: {} != {}. results: {:?}",
+                "{}: {} != {}. results: {:?}",
+                MISMATCH_ERROR_PREFIX,
                 evaluated_values.len(),
-                vals.len(),
+                remaining.len(),
                 evaluated_values
             );
-            records_outcome.extend(vec![error_message; vals.len()]);
+            records_outcome.extend(vec![Some(error_message); remaining.len()]);
+            break;
         }
 
         Ok(records_outcome)
     }
+
+    fn validate_chunk(&self, inputs: &[String], answers: &[Option<String>]) -> Result<()> {
+        let Some(validator) = &self.chunk_validator else {
+            return Ok(());
+        };
+        let outputs: Vec<String> = answers
+            .iter()
+            .map(|answer| answer.clone().unwrap_or_default())
+            .collect();
+        if (validator.0)(inputs, &outputs) {
+            return Ok(());
+        }
+        Err(DataFusionError::Execution(format!(
+            "chunk rejected by validator: {:?}",
+            outputs
+        )))
+    }
+
+    // outside of run_prompt (e.g. in tests) there is no chunk to record it for
+    fn record_served_by(&self, model: &Arc<str>, endpoint: Option<&str>) {
+        let _ = SERVED_BY.try_with(|served_by| {
+            served_by.replace(Some(ServedBy {
+                model: model.clone(),
+                endpoint: endpoint.map(str::to_string),
+            }))
+        });
+    }
+
+    fn record_cache_hits(&self, hits: usize) {
+        telemetry::record_cache_hits(&self.ollama_model, hits);
+        if let Some(progress) = &self.progress {
+            progress.add_cache_hits(hits);
+        }
+    }
+
+    // rows the parser skipped stay NULL
+    fn post_process_answers(
+        &self,
+        vals: &[String],
+        answers: Vec<Option<String>>,
+    ) -> Vec<Option<String>> {
+        vals.iter()
+            .zip(answers)
+            .map(|(input, answer)| {
+                let (answer, detail) = match (&self.detail_split, answer?) {
+                    (Some(split), answer) => (split.0)(&answer),
+                    (None, answer) => (answer, None),
+                };
+                join_detail(self.post_process_answer(input, answer), detail)
+            })
+            .collect()
+    }
+
+    fn post_process_answer(&self, input: &str, answer: String) -> Option<String> {
+        if self
+            .null_sentinels
+            .iter()
+            .any(|sentinel| sentinel.eq_ignore_ascii_case(answer.trim()))
+        {
+            return None;
+        }
+        let answer = match &self.normalization {
+            Some(normalization) => normalization.apply(&answer),
+            None => answer,
+        };
+        let answer = match &self.post_process {
+            Some(post_process) => (post_process.0)(input, &answer),
+            None => answer,
+        };
+        match &self.categories {
+            Some(gate) => gate.check(&answer).map(str::to_string),
+            None => Some(answer),
+        }
+    }
+}
+
+impl Default for AskLLM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Implement the ScalarUDFImpl trait for AskLLM
@@ -81,65 +2553,233 @@ impl ScalarUDFImpl for AskLLM {
         self
     }
     fn name(&self) -> &str {
-        "ask_llm"
+        &self.name
     }
     fn signature(&self) -> &Signature {
         &self.signature
     }
     fn return_type(&self, args: &[DataType]) -> Result<DataType> {
-        if !matches!(args.get(0), Some(&DataType::Utf8)) {
-            return plan_err!("ask_llm only accepts Utf8 arguments");
+        self.output_type(args, None)
+    }
+
+    // lets the planner cast the arguments to Utf8 up front (e.g. a numeric or
+    // dictionary column), so incompatible arguments fail at plan time rather than
+    // in the middle of execution. Run-end encoded Utf8 columns are kept as they are,
+    // and numeric, temporal and boolean values are formatted with the InputFormat.
+    fn coerce_types(&self, arg_types: &[DataType]) -> Result<Vec<DataType>> {
+        if !(2..=3).contains(&arg_types.len()) {
+            return plan_err!(
+                "ask_llm expects 2 or 3 arguments: 'instruction', 'column_value' and an optional 'task', got {}",
+                arg_types.len()
+            );
         }
-        Ok(DataType::Utf8)
+        arg_types
+            .iter()
+            .enumerate()
+            .map(|(i, arg_type)| match arg_type {
+                DataType::RunEndEncoded(_, values)
+                    if i == 1 && values.data_type() == &DataType::Utf8 =>
+                {
+                    Ok(arg_type.clone())
+                }
+                arg_type if i == 1 && InputFormat::formats(arg_type) => Ok(arg_type.clone()),
+                arg_type if can_cast_types(arg_type, &DataType::Utf8) => Ok(DataType::Utf8),
+                arg_type => plan_err!(
+                    "ask_llm argument {} has type {}, which cannot be cast to Utf8",
+                    i + 1,
+                    arg_type
+                ),
+            })
+            .collect()
+    }
+
+    fn return_type_from_args(&self, args: ReturnTypeArgs) -> Result<ReturnInfo> {
+        let instruction = match args.scalar_arguments.first() {
+            Some(Some(ScalarValue::Utf8(instruction))) => instruction.as_deref(),
+            _ if self.provenance => {
+                return plan_err!("ask_llm with provenance metadata needs a literal instruction");
+            }
+            _ => None,
+        };
+        let return_type = self.output_type(args.arg_types, instruction)?;
+        self.output.check()?;
+        // answers that do not parse as the output type become NULL
+        Ok(ReturnInfo::new_nullable(return_type))
     }
 
     fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
-        let ScalarFunctionArgs { mut args, .. } = args;
-        assert_eq!(args.len(), 2);
-        let instruction = args.pop().unwrap();
-        let column_value = args.pop().unwrap();
-        assert_eq!(instruction.data_type(), DataType::Utf8);
-        assert_eq!(column_value.data_type(), DataType::Utf8);
-
-        match (instruction, column_value) {
-            (
+        let ScalarFunctionArgs { args, .. } = args;
+
+        let (instruction, col_values, tasks) = match args.as_slice() {
+            [
+                ColumnarValue::Scalar(ScalarValue::Utf8(instruction)),
                 ColumnarValue::Array(col_values),
+            ] => (instruction, col_values, None),
+            [
                 ColumnarValue::Scalar(ScalarValue::Utf8(instruction)),
-            ) => {
-                let col_values = as_string_array(col_values.as_ref())?;
-                println!("instruction: {:?}", instruction);
-                let values: Vec<_> = col_values.iter().collect();
-                let chunk_size = 5;
-
-                let result: Vec<String> = values
-                    .par_chunks(chunk_size)
-                    .flat_map(|chunk| {
-                        // first we extract the column values from the chunk
-                        let vals: Vec<String> = chunk
-                            .iter()
-                            .map(|opt| opt.unwrap_or_default().to_string())
-                            .collect();
-                        // then we extract the instruction in the UDF
-                        let instruction_str = instruction.as_deref().unwrap_or_default();
-                        let time_start = Instant::now();
-                        let rt = create_tokio_runtime();
-                        println!("runtime created in {:?}", time_start.elapsed());
-                        match rt.block_on(self.process_chunk(instruction_str, &vals)) {
-                            Ok(records) => records,
-                            Err(e) => vec![format!("Error processing chunk: {}", e); vals.len()],
-                        }
-                    })
-                    .collect();
-
-                Ok(ColumnarValue::Array(Arc::new(StringArray::from(result))))
-            }
-
+                ColumnarValue::Array(col_values),
+                ColumnarValue::Array(tasks),
+            ] => (instruction, col_values, Some(tasks)),
             _ => {
                 return plan_err!(
-                    "ask_llm only accepts 2 arguments in the form of 'instruction' (string), 'column_value' (column)"
+                    "ask_llm only accepts arguments in the form of 'instruction' (string), 'column_value' (column) and an optional 'task' (column)"
                 );
             }
+        };
+        let formatted;
+        let col_values = if InputFormat::formats(col_values.data_type()) {
+            formatted = self.input_format.format(col_values)?;
+            &formatted
+        } else {
+            col_values
+        };
+        // a run-end encoded column is answered once per run and expanded back to its
+        // rows at the end, unless a task column may give the rows of a run different tasks
+        let (values, run_rows): (Vec<Option<&str>>, Option<Vec<usize>>) =
+            match run_end_encoded_parts(col_values)? {
+                Some((run_values, rows)) if tasks.is_some() => {
+                    let run_values: Vec<Option<&str>> = run_values.iter().collect();
+                    (rows.iter().map(|&i| run_values[i]).collect(), None)
+                }
+                Some((run_values, rows)) => {
+                    println!("ask_llm: {} runs for {} rows", run_values.len(), rows.len());
+                    (run_values.iter().collect(), Some(rows))
+                }
+                None => (
+                    utf8_argument(col_values, "column_value")?.iter().collect(),
+                    None,
+                ),
+            };
+        println!("instruction: {:?}", instruction);
+        let fields = self.struct_fields(instruction.as_deref());
+        let instruction = load_instruction(
+            instruction.as_deref().unwrap_or_default(),
+            self.instruction_files.as_deref(),
+        )?;
+        let instruction = match &self.few_shot {
+            Some(few_shot) => Cow::Owned(render_examples(&instruction, &few_shot.examples()?)),
+            None => instruction,
+        };
+        let instruction_str = instruction.as_ref();
+        let invocation = Invocation::new(self.retry_budget);
+
+        let result = match tasks {
+            None => match &self.coalescer {
+                Some(coalescer) => {
+                    let owned = values.iter().map(|v| v.map(str::to_string)).collect();
+                    coalescer
+                        .submit(instruction_str, owned, |all| {
+                            let all: Vec<Option<&str>> = all.iter().map(Option::as_deref).collect();
+                            self.evaluate(instruction_str, &all, &invocation)
+                                .map(|answers| self.reconciled(&all, answers))
+                                .map_err(|e| e.to_string())
+                        })
+                        .map_err(DataFusionError::Execution)?
+                }
+                None => self.reconciled(
+                    &values,
+                    self.evaluate(instruction_str, &values, &invocation)?,
+                ),
+            },
+            Some(tasks) => {
+                let tasks = utf8_argument(tasks, "task")?;
+                // group rows by the instruction their task resolves to, evaluate each
+                // group separately and scatter the answers back to their rows
+                let mut groups: Vec<(&str, Vec<usize>)> = Vec::new();
+                for (i, task) in tasks.iter().enumerate() {
+                    let group_instruction = task
+                        .and_then(|task| self.task_templates.get(task))
+                        .map_or(instruction_str, String::as_str);
+                    match groups.iter_mut().find(|(g, _)| *g == group_instruction) {
+                        Some((_, rows)) => rows.push(i),
+                        None => groups.push((group_instruction, vec![i])),
+                    }
+                }
+                let mut result = Scatter::new(values.len());
+                for (group_instruction, rows) in groups {
+                    let group_values: Vec<Option<&str>> = rows.iter().map(|&i| values[i]).collect();
+                    let answers = self.reconciled(
+                        &group_values,
+                        self.evaluate(group_instruction, &group_values, &invocation)?,
+                    );
+                    result.fill(&rows, answers)?;
+                }
+                result.finish()?
+            }
+        };
+
+        if let Some(error) = invocation.failure.into_inner().unwrap() {
+            return Err(DataFusionError::Execution(format!(
+                "ask_llm failed: {}",
+                error
+            )));
+        }
+
+        let timeouts = invocation.timeouts.load(Ordering::SeqCst);
+        if timeouts > 0 {
+            println!(
+                "ask_llm: {} chunks exceeded the {:?} deadline and were returned as NULL",
+                timeouts,
+                self.chunk_deadline.unwrap_or_default()
+            );
+        }
+
+        if self.cancellation.is_cancelled() {
+            let skipped = result.iter().filter(|row| row.answer.is_none()).count();
+            println!(
+                "ask_llm cancelled: {} of {} rows were skipped and returned as NULL",
+                skipped,
+                result.len()
+            );
+        }
+
+        let result = match run_rows {
+            Some(rows) => rows.iter().map(|&i| result[i].clone()).collect(),
+            None => result,
+        };
+        let latencies: Vec<Option<f64>> = result.iter().map(|row| row.latency_ms).collect();
+        let confidences: Vec<Option<f64>> = result.iter().map(|row| row.confidence).collect();
+        let (models, endpoints): (Vec<Option<String>>, Vec<Option<String>>) = result
+            .iter()
+            .map(|row| match &row.served_by {
+                Some(served_by) => (
+                    Some(served_by.model.to_string()),
+                    served_by.endpoint.clone(),
+                ),
+                None => (None, None),
+            })
+            .unzip();
+        let errors: Vec<Option<String>> = result
+            .iter()
+            .map(|row| row.error.as_ref().map(RowError::to_string))
+            .collect();
+        let answers: Vec<Option<String>> = result
+            .into_iter()
+            .map(|row| match row.error {
+                Some(_) if self.error_column => None,
+                _ => row.answer,
+            })
+            .collect();
+        let answers = self.output.build(answers)?;
+        let Some(fields) = fields else {
+            return Ok(ColumnarValue::Array(answers));
+        };
+        let mut columns: Vec<ArrayRef> = vec![answers];
+        if self.error_column {
+            columns.push(Arc::new(StringArray::from(errors)));
+        }
+        if self.latency_column {
+            columns.push(Arc::new(Float64Array::from(latencies)));
+        }
+        if self.consistency.is_some() {
+            columns.push(Arc::new(Float64Array::from(confidences)));
+        }
+        if self.model_column {
+            columns.push(Arc::new(StringArray::from(models)));
+            columns.push(Arc::new(StringArray::from(endpoints)));
         }
+        let result = StructArray::try_new(fields, columns, None)?;
+        Ok(ColumnarValue::Array(Arc::new(result)))
     }
 
     fn documentation(&self) -> Option<&Documentation> {
@@ -147,13 +2787,1502 @@ impl ScalarUDFImpl for AskLLM {
     }
 }
 
-fn parse_llm_response(input: &str) -> Vec<String> {
+// coerce_types casts the arguments to Utf8 when planning, but invoke_with_args can
+// also be called directly with any array
+fn utf8_argument<'a>(array: &'a ArrayRef, argument: &str) -> Result<&'a StringArray> {
+    match array.as_string_opt::<i32>() {
+        Some(array) => Ok(array),
+        None => exec_err!(
+            "ask_llm expects the '{}' argument as Utf8, got {}; cast it to Utf8 first",
+            argument,
+            array.data_type()
+        ),
+    }
+}
+
+// for a run-end encoded Utf8 column, the value of each run and the run of each row
+fn run_end_encoded_parts(array: &ArrayRef) -> Result<Option<(&StringArray, Vec<usize>)>> {
+    fn parts<R: RunEndIndexType>(array: &RunArray<R>) -> Result<(&StringArray, Vec<usize>)> {
+        let values = as_string_array(array.values().as_ref())?;
+        let rows = (0..array.len())
+            .map(|i| array.get_physical_index(i))
+            .collect();
+        Ok((values, rows))
+    }
+    if let Some(array) = array.as_run_opt::<Int16Type>() {
+        return parts(array).map(Some);
+    }
+    if let Some(array) = array.as_run_opt::<Int32Type>() {
+        return parts(array).map(Some);
+    }
+    if let Some(array) = array.as_run_opt::<Int64Type>() {
+        return parts(array).map(Some);
+    }
+    Ok(None)
+}
+
+/// Resolves an instruction given as `file://path` or `@path` to the contents of that
+/// file under `base_dir`, without trailing whitespace, see
+/// [`AskLLM::with_instruction_files`]. Other instructions, and all of them without a
+/// `base_dir`, are returned as they are. The file is read once per invocation.
+pub(crate) fn load_instruction<'a>(
+    instruction: &'a str,
+    base_dir: Option<&Path>,
+) -> Result<Cow<'a, str>> {
+    let Some((base_dir, path)) = base_dir.zip(
+        instruction
+            .strip_prefix("file://")
+            .or_else(|| instruction.strip_prefix('@')),
+    ) else {
+        return Ok(Cow::Borrowed(instruction));
+    };
+    let read_error = |e: std::io::Error| {
+        DataFusionError::Execution(format!("cannot read instruction file {}: {}", path, e))
+    };
+    let base_dir = base_dir.canonicalize().map_err(read_error)?;
+    let resolved = base_dir.join(path).canonicalize().map_err(read_error)?;
+    if !resolved.starts_with(&base_dir) {
+        return Err(DataFusionError::Execution(format!(
+            "instruction file {} is outside {}",
+            path,
+            base_dir.display()
+        )));
+    }
+    let text = std::fs::read_to_string(resolved).map_err(read_error)?;
+    Ok(Cow::Owned(text.trim_end().to_string()))
+}
+
+// replaces the answer of every row whose value occurs more than once with the most
+// common answer among the occurrences, the earliest on a tie; failed rows do not vote
+// but take the majority too, and values that never got an answer are left alone.
+// Returns the number of rows whose answer changed.
+fn reconcile_duplicates(values: &[Option<&str>], answers: &mut [RowAnswer]) -> usize {
+    let mut occurrences: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, value) in values.iter().enumerate() {
+        if let Some(value) = value {
+            occurrences.entry(value).or_default().push(i);
+        }
+    }
+    let mut changed = 0;
+    for rows in occurrences.values().filter(|rows| rows.len() > 1) {
+        // answer -> (votes, first row)
+        let mut votes: HashMap<&str, (usize, usize)> = HashMap::new();
+        for &i in rows {
+            if let (Some(answer), None) = (&answers[i].answer, &answers[i].error) {
+                votes.entry(answer).or_insert((0, i)).0 += 1;
+            }
+        }
+        let Some(majority) = votes
+            .into_iter()
+            .max_by(|(_, (a, first_a)), (_, (b, first_b))| a.cmp(b).then(first_b.cmp(first_a)))
+            .map(|(answer, _)| answer.to_string())
+        else {
+            continue;
+        };
+        for &i in rows {
+            if answers[i].answer.as_deref() != Some(majority.as_str()) {
+                answers[i].answer = Some(majority.clone());
+                answers[i].error = None;
+                changed += 1;
+            }
+        }
+    }
+    changed
+}
+
+// the start of a response for log messages, at most RESPONSE_PREVIEW_CHARS characters
+fn response_preview(response: &str) -> String {
+    match response.char_indices().nth(RESPONSE_PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}...", &response[..end]),
+        None => response.to_string(),
+    }
+}
+
+// the instruction for the format re-prompt, see AskLLM::with_format_reprompt
+fn format_reminder(instruction: &str, rows: usize) -> String {
+    format!(
+        "{}. Output exactly {} lines, one per item, each formatted as 'N -> value' \
+         where N is the item number, and nothing else",
+        instruction.trim_end().trim_end_matches(['.', ':']),
+        rows
+    )
+}
+
+// the explicit line format of ResponseParsing::Indexed for the rows first_row..
+fn indexed_instruction(instruction: &str, first_row: usize, rows: usize) -> String {
+    format!(
+        "{}. Return exactly {} lines, for items {} to {}. Line i must be 'i: answer' \
+         where i is the item number, with no extra text",
+        instruction.trim_end().trim_end_matches(['.', ':']),
+        rows,
+        first_row,
+        first_row + rows - 1
+    )
+}
+
+// matches every line that starts with a row number to that row, the first line per
+// row wins; the result ends at the last answered row, so missing trailing rows are a
+// count mismatch while rows skipped before it are None
+pub(crate) fn parse_indexed_response(
+    input: &str,
+    rows: usize,
+    first_row: usize,
+) -> Vec<Option<String>> {
+    let mut answers = vec![None; rows];
+    for line in input.lines() {
+        let line = line.trim().trim_start_matches(['*', '-', ' ']);
+        let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let Some(row) = line[..digits]
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(first_row))
+            .filter(|&row| row < rows)
+        else {
+            continue;
+        };
+        let rest = line[digits..].trim_start().trim_start_matches('*');
+        let Some(answer) = [":", "->", ".", ")"]
+            .into_iter()
+            .find_map(|separator| rest.strip_prefix(separator))
+        else {
+            continue;
+        };
+        answers[row].get_or_insert_with(|| answer.trim().to_string());
+    }
+    let answered = answers
+        .iter()
+        .rposition(Option::is_some)
+        .map_or(0, |last| last + 1);
+    answers.truncate(answered);
+    answers
+}
+
+// only the first "->" separates the row number from the answer, so answers that
+// contain "->" themselves are kept intact. Parsing stops after `expected` answers, so
+// commentary after a complete list ("Let me know if ...") is ignored even when it
+// contains "->"; fewer answers are still a count mismatch
+pub(crate) fn parse_llm_response(input: &str, expected: usize) -> Vec<String> {
+    parse_llm_lines(input, expected)
+        .into_iter()
+        .map(|(_, value)| value)
+        .collect()
+}
+
+// like parse_llm_response, but keeps the line each answer was taken from
+pub(crate) fn parse_llm_lines(input: &str, expected: usize) -> Vec<(String, String)> {
+    input
+        .lines()
+        .filter_map(|line| {
+            line.split_once("->")
+                .map(|(_, value)| (line.to_string(), value.trim().to_string()))
+        })
+        .take(expected)
+        .collect()
+}
+
+// like parse_llm_response, but first removes the row number and, when the model
+// restated it, the input of that row from the start of each line
+pub(crate) fn parse_echoed_response(input: &str, vals: &[String], first_row: usize) -> Vec<String> {
     input
         .lines()
         .filter_map(|line| {
-            line.split("->")
-                .nth(1)
-                .map(|value| value.trim().to_string())
+            let line = line.trim();
+            let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let Some(value) = line[..digits]
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| vals.get(n.checked_sub(first_row)?))
+            else {
+                return line
+                    .split_once("->")
+                    .map(|(_, answer)| answer.trim().to_string());
+            };
+            let rest = line[digits..].trim_start_matches(['.', ')']).trim_start();
+            let echoed = [format!("\"{value}\""), format!("'{value}'"), value.clone()]
+                .into_iter()
+                .find_map(|echo| rest.strip_prefix(echo.as_str()));
+            match echoed {
+                Some(after_echo) => {
+                    let after_echo = after_echo.trim_start();
+                    ["->", "=>", ":", "-"]
+                        .into_iter()
+                        .find_map(|separator| after_echo.strip_prefix(separator))
+                        .map(|answer| answer.trim().to_string())
+                }
+                None => rest
+                    .strip_prefix("->")
+                    .or_else(|| rest.split_once("->").map(|(_, answer)| answer))
+                    .map(|answer| answer.trim().to_string()),
+            }
         })
+        .take(vals.len())
         .collect()
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use datafusion::arrow::array::{BinaryArray, Int32Array};
+    use datafusion::arrow::datatypes::{Float64Type, UnionFields, UnionMode};
+    use datafusion::arrow::record_batch::RecordBatch;
+
+    #[test]
+    fn test_parse_discards_echoed_inputs() {
+        let vals = vec![
+            "Great -> would buy again".to_string(),
+            "Late delivery: box damaged".to_string(),
+            "ok".to_string(),
+        ];
+        let response = "Here are the results:\n\
+            1. \"Great -> would buy again\" -> positive\n\
+            2. Late delivery: box damaged: negative\n\
+            3 -> neutral";
+        assert_eq!(
+            parse_echoed_response(response, &vals, 1),
+            vec!["positive", "negative", "neutral"]
+        );
+        // the plain parser splits inside the echoed input
+        assert_eq!(
+            parse_llm_response(response, vals.len())[0],
+            "would buy again\" -> positive"
+        );
+    }
+
+    #[test]
+    fn test_echo_tolerant_parse_accepts_plain_lines() {
+        let vals = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(
+            parse_echoed_response("1 -> yes\n2 -> a -> b", &vals, 1),
+            vec!["yes", "a -> b"]
+        );
+        // with global row numbers the echoed number is offset by the chunk's first row
+        assert_eq!(
+            parse_echoed_response("6. a -> yes\n7. \"b\" -> no", &vals, 6),
+            vec!["yes", "no"]
+        );
+    }
+
+    #[test]
+    fn test_reconcile_duplicates_uses_the_majority_answer() {
+        let values = [
+            Some("a"),
+            Some("b"),
+            Some("a"),
+            Some("a"),
+            None,
+            Some("b"),
+            Some("c"),
+        ];
+        let mut answers: Vec<RowAnswer> = [
+            Some("x"),
+            Some("p"),
+            Some("y"),
+            Some("y"),
+            None,
+            Some("q"),
+            Some("z"),
+        ]
+        .into_iter()
+        .map(|answer| RowAnswer::local(answer.map(str::to_string)))
+        .collect();
+        assert_eq!(reconcile_duplicates(&values, &mut answers), 2);
+        let answers: Vec<Option<&str>> = answers.iter().map(|row| row.answer.as_deref()).collect();
+        // "a" has a majority, "b" is a tie won by the earliest answer
+        assert_eq!(
+            answers,
+            [
+                Some("y"),
+                Some("p"),
+                Some("y"),
+                Some("y"),
+                None,
+                Some("p"),
+                Some("z")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_instruction_from_file() {
+        let base_dir = std::env::temp_dir().join(format!("instructions_{}", std::process::id()));
+        std::fs::create_dir_all(&base_dir).unwrap();
+        let path = base_dir.join("sentiment.txt");
+        std::fs::write(&path, "Classify the sentiment\n").unwrap();
+        let outside = std::env::temp_dir().join(format!("outside_{}.txt", std::process::id()));
+        std::fs::write(&outside, "secret").unwrap();
+        let base = Some(base_dir.as_path());
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            load_instruction(&format!("file://{}", path), base).unwrap(),
+            "Classify the sentiment"
+        );
+        assert_eq!(
+            load_instruction("@sentiment.txt", base).unwrap(),
+            "Classify the sentiment"
+        );
+        // off by default, so an instruction starting with @ is sent as it is
+        assert_eq!(
+            load_instruction("@sentiment.txt", None).unwrap(),
+            "@sentiment.txt"
+        );
+        let escape = format!("@../{}", outside.file_name().unwrap().to_str().unwrap());
+        let error = load_instruction(&escape, base).unwrap_err();
+        assert!(error.to_string().contains("is outside"), "{error}");
+        std::fs::remove_file(&outside).unwrap();
+        std::fs::remove_dir_all(&base_dir).unwrap();
+        assert_eq!(load_instruction("Summarize", base).unwrap(), "Summarize");
+        let error = load_instruction("@sentiment.txt", base).unwrap_err();
+        assert!(error.to_string().contains("cannot read instruction file"));
+    }
+
+    #[test]
+    fn test_format_reminder_states_the_row_count() {
+        assert_eq!(
+            format_reminder("Categorize the feedback.", 3),
+            "Categorize the feedback. Output exactly 3 lines, one per item, each formatted \
+             as 'N -> value' where N is the item number, and nothing else"
+        );
+    }
+
+    #[test]
+    fn test_response_preview_is_truncated() {
+        assert_eq!(response_preview("1 -> yes"), "1 -> yes");
+        let preview = response_preview(&"é".repeat(RESPONSE_PREVIEW_CHARS + 1));
+        assert_eq!(
+            preview,
+            format!("{}...", "é".repeat(RESPONSE_PREVIEW_CHARS))
+        );
+    }
+
+    #[test]
+    fn test_indexed_answers_are_matched_by_row_number() {
+        assert_eq!(
+            indexed_instruction("Categorize the feedback.", 6, 3),
+            "Categorize the feedback. Return exactly 3 lines, for items 6 to 8. Line i must \
+             be 'i: answer' where i is the item number, with no extra text"
+        );
+        // reordered lines are matched to their rows
+        assert_eq!(
+            parse_indexed_response("2: negative\n1: positive\n3: neutral", 3, 1),
+            vec![
+                Some("positive".to_string()),
+                Some("negative".to_string()),
+                Some("neutral".to_string())
+            ]
+        );
+        // a skipped row in the middle is None, other separators and chatter are accepted
+        // and out of range or repeated indices are ignored
+        assert_eq!(
+            parse_indexed_response(
+                "Here you go:\n**7**: yes\n9 -> no\n7: maybe\n12: late",
+                3,
+                7
+            ),
+            vec![Some("yes".to_string()), None, Some("no".to_string())]
+        );
+        // missing trailing rows shorten the result, so they are a count mismatch
+        assert_eq!(
+            parse_indexed_response("1. a\n2) b", 4, 1),
+            vec![Some("a".to_string()), Some("b".to_string())]
+        );
+        assert!(parse_indexed_response("no numbered lines", 2, 1).is_empty());
+    }
+
+    #[test]
+    fn test_indexed_answers_keep_a_custom_system_prompt() {
+        let ask_llm = AskLLM::new().with_indexed_answers();
+        assert_eq!(ask_llm.system_prompt, ASK_LLM_INDEXED_SYSTEM_PROMPT);
+        let before = AskLLM::new()
+            .with_system_prompt("Answer in French.")
+            .with_indexed_answers();
+        assert_eq!(before.system_prompt, "Answer in French.");
+        let after = AskLLM::new()
+            .with_indexed_answers()
+            .with_system_prompt("Answer in French.");
+        assert_eq!(after.system_prompt, "Answer in French.");
+    }
+
+    #[test]
+    fn test_parse_keeps_arrows_inside_answers() {
+        let parsed = parse_llm_response("1 -> a -> b\n2 -> negative", 2);
+        assert_eq!(parsed, vec!["a -> b", "negative"]);
+    }
+
+    #[test]
+    fn test_parse_ignores_commentary_after_a_complete_list() {
+        let response = "1 -> positive\n2 -> negative\n\nNote: ratings map text -> sentiment. Let me know if you need anything else!";
+        assert_eq!(
+            parse_llm_response(response, 2),
+            vec!["positive", "negative"]
+        );
+        // a short list is still short
+        assert_eq!(parse_llm_response("1 -> positive", 2).len(), 1);
+        let vals = vec!["good".to_string(), "bad".to_string()];
+        assert_eq!(
+            parse_echoed_response(response, &vals, 1),
+            vec!["positive", "negative"]
+        );
+    }
+
+    #[test]
+    fn test_cancelled_udf_returns_nulls() {
+        let ask_llm = cancelled(AskLLM::new());
+        let values = StringArray::from(vec!["Excellent experience!", "Wrong item delivered."]);
+        let result = invoke(&ask_llm, "Categorize", vec![Arc::new(values)]).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result.null_count(), 2);
+    }
+
+    // the task groups are evaluated one after the other, so cancelling while the first
+    // group's chunk is in flight lets that chunk finish and skips the other group
+    #[test]
+    fn test_cancelling_while_a_task_group_is_in_flight() {
+        let (host, requests) = spawn_mock_server(|_| {
+            std::thread::sleep(Duration::from_millis(300));
+            let body = r#"{"message":{"content":"1 -> ok"},"done_reason":"stop"}"#;
+            (200, body.to_string())
+        });
+        let config = AiConfig::default()
+            .with_ollama_host(host)
+            .with_items_per_prompt(1);
+        let ask_llm = AskLLM::with_config(&config)
+            .with_max_concurrent_prompts(1)
+            .with_task_template("sentiment", "Classify the sentiment");
+        let cancellation = ask_llm.cancellation();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            cancellation.cancel();
+        });
+        let values = strings(&["Excellent experience!", "Wrong item delivered."]);
+        let tasks = StringArray::from(vec![Some("sentiment"), None]);
+        let result = invoke(&ask_llm, "Categorize", vec![values, Arc::new(tasks)]).unwrap();
+        canceller.join().unwrap();
+        let result = as_string_array(&result).unwrap();
+        assert_eq!(result.value(0), "ok");
+        assert!(result.is_null(1));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_output_type_is_applied() {
+        let ask_llm = cancelled(AskLLM::new().with_output_type(DataType::Int64));
+        assert_eq!(
+            ask_llm.return_type(&[DataType::Utf8]).unwrap(),
+            DataType::Int64
+        );
+        let result = invoke(&ask_llm, "Rate from 1 to 5", vec![strings(&["Great!"])]).unwrap();
+        assert_eq!(result.data_type(), &DataType::Int64);
+    }
+
+    #[test]
+    fn test_pre_filter_resolves_rows_without_the_model() {
+        let ask_llm = cancelled(
+            AskLLM::new()
+                .with_pre_filter(|value| value.trim().is_empty().then(|| "neutral".to_string())),
+        );
+        let result = invoke(&ask_llm, "Categorize", vec![strings(&["  ", "Great!"])]).unwrap();
+        let result = as_string_array(&result).unwrap();
+        assert_eq!(result.value(0), "neutral");
+        assert!(result.is_null(1));
+    }
+
+    #[test]
+    fn test_chunk_deadline_returns_nulls_for_slow_backend() {
+        // a backend that accepts connections but never answers
+        let backend = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = AiConfig::default()
+            .with_ollama_host(format!("http://{}", backend.local_addr().unwrap()));
+        let ask_llm = AskLLM::with_config(&config).with_chunk_deadline(Duration::from_millis(200));
+        let started = Instant::now();
+        let result = invoke(&ask_llm, "Categorize", vec![strings(&["Great!", "Awful."])]).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(result.null_count(), 2);
+    }
+
+    #[test]
+    fn test_blank_inputs_are_null_without_a_model_call() {
+        let values = vec![Some(""), Some("  \t"), None, Some("Great!")];
+        let answer_rest = |blank_input| {
+            let ask_llm = AskLLM::default()
+                .with_blank_input(blank_input)
+                .with_pre_filter(|_| Some("answered".to_string()));
+            let invocation = Invocation::new(None);
+            let answers = ask_llm.evaluate("Categorize", &values, &invocation);
+            answers
+                .unwrap()
+                .into_iter()
+                .map(|row| row.answer)
+                .collect::<Vec<_>>()
+        };
+        let answered = || Some("answered".to_string());
+        assert_eq!(
+            answer_rest(BlankInput::Empty),
+            vec![None, answered(), None, answered()]
+        );
+        assert_eq!(
+            answer_rest(BlankInput::Whitespace),
+            vec![None, None, None, answered()]
+        );
+    }
+
+    #[test]
+    fn test_run_end_encoded_input_is_answered_once_per_run() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let ask_llm = AskLLM::new().with_pre_filter(move |value| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            Some(value.to_uppercase())
+        });
+        let runs: RunArray<Int32Type> = std::iter::repeat_n("good", 40)
+            .chain(std::iter::repeat_n("bad", 25))
+            .chain(std::iter::repeat_n("good", 35))
+            .collect();
+        let column: ArrayRef = Arc::new(runs);
+        let result = invoke(&ask_llm, "Categorize", vec![column]).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let result = as_string_array(&result).unwrap();
+        assert_eq!(result.len(), 100);
+        assert_eq!(result.value(0), "GOOD");
+        assert_eq!(result.value(40), "BAD");
+        assert_eq!(result.value(99), "GOOD");
+    }
+
+    // a cancelled UDF skips its chunks, so only rows answered without the model have
+    // a value
+    fn cancelled(ask_llm: AskLLM) -> AskLLM {
+        ask_llm.cancellation().cancel();
+        ask_llm
+    }
+
+    pub(crate) fn strings(values: &[&str]) -> ArrayRef {
+        Arc::new(StringArray::from(values.to_vec()))
+    }
+
+    // invokes the UDF as DataFusion would, with a literal instruction and `columns` for
+    // the values (and the optional task)
+    pub(crate) fn invoke(
+        udf: &dyn ScalarUDFImpl,
+        instruction: &str,
+        columns: Vec<ArrayRef>,
+    ) -> Result<ArrayRef> {
+        let number_rows = columns.first().map_or(0, |column| column.len());
+        let return_type = udf.return_type(&vec![DataType::Utf8; columns.len() + 1])?;
+        let mut args = vec![ColumnarValue::Scalar(ScalarValue::Utf8(Some(
+            instruction.to_string(),
+        )))];
+        args.extend(columns.into_iter().map(ColumnarValue::Array));
+        let result = udf.invoke_with_args(ScalarFunctionArgs {
+            args,
+            number_rows,
+            return_type: &return_type,
+        })?;
+        match result {
+            ColumnarValue::Array(result) => Ok(result),
+            ColumnarValue::Scalar(_) => panic!("expected an array result"),
+        }
+    }
+
+    // registers `udf` on a new session and collects the result of `sql`
+    pub(crate) async fn query(udf: impl ScalarUDFImpl + 'static, sql: &str) -> Vec<RecordBatch> {
+        let ctx = datafusion::prelude::SessionContext::new();
+        ctx.register_udf(datafusion_expr::ScalarUDF::from(udf));
+        ctx.sql(sql).await.unwrap().collect().await.unwrap()
+    }
+
+    // a port nothing listens on, so every request is refused
+    fn refused_host() -> String {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = format!("http://{}", closed.local_addr().unwrap());
+        drop(closed);
+        host
+    }
+
+    // answers every chat request with "1 -> ok", enough for single-row prompts
+    pub(crate) fn spawn_mock_ollama() -> String {
+        spawn_mock_server(|_| {
+            let body = r#"{"message":{"content":"1 -> ok"},"done_reason":"stop"}"#;
+            (200, body.to_string())
+        })
+        .0
+    }
+
+    // answers every request, one at a time, with the status and JSON body `respond`
+    // returns for it; returns the host and the number of requests served so far
+    pub(crate) fn spawn_mock_server(
+        respond: impl Fn(&str) -> (u16, String) + Send + 'static,
+    ) -> (String, Arc<AtomicUsize>) {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let served = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                // read the headers and the JSON body, which ends with the closing brace
+                while !request.ends_with(b"}") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                served.fetch_add(1, Ordering::SeqCst);
+                let (status, body) = respond(&String::from_utf8_lossy(&request));
+                let reason = reqwest::StatusCode::from_u16(status)
+                    .unwrap()
+                    .canonical_reason()
+                    .unwrap_or_default();
+                let response = format!(
+                    "HTTP/1.1 {} {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    reason,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (host, requests)
+    }
+
+    #[test]
+    fn test_rejected_and_malformed_responses_are_not_retried() {
+        let not_found = r#"{"error":"model 'nope' not found"}"#;
+        for (status, body, expected) in [
+            (404, not_found, "not found"),
+            (
+                200,
+                "<html>Bad gateway</html>",
+                "Failed to parse Ollama response",
+            ),
+        ] {
+            let (host, requests) = spawn_mock_server(move |_| (status, body.to_string()));
+            let config = AiConfig::default()
+                .with_ollama_host(host)
+                .with_max_chunk_retries(3);
+            let ask_llm = AskLLM::with_config(&config).with_error_column();
+            let result = invoke(&ask_llm, "Categorize", vec![strings(&["fine"])]).unwrap();
+            let errors = result.as_struct().column_by_name("error").unwrap();
+            let error = as_string_array(errors).unwrap().value(0);
+            assert!(error.contains(expected), "{error}");
+            assert_eq!(requests.load(Ordering::SeqCst), 1, "{error}");
+        }
+    }
+
+    #[test]
+    fn test_non_utf8_arguments_fail_with_their_type() {
+        let ask_llm = AskLLM::new().with_dry_run(DryRun::new());
+        let ints: ArrayRef = Arc::new(Int32Array::from(vec![1, 2]));
+        // numeric values are formatted as text, a numeric task column is an error
+        assert!(invoke(&ask_llm, "Categorize", vec![ints.clone()]).is_ok());
+        let error = invoke(&ask_llm, "Categorize", vec![strings(&["a", "b"]), ints])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("'task' argument as Utf8, got Int32"),
+            "{error}"
+        );
+        let bytes: ArrayRef = Arc::new(BinaryArray::from(vec![b"a".as_ref(), b"b".as_ref()]));
+        let error = invoke(&ask_llm, "Categorize", vec![bytes])
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("'column_value' argument as Utf8, got Binary"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn test_panicking_chunk_does_not_fail_the_query() {
+        let config = AiConfig::default()
+            .with_ollama_host(spawn_mock_ollama())
+            .with_items_per_prompt(1);
+        let ask_llm = AskLLM::with_config(&config).with_post_process(|value, answer| {
+            assert_ne!(value, "boom", "backend failure");
+            answer.to_string()
+        });
+        let result = invoke(
+            &ask_llm,
+            "Categorize",
+            vec![strings(&["fine", "boom", "good"])],
+        )
+        .unwrap();
+        let result = as_string_array(&result).unwrap();
+        assert_eq!(result.value(0), "ok");
+        assert!(result.value(1).contains("panicked"));
+        assert_eq!(result.value(2), "ok");
+    }
+
+    #[test]
+    fn test_answer_cache_skips_repeated_values() {
+        let ask_llm = AskLLM::with_config(&AiConfig::default().with_items_per_prompt(1))
+            .with_answer_cache()
+            .with_max_concurrent_prompts(2);
+        let cache = ask_llm.answer_cache.clone().unwrap();
+        assert_eq!(cache.shard_count(), 8);
+        cache.insert(
+            ("Categorize".to_string(), "Great!".to_string()),
+            "positive".to_string(),
+        );
+        // cancelled, so only cached rows can be answered
+        ask_llm.cancellation().cancel();
+        let invocation = Invocation::new(None);
+        let answers = ask_llm
+            .evaluate("Categorize", &[Some("Great!"), Some("Awful.")], &invocation)
+            .unwrap();
+        let answers: Vec<_> = answers.into_iter().map(|row| row.answer).collect();
+        assert_eq!(answers, vec![Some("positive".to_string()), None]);
+    }
+
+    #[test]
+    fn test_answer_cache_preload_skips_stale_entries() {
+        let path = std::env::temp_dir().join(format!("answers_{}.jsonl", std::process::id()));
+        let ask_llm = AskLLM::new().with_answer_cache();
+        let cache = ask_llm.answer_cache.clone().unwrap();
+        cache.insert(
+            ("Categorize".to_string(), "Great!".to_string()),
+            "positive".to_string(),
+        );
+        assert_eq!(ask_llm.export_answer_cache(&path).unwrap(), 1);
+        assert!(AskLLM::new().export_answer_cache(&path).is_err());
+
+        // a fresh UDF starts warm and answers the known input without the model
+        let warm = AskLLM::new().with_answer_cache_preload(&path).unwrap();
+        warm.cancellation().cancel();
+        let answers = warm
+            .evaluate("Categorize", &[Some("Great!")], &Invocation::new(None))
+            .unwrap();
+        assert_eq!(answers[0].answer.as_deref(), Some("positive"));
+
+        // other models, system prompts and answer settings do not reuse the answers
+        let config = AiConfig::default().with_chat_model("llama3.1:70b");
+        let other_model = AskLLM::with_config(&config)
+            .with_answer_cache_preload(&path)
+            .unwrap();
+        assert!(other_model.answer_cache.unwrap().is_empty());
+        let other_prompt = AskLLM::new()
+            .with_system_prompt("Answer in French.")
+            .with_answer_cache_preload(&path)
+            .unwrap();
+        assert!(other_prompt.answer_cache.unwrap().is_empty());
+        let other_output = AskLLM::new()
+            .with_output_type(DataType::Int64)
+            .with_answer_cache_preload(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(other_output.answer_cache.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_arguments_are_coerced_to_utf8() {
+        let ask_llm = AskLLM::new();
+        let run_end_encoded = DataType::RunEndEncoded(
+            Arc::new(Field::new("run_ends", DataType::Int32, false)),
+            Arc::new(Field::new("values", DataType::Utf8, true)),
+        );
+        assert_eq!(
+            ask_llm
+                .coerce_types(&[DataType::Utf8, DataType::Int64, DataType::LargeUtf8])
+                .unwrap(),
+            vec![DataType::Utf8, DataType::Int64, DataType::Utf8]
+        );
+        // only the column value is formatted, other arguments are still cast
+        assert_eq!(
+            ask_llm
+                .coerce_types(&[DataType::Int64, DataType::LargeUtf8])
+                .unwrap(),
+            vec![DataType::Utf8, DataType::Utf8]
+        );
+        assert_eq!(
+            ask_llm
+                .coerce_types(&[DataType::Utf8, run_end_encoded.clone()])
+                .unwrap(),
+            vec![DataType::Utf8, run_end_encoded]
+        );
+        let union = DataType::Union(UnionFields::empty(), UnionMode::Sparse);
+        assert!(ask_llm.coerce_types(&[DataType::Utf8, union]).is_err());
+        assert!(ask_llm.coerce_types(&[DataType::Utf8]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_typed_output_is_planned_without_runtime_casts() {
+        let ctx = datafusion::prelude::SessionContext::new();
+        ctx.register_udf(datafusion_expr::ScalarUDF::from(
+            AskLLM::new().with_output_type(DataType::Int64),
+        ));
+        let df = ctx
+            .sql("SELECT ask_llm('Rate from 1 to 5', x) > 3 AS good FROM (VALUES (1), (2)) AS t(x)")
+            .await
+            .unwrap();
+        assert_eq!(
+            df.schema().field(0).data_type(),
+            &DataType::Boolean,
+            "the comparison is planned against the Int64 output"
+        );
+    }
+
+    #[test]
+    fn test_input_guard_skips_non_textual_rows() {
+        let ask_llm = AskLLM::new()
+            .with_input_guard(InputGuard::default().with_action(GuardAction::Skip))
+            .with_pre_filter(|_| Some("answered".to_string()));
+        let invocation = Invocation::new(None);
+        let blob = "aGVsbG8gd29ybGQsIHRoaXMgaXMgYSBiYXNlNjQgZW5jb2RlZCBibG9i";
+        let answers = ask_llm
+            .evaluate("Categorize", &[Some(blob), Some("Great!")], &invocation)
+            .unwrap();
+        let answers: Vec<_> = answers.into_iter().map(|row| row.answer).collect();
+        assert_eq!(answers, vec![None, Some("answered".to_string())]);
+    }
+
+    #[test]
+    fn test_latency_column_splits_chunk_latency_over_rows() {
+        let config = AiConfig::default()
+            .with_ollama_host(spawn_mock_ollama())
+            .with_items_per_prompt(1);
+        let ask_llm = AskLLM::with_config(&config)
+            .with_latency_column()
+            .with_pre_filter(|value| (value == "skip").then(|| "skipped".to_string()));
+        assert!(matches!(
+            ask_llm
+                .return_type(&[DataType::Utf8, DataType::Utf8])
+                .unwrap(),
+            DataType::Struct(_)
+        ));
+        let result = invoke(&ask_llm, "Categorize", vec![strings(&["fine", "skip"])]).unwrap();
+        let result = result.as_struct();
+        let answers = as_string_array(result.column(0)).unwrap();
+        let latencies = result.column(1).as_primitive::<Float64Type>();
+        assert_eq!(answers.value(0), "ok");
+        assert!(latencies.value(0) > 0.0);
+        assert_eq!(answers.value(1), "skipped");
+        assert!(latencies.is_null(1));
+    }
+
+    #[test]
+    fn test_self_consistency_adds_a_confidence_column() {
+        let config = AiConfig::default().with_ollama_host(spawn_mock_ollama());
+        let ask_llm = AskLLM::with_config(&config).with_self_consistency(SelfConsistency::new(3));
+        let DataType::Struct(fields) = ask_llm.return_type(&[DataType::Utf8]).unwrap() else {
+            panic!("expected a struct");
+        };
+        assert_eq!(fields[1].name(), "confidence");
+        let result = invoke(&ask_llm, "Categorize", vec![strings(&["fine"])]).unwrap();
+        let result = result.as_struct();
+        assert_eq!(as_string_array(result.column(0)).unwrap().value(0), "ok");
+        // the mock always gives the same answer
+        assert_eq!(result.column(1).as_primitive::<Float64Type>().value(0), 1.0);
+        assert_eq!(ask_llm.dry_run_plan("Categorize", &[Some("fine")]).calls, 3);
+    }
+
+    #[test]
+    fn test_response_key_depends_on_chunk_order_and_boundaries() {
+        let ask_llm = AskLLM::new().with_response_cache();
+        let vals = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let key = ask_llm.response_key(&ask_llm.ollama_model, "Categorize", 1, &vals(&["a", "b"]));
+        assert_eq!(
+            key,
+            ask_llm.response_key(&ask_llm.ollama_model, "Categorize", 1, &vals(&["a", "b"]))
+        );
+        assert_ne!(
+            key,
+            ask_llm.response_key(&ask_llm.ollama_model, "Categorize", 1, &vals(&["b", "a"]))
+        );
+        assert_ne!(
+            key,
+            ask_llm.response_key(&ask_llm.ollama_model, "Categorize", 1, &vals(&["ab"]))
+        );
+        assert_ne!(
+            key,
+            ask_llm.response_key(&ask_llm.ollama_model, "Summarize", 1, &vals(&["a", "b"]))
+        );
+        // a UDF sharing the cache with other settings makes other requests
+        let seeded = AskLLM::new().with_response_cache().with_seed(7);
+        assert_ne!(
+            key,
+            seeded.response_key(&ask_llm.ollama_model, "Categorize", 1, &vals(&["a", "b"]))
+        );
+    }
+
+    #[test]
+    fn test_retries_vary_the_seed() {
+        let ask_llm = AskLLM::new();
+        assert!(ask_llm.attempt_options(1).get("seed").is_none());
+        let ask_llm = ask_llm.with_seed(7);
+        assert_eq!(ask_llm.attempt_options(0)["seed"], 7);
+        assert_ne!(ask_llm.attempt_options(1)["seed"], 7);
+        assert_ne!(
+            ask_llm.attempt_options(1)["seed"],
+            ask_llm.attempt_options(2)["seed"]
+        );
+        let mut options = Map::new();
+        options.insert("seed".to_string(), 7.into());
+        let ask_llm = AskLLM::new()
+            .with_ollama_options(options)
+            .with_seed_variation(false);
+        assert_eq!(ask_llm.attempt_options(3)["seed"], 7);
+    }
+
+    #[test]
+    fn test_response_cache_replays_fully_answered_chunks() {
+        let (host, requests) = spawn_mock_server(|_| {
+            let body = r#"{"message":{"content":"1 -> ok"},"done_reason":"stop"}"#;
+            (200, body.to_string())
+        });
+        let config = AiConfig::default().with_ollama_host(host);
+        let ask_llm = AskLLM::with_config(&config).with_response_cache();
+        let vals = vec!["fine".to_string()];
+        let rt = RuntimeConfig::current_thread().build().unwrap();
+        let first = rt
+            .block_on(ask_llm.request_chunk("Categorize", &vals, 1, 0))
+            .unwrap();
+        let cache = ask_llm.response_cache.clone().unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        let second = rt
+            .block_on(ask_llm.request_chunk("Categorize", &vals, 1, 0))
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(cache.len(), 1);
+        // the replayed chunk makes no request
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_only_truncated_responses_keep_their_leading_answers() {
+        for done_reason in ["length", "stop"] {
+            let responses = AtomicUsize::new(0);
+            let (host, requests) = spawn_mock_server(move |_| {
+                let body = match responses.fetch_add(1, Ordering::SeqCst) {
+                    0 => json!({"message": {"content": "1 -> a"}, "done_reason": done_reason}),
+                    _ => json!({"message": {"content": "1 -> b"}, "done_reason": "stop"}),
+                };
+                (200, body.to_string())
+            });
+            let ask_llm = AskLLM::with_config(&AiConfig::default().with_ollama_host(host))
+                .with_truncation_mode(TruncationMode::KeepLeading);
+            let vals = vec!["fine".to_string(), "good".to_string()];
+            let rt = RuntimeConfig::current_thread().build().unwrap();
+            let answers = rt
+                .block_on(ask_llm.request_chunk("Categorize", &vals, 1, 0))
+                .unwrap();
+            if done_reason == "length" {
+                assert_eq!(answers, vec![Some("a".to_string()), Some("b".to_string())]);
+                assert_eq!(requests.load(Ordering::SeqCst), 2);
+            } else {
+                // the model skipped a row, so none of its answers can be trusted
+                assert!(answers.iter().all(|answer| {
+                    answer
+                        .as_deref()
+                        .is_some_and(|answer| answer.starts_with(MISMATCH_ERROR_PREFIX))
+                }));
+                assert_eq!(requests.load(Ordering::SeqCst), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejected_chunks_are_retried() {
+        let config = AiConfig::default().with_ollama_host(spawn_mock_ollama());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let validator_calls = calls.clone();
+        let ask_llm = AskLLM::with_config(&config)
+            .with_max_chunk_retries(1)
+            .with_response_cache()
+            .with_chunk_validator(move |inputs, outputs| {
+                validator_calls.fetch_add(1, Ordering::SeqCst);
+                assert_eq!(inputs, ["fine"]);
+                outputs != ["ok"]
+            });
+        let vals = vec!["fine".to_string()];
+        let rt = RuntimeConfig::current_thread().build().unwrap();
+        let outcome =
+            rt.block_on(ask_llm.process_chunk("Categorize", &vals, 1, &RetryBudget::new(None)));
+        assert!(
+            outcome
+                .unwrap_err()
+                .to_string()
+                .contains("rejected by validator")
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        // rejected responses are not replayed
+        assert!(ask_llm.response_cache.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_prompt_limit_counts_the_template_overhead() {
+        let overhead = get_prompt_overhead(ChatTemplate::ChatMl, "Be brief.", "Classify").len();
+        // room for the overhead and two short rows
+        let ask_llm = AskLLM::new()
+            .with_system_prompt("Be brief.")
+            .with_max_prompt_chars(overhead + 14);
+        let batch = [Some("a"), Some("b"), Some("c"), Some("d")];
+        assert_eq!(ask_llm.subdivide("Classify", &batch).len(), 1);
+        let ask_llm = ask_llm.with_chat_template(ChatTemplate::ChatMl);
+        let sizes: Vec<usize> = ask_llm
+            .subdivide("Classify", &batch)
+            .iter()
+            .map(|part| part.len())
+            .collect();
+        assert_eq!(sizes, vec![2, 2]);
+    }
+
+    #[test]
+    fn test_oversized_chunks_are_subdivided_in_order() {
+        let ask_llm = AskLLM::new()
+            .with_system_prompt("")
+            .with_max_prompt_chars(40);
+        let long = "x".repeat(20);
+        let batch = [Some("a"), Some(long.as_str()), Some("b"), Some("c"), None];
+        let parts = ask_llm.subdivide("Classify", &batch);
+        let sizes: Vec<usize> = parts.iter().map(|part| part.len()).collect();
+        assert_eq!(sizes, vec![1, 1, 3]);
+        assert_eq!(parts.concat(), batch);
+        // a single row over the limit is still sent
+        let too_long = "y".repeat(100);
+        assert_eq!(
+            ask_llm
+                .subdivide("Classify", &[Some(too_long.as_str())])
+                .len(),
+            1
+        );
+        assert_eq!(AskLLM::new().subdivide("Classify", &batch).len(), 1);
+        // with a word-count estimator the long row is a single unit
+        let words = LengthEstimator::new(|text| text.split_whitespace().count());
+        let ask_llm = ask_llm.with_max_prompt_tokens(5, words);
+        let sizes: Vec<usize> = ask_llm
+            .subdivide("Classify", &batch)
+            .iter()
+            .map(|part| part.len())
+            .collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+    }
+
+    #[test]
+    fn test_volatility_defaults_to_volatile() {
+        assert_eq!(AskLLM::new().signature().volatility, Volatility::Volatile);
+        let ask_llm = AskLLM::new().with_volatility(Volatility::Immutable);
+        assert_eq!(ask_llm.signature().volatility, Volatility::Immutable);
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast_propagates_backend_errors() {
+        let host = refused_host();
+        let sql = "SELECT ask_llm('Categorize', column1) FROM (VALUES ('Great!'), ('Awful.'))";
+        let config = AiConfig::default().with_ollama_host(host);
+
+        let batches = query(AskLLM::with_config(&config), sql).await;
+        assert_eq!(batches[0].num_rows(), 2, "lenient mode masks the error");
+
+        let ctx = datafusion::prelude::SessionContext::new();
+        ctx.register_udf(datafusion_expr::ScalarUDF::from(
+            AskLLM::with_config(&config).with_fail_fast(true),
+        ));
+        let error = ctx.sql(sql).await.unwrap().collect().await.unwrap_err();
+        assert!(error.to_string().contains("ask_llm failed"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_error_column_separates_backend_errors() {
+        let host = refused_host();
+        let config = AiConfig::default()
+            .with_ollama_host(host)
+            .with_max_chunk_retries(0);
+        let batches = query(
+            AskLLM::with_config(&config).with_error_column(),
+            "SELECT ask_llm('Categorize', column1) AS r FROM (VALUES ('Great!'))",
+        )
+        .await;
+        let result = batches[0].column(0).as_struct();
+        assert!(result.column_by_name("value").unwrap().is_null(0));
+        let errors = as_string_array(result.column_by_name("error").unwrap()).unwrap();
+        assert!(errors.value(0).starts_with("Error processing chunk"));
+    }
+
+    #[tokio::test]
+    async fn test_provenance_metadata_is_attached_to_the_answer_field() {
+        let config = AiConfig::default().with_ollama_host(spawn_mock_ollama());
+        let ask_llm = AskLLM::with_config(&config).with_provenance_metadata();
+        let expected = ask_llm.provenance_metadata("Categorize");
+        // without the instruction literal there is nothing to attach
+        let DataType::Struct(fields) = ask_llm.return_type(&[DataType::Utf8; 2]).unwrap() else {
+            panic!("expected a struct");
+        };
+        assert!(fields[0].metadata().is_empty());
+        let batches = query(
+            ask_llm,
+            "SELECT ask_llm('Categorize', column1) AS r FROM (VALUES ('Great!'))",
+        )
+        .await;
+        let schema = batches[0].schema();
+        let DataType::Struct(fields) = schema.field(0).data_type() else {
+            panic!("expected a struct, got {}", schema.field(0).data_type());
+        };
+        assert_eq!(fields[0].metadata(), &expected);
+        assert_eq!(expected["datafusion_ai.model"], config.chat_model);
+        assert_eq!(expected["datafusion_ai.instruction_sha256"].len(), 64);
+        let result = batches[0].column(0).as_struct();
+        assert_eq!(as_string_array(result.column(0)).unwrap().value(0), "ok");
+    }
+
+    // queries run invoke_with_args on a tokio worker, so the single request fast path
+    // must not block on a runtime from the calling thread
+    #[tokio::test]
+    async fn test_small_input_is_answered_inside_a_query() {
+        let config = AiConfig::default().with_ollama_host(spawn_mock_ollama());
+        let batches = query(
+            AskLLM::with_config(&config),
+            "SELECT ask_llm('Categorize', column1) AS r FROM (VALUES ('Great!'))",
+        )
+        .await;
+        let result = as_string_array(batches[0].column(0)).unwrap();
+        assert_eq!(result.value(0), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_model_column_names_the_serving_endpoint() {
+        let config = AiConfig::default().with_ollama_host(spawn_mock_ollama());
+        let batches = query(
+            AskLLM::with_config(&config).with_model_column(),
+            "SELECT ask_llm('Categorize', column1) AS r FROM (VALUES ('Great!'))",
+        )
+        .await;
+        let result = batches[0].column(0).as_struct();
+        let values = as_string_array(result.column_by_name("answer").unwrap()).unwrap();
+        assert_eq!(values.value(0), "ok");
+        let models = as_string_array(result.column_by_name("model").unwrap()).unwrap();
+        assert_eq!(models.value(0), config.chat_model);
+        let endpoints = as_string_array(result.column_by_name("endpoint").unwrap()).unwrap();
+        assert_eq!(endpoints.value(0), config.chat_urls()[0]);
+    }
+
+    #[test]
+    fn test_cost_callback_reports_every_completed_row() {
+        let config = AiConfig::default()
+            .with_ollama_host(spawn_mock_ollama())
+            .with_items_per_prompt(1);
+        let costs = Arc::new(Mutex::new(Vec::new()));
+        let reported = costs.clone();
+        let ask_llm = AskLLM::with_config(&config)
+            .with_cost_callback(TokenPricing::per_million(1.0, 2.0), move |cost| {
+                reported.lock().unwrap().push(cost.clone())
+            });
+        invoke(
+            &ask_llm,
+            "Categorize",
+            vec![strings(&["fine", "good", "great"])],
+        )
+        .unwrap();
+        let costs = costs.lock().unwrap();
+        assert_eq!(costs.len(), 3);
+        // the mock reports no token counts, so only the backend time is spent
+        assert!(costs.iter().all(|cost| cost.cost == 0.0));
+        assert!(
+            costs
+                .iter()
+                .all(|cost| cost.model.as_deref() == Some(config.chat_model.as_str()))
+        );
+
+        let usage = ChunkUsage {
+            tokens: TokenUsage {
+                prompt_tokens: 300,
+                completion_tokens: 30,
+            },
+            backend_time: Duration::from_secs(3),
+        };
+        let costs = row_costs(TokenPricing::per_million(1.0, 2.0), usage, None, 3);
+        assert_eq!(costs.len(), 3);
+        assert_eq!(costs[0].prompt_tokens, 100.0);
+        assert_eq!(costs[0].backend_time, Duration::from_secs(1));
+        assert!((costs.iter().map(|cost| cost.cost).sum::<f64>() - 0.00036).abs() < 1e-12);
+    }
+
+    #[tokio::test]
+    async fn test_routed_rows_are_answered_by_the_premium_model() {
+        let config = AiConfig::default().with_ollama_host(spawn_mock_ollama());
+        let premium = AskLLM::with_config(&config.clone().with_chat_model("llama3.1:70b"));
+        let ask_llm = AskLLM::with_config(&config)
+            .with_routing(premium, Routing::predicate(|value| value.len() > 10))
+            .with_model_column();
+        let batches = query(
+            ask_llm,
+            "SELECT ask_llm('Categorize', column1) AS r \
+             FROM (VALUES ('Great!'), ('A long and thoughtful review'))",
+        )
+        .await;
+        let result = batches[0].column(0).as_struct();
+        let values = as_string_array(result.column_by_name("answer").unwrap()).unwrap();
+        assert_eq!(values.value(0), "ok");
+        assert_eq!(values.value(1), "ok");
+        let models = as_string_array(result.column_by_name("model").unwrap()).unwrap();
+        assert_eq!(models.value(0), config.chat_model);
+        assert_eq!(models.value(1), "llama3.1:70b");
+    }
+
+    #[tokio::test]
+    async fn test_failed_chunks_fall_back_to_the_secondary_model() {
+        // the primary refuses every request
+        let config = AiConfig::default()
+            .with_ollama_host(refused_host())
+            .with_fallback("llama3.2:1b", spawn_mock_ollama());
+        let batches = query(
+            AskLLM::with_config(&config).with_model_column(),
+            "SELECT ask_llm('Categorize', column1) AS r FROM (VALUES ('Great!'))",
+        )
+        .await;
+        let result = batches[0].column(0).as_struct();
+        let values = as_string_array(result.column_by_name("answer").unwrap()).unwrap();
+        assert_eq!(values.value(0), "ok");
+        let models = as_string_array(result.column_by_name("model").unwrap()).unwrap();
+        assert_eq!(models.value(0), "llama3.2:1b");
+    }
+
+    #[test]
+    fn test_dry_run_plan_counts_calls_without_the_model() {
+        let ask_llm = AskLLM::with_config(&AiConfig::default().with_items_per_prompt(2))
+            .with_blank_input(BlankInput::Whitespace)
+            .with_answer_cache();
+        let values = [
+            Some("Great!"),
+            None,
+            Some("Awful."),
+            Some("Great!"),
+            Some("Fine"),
+        ];
+        let estimate = ask_llm.dry_run_plan("Categorize", &values);
+        assert_eq!(estimate.rows, 5);
+        assert_eq!(estimate.local_rows, 1);
+        assert_eq!(estimate.cached_rows, 1);
+        assert_eq!(estimate.prompted_rows, 3);
+        assert_eq!(estimate.calls, 2);
+        assert!(estimate.prompt_tokens > 0);
+        assert_eq!(estimate.completion_tokens, 3 * COMPLETION_TOKENS_PER_ROW);
+
+        // in dry-run mode the UDF records the estimate and answers NULL
+        let dry_run = DryRun::new();
+        let ask_llm = ask_llm.with_dry_run(dry_run.clone());
+        let answers = ask_llm
+            .evaluate("Categorize", &values, &Invocation::new(None))
+            .unwrap();
+        assert!(answers.iter().all(|row| row.answer.is_none()));
+        assert_eq!(dry_run.total(), estimate);
+    }
+
+    #[test]
+    fn test_plan_summary_describes_the_invocation() {
+        let ask_llm =
+            AskLLM::with_config(&AiConfig::default().with_items_per_prompt(2)).with_answer_cache();
+        let values = [Some("Great!"), Some("Awful."), Some("Great!"), None];
+        let summary = ask_llm.plan_summary("Categorize", &values);
+        assert!(summary.contains("2 rows per prompt"), "{summary}");
+        assert!(summary.contains("answer cache on"), "{summary}");
+        assert!(
+            summary.contains("2 distinct of 3 values (33% duplicates)"),
+            "{summary}"
+        );
+        let estimate = ask_llm.dry_run_plan("Categorize", &values);
+        assert!(
+            summary.ends_with(&format!("estimate: {estimate}")),
+            "{summary}"
+        );
+    }
+
+    #[test]
+    fn test_small_inputs_are_sent_in_a_single_request() {
+        let values = [Some("a"), Some("b"), Some("c"), Some("d"), Some("e")];
+        let ask_llm = AskLLM::with_config(&AiConfig::default().with_items_per_prompt(2));
+        assert_eq!(ask_llm.dry_run_plan("Categorize", &values).calls, 3);
+        let ask_llm = ask_llm.with_single_request_threshold(5);
+        assert_eq!(ask_llm.dry_run_plan("Categorize", &values).calls, 1);
+        // more rows, or a prompt over the limit, use the chunked path
+        assert_eq!(ask_llm.dry_run_plan("Categorize", &[Some("a"); 6]).calls, 3);
+        let ask_llm = ask_llm.with_max_prompt_chars(ASK_LLM_SYSTEM_PROMPT.len() + 20);
+        assert!(!ask_llm.fits_single_request("Categorize", &values));
+    }
+
+    // answers `key: value` lines, with `-` for a skipped row
+    #[derive(Debug)]
+    struct ColonParser;
+
+    impl ResponseParser for ColonParser {
+        fn parse(&self, raw: &str, _expected: usize) -> Vec<Option<String>> {
+            raw.lines()
+                .filter_map(|line| line.split_once(':'))
+                .map(|(_, value)| Some(value.trim()).filter(|v| *v != "-").map(String::from))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_sentinel_answers_become_null() {
+        let vals = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let answers = vec![
+            Some("no response content ".to_string()),
+            Some("N/A".to_string()),
+            Some("yes".to_string()),
+        ];
+        assert_eq!(
+            AskLLM::new().post_process_answers(&vals, answers.clone()),
+            vec![None, Some("N/A".to_string()), Some("yes".to_string())]
+        );
+        let ask_llm = AskLLM::new().with_null_sentinels(["N/A"]);
+        assert_eq!(
+            ask_llm.post_process_answers(&vals, answers),
+            vec![
+                Some("no response content ".to_string()),
+                None,
+                Some("yes".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict_categories_gate_labels_and_agreement() {
+        let vals = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let answers = vec![
+            Some("POSITIVE ".to_string()),
+            Some("somewhat positive".to_string()),
+            Some("N/A".to_string()),
+        ];
+        let ask_llm = AskLLM::new()
+            .with_null_sentinels(["N/A"])
+            .with_strict_categories(CategoricalGate::new(["Positive", "Negative"]));
+        assert_eq!(
+            ask_llm.post_process_answers(&vals, answers),
+            vec![Some("Positive".to_string()), None, None]
+        );
+
+        // the mock answers `ok` to every sample, so the row is kept only when `ok` is
+        // a label, however high the agreement threshold
+        let config = AiConfig::default().with_ollama_host(spawn_mock_ollama());
+        let answer = |gate: CategoricalGate| {
+            let ask_llm = AskLLM::with_config(&config)
+                .with_self_consistency(SelfConsistency::new(3))
+                .with_strict_categories(gate);
+            let result = invoke(&ask_llm, "Categorize", vec![strings(&["fine"])]).unwrap();
+            let answers = as_string_array(result.as_struct().column(0))
+                .unwrap()
+                .clone();
+            (!answers.is_null(0)).then(|| answers.value(0).to_string())
+        };
+        assert_eq!(
+            answer(CategoricalGate::new(["OK", "Bad"]).with_min_agreement(1.0)),
+            Some("OK".to_string())
+        );
+        assert_eq!(answer(CategoricalGate::new(["Good", "Bad"])), None);
+    }
+
+    #[test]
+    fn test_custom_response_parser_can_leave_gaps() {
+        assert_eq!(
+            ArrowParser.parse("1 -> yes\nnoise\n2 -> no", 2),
+            vec![Some("yes".to_string()), Some("no".to_string())]
+        );
+        let ask_llm = AskLLM::new()
+            .with_response_parser(ColonParser)
+            .with_normalization(Normalization::categorical());
+        let vals = vec!["a".to_string(), "b".to_string()];
+        let answers = ColonParser.parse("1: Yes\n2: -", 2);
+        assert_eq!(
+            ask_llm.post_process_answers(&vals, answers),
+            vec![Some("yes".to_string()), None]
+        );
+    }
+
+    #[test]
+    fn test_row_numbers_follow_the_rows_sent() {
+        for global in [false, true] {
+            let prompts = Arc::new(Mutex::new(Vec::new()));
+            let sent = prompts.clone();
+            let (host, _) = spawn_mock_server(move |request| {
+                let mut sent = sent.lock().unwrap();
+                sent.push(request.to_string());
+                // the first response is cut off after one answer
+                let body = if sent.len() == 1 {
+                    json!({"message": {"content": "1 -> ok"}, "done_reason": "length"})
+                } else {
+                    json!({"message": {"content": "1 -> ok\n2 -> ok"}, "done_reason": "stop"})
+                };
+                (200, body.to_string())
+            });
+            let config = AiConfig::default()
+                .with_ollama_host(host)
+                .with_windowing(WindowingConfig::new(10, 10, 0));
+            let ask_llm = AskLLM::with_config(&config)
+                .with_truncation_mode(TruncationMode::KeepLeading)
+                .with_global_row_numbers(global);
+            let rt = RuntimeConfig::current_thread().build().unwrap();
+            let vals = vec!["fine".to_string(), "good".to_string()];
+            let answers = rt
+                .block_on(ask_llm.request_chunk("Categorize", &vals, 1, 0))
+                .unwrap();
+            assert_eq!(answers.len(), 2);
+            // the row re-requested after the truncated response
+            let expected = if global { "2. good" } else { "1. good" };
+            assert!(prompts.lock().unwrap()[1].contains(expected), "{prompts:?}");
+
+            // the row after an oversized (windowed) row
+            prompts.lock().unwrap().clear();
+            let vals = vec!["a".to_string(), "x".repeat(15), "b".to_string()];
+            let answers = rt
+                .block_on(ask_llm.process_values("Categorize", &vals, 1, &RetryBudget::new(None)))
+                .unwrap();
+            assert_eq!(answers.len(), 3);
+            let expected = if global { "3. b" } else { "2. b" };
+            assert!(
+                prompts
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|prompt| prompt.contains(expected)),
+                "{prompts:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_skipped_rows_are_not_reprompted() {
+        let (host, requests) = spawn_mock_server(|_| {
+            let body = r#"{"message":{"content":"1: -\n2: -"},"done_reason":"stop"}"#;
+            (200, body.to_string())
+        });
+        let ask_llm = AskLLM::with_config(&AiConfig::default().with_ollama_host(host))
+            .with_response_parser(ColonParser);
+        let vals = vec!["a".to_string(), "b".to_string()];
+        let rt = RuntimeConfig::current_thread().build().unwrap();
+        let answers = rt
+            .block_on(ask_llm.request_chunk("Categorize", &vals, 1, 0))
+            .unwrap();
+        // every row was parsed as skipped, which is an answer rather than a format error
+        assert_eq!(answers, vec![None, None]);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_regex_parser_extracts_the_value_group() {
+        // numbered lines, ignoring an explanation in parentheses
+        let parser = RegexParser::new(r"(?m)^\d+[.)]\s*(?P<value>[^(\n]+?)\s*(\(.*\))?$").unwrap();
+        assert_eq!(
+            parser.parse("Sure!\n1. positive (praises delivery)\n2) negative\n", 2),
+            vec![Some("positive".to_string()), Some("negative".to_string())]
+        );
+        // scores in `<score>` tags, anywhere in the text
+        let parser = RegexParser::new(r"<score>(?P<value>\d+)</score>").unwrap();
+        assert_eq!(
+            parser.parse("first <score>4</score>, second <score>2</score>, done", 2),
+            vec![Some("4".to_string()), Some("2".to_string())]
+        );
+        // an optional group that did not match is a skipped row
+        let parser = RegexParser::new(r"(?m)^\d+: (?:-|(?P<value>.+))$").unwrap();
+        assert_eq!(
+            parser.parse("1: yes\n2: -", 2),
+            vec![Some("yes".to_string()), None]
+        );
+
+        assert!(RegexParser::new(r"(\d+").is_err());
+        assert!(RegexParser::new(r"(?P<answer>\w+)").is_err());
+    }
+}
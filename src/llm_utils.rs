@@ -11,12 +11,16 @@ use std::collections::VecDeque;
 use std::{
     num::NonZeroU32,
     pin::pin,
-    sync::{Arc, Mutex, RwLock},
+    sync::{Arc, Mutex, RwLock, Weak},
 };
 
+use crate::cancellation::Cancellation;
+use crate::chat_template::ChatTemplate;
+
+// fields drop in declaration order, so the model is freed before the backend
 struct LlamaResources {
-    backend: LlamaBackend,
     model: LlamaModel,
+    backend: LlamaBackend,
 }
 
 impl std::fmt::Debug for LlamaResources {
@@ -25,15 +29,41 @@ impl std::fmt::Debug for LlamaResources {
     }
 }
 
-static LLAMA_RESOURCES: tokio::sync::OnceCell<Mutex<LlamaResources>> =
-    tokio::sync::OnceCell::const_new();
+// The loaded model is process-global; `None` when no model is loaded.
+static LLAMA_RESOURCES: Mutex<Option<Arc<LlamaResources>>> = Mutex::new(None);
+
+/// Counts tokens with the tokenizer of the model that was loaded when it was created,
+/// see [`LlamaApp::token_counter`]. Counting does not take the model lock, so it does
+/// not wait for running generations, and the counter does not keep the model loaded.
+#[derive(Debug, Clone, Default)]
+pub struct TokenCounter(Weak<LlamaResources>);
+
+impl TokenCounter {
+    /// The number of tokens `text` is split into, without a BOS token. Returns `None`
+    /// if the model has been unloaded or `text` cannot be tokenized.
+    pub fn count(&self, text: &str) -> Option<usize> {
+        let resources = self.0.upgrade()?;
+        let tokens = resources.model.str_to_token(text, AddBos::Never).ok()?;
+        Some(tokens.len())
+    }
+}
 
 #[derive(Debug)]
-pub struct LlamaApp {}
+pub struct LlamaApp {
+    batch_capacity: Option<usize>,
+    deterministic: bool,
+    cancellation: Option<Cancellation>,
+}
 
 impl LlamaApp {
     /// Creates a new instance by loading a given model file from disk.
+    /// Fails if a model is already loaded; call [`LlamaApp::unload`] first to switch models.
     pub fn new(model_path: &str) -> anyhow::Result<Self> {
+        let mut loaded = LLAMA_RESOURCES.lock().unwrap();
+        if loaded.is_some() {
+            anyhow::bail!("a LLaMA model is already loaded, unload it before loading another");
+        }
+
         // Initialize the backend
         let mut backend = LlamaBackend::init().context("Failed to initialize LLaMA backend")?;
         backend.void_logs(); // => remove this line if you want to see the logs
@@ -43,27 +73,119 @@ impl LlamaApp {
         let model = LlamaModel::load_from_file(&backend, model_path, &model_params)
             .with_context(|| format!("Unable to load model from path: {}", model_path))?;
 
-        let resources = LlamaResources { backend, model };
-        LLAMA_RESOURCES.set(Mutex::new(resources)).unwrap();
+        *loaded = Some(Arc::new(LlamaResources { backend, model }));
+
+        Ok(Self {
+            batch_capacity: None,
+            deterministic: false,
+            cancellation: None,
+        })
+    }
+
+    /// Sets how many prompt tokens are decoded per batch. Prompts longer than this are
+    /// decoded in several batches. Must be between 1 and the context size passed to
+    /// `generate_text`. Defaults to the prompt length, with a minimum of 64.
+    pub fn with_batch_capacity(mut self, batch_capacity: usize) -> Self {
+        self.batch_capacity = Some(batch_capacity);
+        self
+    }
+
+    /// Uses pure greedy decoding, always picking the most likely token, so the same
+    /// prompt always produces the same output, e.g. for tests and golden files. This
+    /// overrides the sampler: `temp` and `seed` are ignored.
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Checks `cancellation` before every generated token. Once it is cancelled, a
+    /// running generation stops and returns the tokens generated so far, and later
+    /// generations return no tokens, so a caller can stop a runaway generation from
+    /// another thread, e.g. on a timeout or at shutdown.
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(Cancellation::is_cancelled)
+    }
+
+    /// Detects the chat template of the loaded model from its GGUF metadata.
+    /// Returns `None` if no model is loaded or the template is not recognized.
+    pub fn detect_chat_template(&self) -> Option<ChatTemplate> {
+        let loaded = LLAMA_RESOURCES.lock().unwrap();
+        let template = loaded
+            .as_ref()?
+            .model
+            .meta_val_str("tokenizer.chat_template")
+            .ok()?;
+        ChatTemplate::detect(&template)
+    }
+
+    /// Releases the loaded model and the llama.cpp backend, freeing their CPU/GPU memory.
+    ///
+    /// Generations hold the model lock for their whole duration, so this blocks until
+    /// any in-flight generation finishes and never frees memory still in use. After
+    /// unloading, every `LlamaApp` instance returns an error from `generate_text` until
+    /// a model is loaded again with [`LlamaApp::new`]. Returns `false` if no model was loaded.
+    /// A [`TokenCounter`] that is counting at that moment delays the release until it
+    /// is done.
+    pub fn unload() -> bool {
+        LLAMA_RESOURCES.lock().unwrap().take().is_some()
+    }
 
-        Ok(Self {})
+    /// Returns a [`TokenCounter`] for the loaded model, which counts nothing if no
+    /// model is loaded.
+    pub fn token_counter() -> TokenCounter {
+        let loaded = LLAMA_RESOURCES.lock().unwrap();
+        TokenCounter(loaded.as_ref().map(Arc::downgrade).unwrap_or_default())
     }
 
     /// Generates text given a prompt.
-    /// This reuses the model + context stored in `self`.
+    /// This reuses the model + context stored in `self`. With `stop`, generation ends
+    /// as soon as the expected number of lines is complete, see [`LineStop`].
     pub fn generate_text(
         &self,
         prompt: &str,
         ctx_size: u32,
         temp: f32,
         seed: Option<u32>,
+        stop: Option<&LineStop>,
     ) -> anyhow::Result<String> {
-        let ctx_params =
+        let tokens = self.generate_text_with_logprobs(prompt, ctx_size, temp, seed, None, stop)?;
+        Ok(tokens.into_iter().map(|token| token.text).collect())
+    }
+
+    /// Like [`LlamaApp::generate_text`], but returns every generated token together
+    /// with its log-probability under the model's output distribution. At most
+    /// `max_new_tokens` tokens are generated; `None` lets generation run until the
+    /// context is full. Generation also stops early, keeping the partial output, when
+    /// the cancellation handle is cancelled (see [`LlamaApp::with_cancellation`]).
+    pub fn generate_text_with_logprobs(
+        &self,
+        prompt: &str,
+        ctx_size: u32,
+        temp: f32,
+        seed: Option<u32>,
+        max_new_tokens: Option<usize>,
+        stop: Option<&LineStop>,
+    ) -> anyhow::Result<Vec<GeneratedToken>> {
+        let mut ctx_params =
             LlamaContextParams::default().with_n_ctx(Some(NonZeroU32::new(ctx_size).unwrap()));
+        if let Some(batch_capacity) = self.batch_capacity {
+            validate_batch_capacity(batch_capacity, ctx_size)?;
+            ctx_params = ctx_params.with_n_batch(batch_capacity as u32);
+        }
 
-        let resources = LLAMA_RESOURCES.get().unwrap().lock().unwrap();
+        let loaded = LLAMA_RESOURCES.lock().unwrap();
+        let resources = loaded
+            .as_ref()
+            .context("No LLaMA model is loaded, it may have been unloaded")?;
 
-        let output_text = {
+        let output_tokens = {
             // Create a context for this model
             let mut ctx = resources
                 .model
@@ -71,7 +193,11 @@ impl LlamaApp {
                 .context("Unable to create LLaMA context")?;
 
             // Build a sampler (decides how to pick tokens)
-            let mut sampler = build_sampler(seed, temp);
+            let mut sampler = if self.deterministic {
+                LlamaSampler::greedy()
+            } else {
+                build_sampler(seed, temp)
+            };
 
             // Convert prompt to tokens (including a BOS token at the start)
             let tokens = resources
@@ -82,23 +208,36 @@ impl LlamaApp {
             let prompt_length = tokens.len() as i32;
 
             // Prepare batch
-            let batch_size = std::cmp::max(prompt_length, 64) as usize;
+            let batch_size = self
+                .batch_capacity
+                .unwrap_or(std::cmp::max(prompt_length, 64) as usize);
             let mut batch = LlamaBatch::new(batch_size, 1);
             let last_index = prompt_length - 1;
-            for (i, token) in (0_i32..).zip(tokens.into_iter()) {
-                let is_last = i == last_index;
-                batch.add(token, i, &[0], is_last)?;
-            }
 
-            // Decode the prompt (feed prompt tokens into context)
-            ctx.decode(&mut batch)?;
+            // Decode the prompt (feed prompt tokens into context), one batch at a time
+            // when the prompt is longer than the batch capacity
+            for (offset, piece) in (0_i32..).step_by(batch_size).zip(tokens.chunks(batch_size)) {
+                batch.clear();
+                for (i, &token) in (offset..).zip(piece) {
+                    let is_last = i == last_index;
+                    batch.add(token, i, &[0], is_last)?;
+                }
+                ctx.decode(&mut batch)?;
+            }
 
             // Main generation loop: repeatedly sample the next token
-            let mut output_text = String::new();
-            let max_generation_tokens = (ctx_size as i32) - prompt_length;
-            let mut n_cur = batch.n_tokens();
+            let mut output_tokens = Vec::new();
+            let max_generation_tokens =
+                generation_budget(ctx_size, prompt_length as usize, max_new_tokens);
+            let mut n_cur = prompt_length;
+            let mut lines = stop.map(LineStop::counter);
             // We'll generate until we hit max tokens or an EOG (end-of-generation) token
-            while n_cur <= max_generation_tokens {
+            while output_tokens.len() < max_generation_tokens {
+                // 0) Stop early if the caller gave up on this generation
+                if self.is_cancelled() {
+                    break;
+                }
+
                 // 1) Sample next token
                 let token = sampler.sample(&ctx, batch.n_tokens() - 1);
                 // Accept the token (update internal state in the sampler, if any)
@@ -110,35 +249,196 @@ impl LlamaApp {
                     break;
                 }
 
-                // 3) Convert token to UTF-8 and append to output
+                // 3) Log-probability of the sampled token (log-softmax over the raw logits)
+                let logits = ctx.get_logits_ith(batch.n_tokens() - 1);
+                let logprob = token_logprob(logits, token.0 as usize);
+
+                // 4) Convert token to UTF-8 and append to output
                 let token_bytes = resources.model.token_to_bytes(token, Special::Tokenize)?;
                 let mut decode_buffer = String::with_capacity(32);
                 {
                     let mut decoder = UTF_8.new_decoder();
                     let _ = decoder.decode_to_string(&token_bytes, &mut decode_buffer, false);
                 }
-                output_text.push_str(&decode_buffer);
+                // 5) Stop once the expected lines are complete; the line break that
+                // completes the last one is kept
+                let lines_complete = lines
+                    .as_mut()
+                    .is_some_and(|lines| lines.push(&decode_buffer));
+                output_tokens.push(GeneratedToken {
+                    text: decode_buffer,
+                    logprob,
+                });
+                if lines_complete {
+                    break;
+                }
 
-                // 4) Feed the newly generated token back into the model so it can predict the next one
+                // 6) Feed the newly generated token back into the model so it can predict the next one
                 batch.clear();
                 batch.add(token, n_cur, &[0], true)?;
                 ctx.decode(&mut batch)?;
 
                 n_cur += 1;
             }
-            output_text
+            output_tokens
         }; // context is dropped here
 
-        Ok(output_text)
+        Ok(output_tokens)
+    }
+}
+
+// tokens that may follow a prompt: the rest of the context, optionally capped
+fn generation_budget(ctx_size: u32, prompt_length: usize, max_new_tokens: Option<usize>) -> usize {
+    let room = (ctx_size as usize).saturating_sub(prompt_length);
+    max_new_tokens.map_or(room, |max_new_tokens| max_new_tokens.min(room))
+}
+
+fn validate_batch_capacity(batch_capacity: usize, ctx_size: u32) -> anyhow::Result<()> {
+    if batch_capacity == 0 || batch_capacity > ctx_size as usize {
+        anyhow::bail!(
+            "batch capacity must be between 1 and the context size ({}), got {}",
+            ctx_size,
+            batch_capacity
+        );
+    }
+    Ok(())
+}
+
+type LineDetector = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Stops a generation once it has produced a number of complete lines, e.g. one
+/// answer line per row of a chunk, so tokens are not spent on whatever the model adds
+/// after the list. Only lines accepted by the line detector count, and a line is
+/// complete at its line break. This applies on top of the other ways generation ends:
+/// the model's end-of-generation token, the token cap and cancellation, whichever
+/// comes first.
+#[derive(Clone)]
+pub struct LineStop {
+    lines: usize,
+    is_line: LineDetector,
+}
+
+impl std::fmt::Debug for LineStop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LineStop")
+            .field("lines", &self.lines)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LineStop {
+    /// Stops after `lines` non-blank lines.
+    pub fn new(lines: usize) -> Self {
+        Self {
+            lines,
+            is_line: Arc::new(|line| !line.trim().is_empty()),
+        }
+    }
+
+    /// Stops after `lines` answer lines in the `n -> answer` format, so a preamble such
+    /// as `Here are the answers:` does not count.
+    pub fn answer_lines(lines: usize) -> Self {
+        Self::new(lines).with_line_detector(|line| line.contains("->"))
+    }
+
+    /// Counts only the lines for which `is_line` returns true.
+    pub fn with_line_detector(
+        mut self,
+        is_line: impl Fn(&str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.is_line = Arc::new(is_line);
+        self
+    }
+
+    fn counter(&self) -> LineCounter<'_> {
+        LineCounter {
+            stop: self,
+            line: String::new(),
+            complete: 0,
+        }
     }
 }
 
+// counts the complete lines of a generation as its tokens arrive
+struct LineCounter<'a> {
+    stop: &'a LineStop,
+    line: String,
+    complete: usize,
+}
+
+impl LineCounter<'_> {
+    // adds the text of the next token, returns whether the expected lines are complete
+    fn push(&mut self, text: &str) -> bool {
+        let mut parts = text.split('\n');
+        self.line.push_str(parts.next().unwrap_or_default());
+        for part in parts {
+            if (self.stop.is_line)(&self.line) {
+                self.complete += 1;
+            }
+            self.line.clear();
+            self.line.push_str(part);
+        }
+        self.complete >= self.stop.lines
+    }
+}
+
+/// A single generated token and its log-probability.
+#[derive(Debug, Clone)]
+pub struct GeneratedToken {
+    pub text: String,
+    pub logprob: f32,
+}
+
+/// Log-probability of `token` given the raw logits, i.e. `logits[token] - logsumexp(logits)`.
+fn token_logprob(logits: &[f32], token: usize) -> f32 {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let sum: f32 = logits.iter().map(|logit| (logit - max).exp()).sum();
+    logits
+        .get(token)
+        .map_or(f32::NEG_INFINITY, |logit| logit - max - sum.ln())
+}
+
+/// Groups generated tokens into lines and returns each non-empty line with its
+/// confidence, the geometric mean of its token probabilities (`exp(mean logprob)`).
+pub fn line_confidences(tokens: &[GeneratedToken]) -> Vec<(String, f64)> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut logprobs: Vec<f32> = Vec::new();
+    let mut flush = |line: &mut String, logprobs: &mut Vec<f32>| {
+        if !line.trim().is_empty() && !logprobs.is_empty() {
+            let mean = logprobs.iter().map(|lp| *lp as f64).sum::<f64>() / logprobs.len() as f64;
+            lines.push((line.trim().to_string(), mean.exp()));
+        }
+        line.clear();
+        logprobs.clear();
+    };
+    for token in tokens {
+        let parts: Vec<&str> = token.text.split('\n').collect();
+        // a token spanning a line break counts towards the last line it has text on
+        let owner = parts.iter().rposition(|part| !part.is_empty()).unwrap_or(0);
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                flush(&mut line, &mut logprobs);
+            }
+            line.push_str(part);
+            if i == owner {
+                logprobs.push(token.logprob);
+            }
+        }
+    }
+    flush(&mut line, &mut logprobs);
+    lines
+}
+
+/// The sampler seed used when none is given.
+pub const DEFAULT_SEED: u32 = 1234;
+
 /// Build the sampler (decides how to pick next tokens).
 fn build_sampler(seed: Option<u32>, temp: f32) -> LlamaSampler {
     // A sampler pipeline: random distribution + greedy pick.
     // You can extend or replace with your own logic (top-k, top-p, etc.)
     let sampler = LlamaSampler::chain_simple([
-        LlamaSampler::dist(seed.unwrap_or(1234)),
+        LlamaSampler::dist(seed.unwrap_or(DEFAULT_SEED)),
         LlamaSampler::greedy(),
         LlamaSampler::temp(temp),
         //LlamaSampler::min_p(0.2, 10),
@@ -146,8 +446,45 @@ fn build_sampler(seed: Option<u32>, temp: f32) -> LlamaSampler {
     sampler
 }
 
+/// The default system prompt for the local llama.cpp model, used by [`get_prompt`] and
+/// `ask_llm_confidence`, which asks for exactly one value per item and nothing else.
+pub const SYSTEM_PROMPT: &str = "You are an AI evaluator that processes lists of items according to specific criteria.
+Always respond with ONLY comma-separated values matching the exact number and order of input items. 
+For ratings, use only the specified numbers, or categories, For yes/no questions, use only 'yes' or 'no'.";
+
 /// Helper function to create a prompt for the LLM
 pub fn get_prompt(instruction: &str, column_values: &[String]) -> String {
+    get_prompt_with_template(ChatTemplate::default(), instruction, column_values)
+}
+
+/// Same as [`get_prompt`], rendered with the special tokens of `template`.
+pub fn get_prompt_with_template(
+    template: ChatTemplate,
+    instruction: &str,
+    column_values: &[String],
+) -> String {
+    get_prompt_with_system(template, SYSTEM_PROMPT, instruction, column_values)
+}
+
+/// The part of [`get_prompt_with_system`] that is the same for every chunk: the
+/// template's special tokens, the system prompt and the instruction, rendered without
+/// any values. A chunk's prompt is this plus one `n. value` line per value, so size
+/// limits can budget the values against what is left.
+pub fn get_prompt_overhead(
+    template: ChatTemplate,
+    system_prompt: &str,
+    instruction: &str,
+) -> String {
+    get_prompt_with_system(template, system_prompt, instruction, &[])
+}
+
+/// Same as [`get_prompt_with_template`] with a custom system prompt.
+pub fn get_prompt_with_system(
+    template: ChatTemplate,
+    system_prompt: &str,
+    instruction: &str,
+    column_values: &[String],
+) -> String {
     let column_values_str = column_values
         .iter()
         .enumerate()
@@ -155,24 +492,33 @@ pub fn get_prompt(instruction: &str, column_values: &[String]) -> String {
         .collect::<Vec<_>>()
         .join("\n");
 
-    format!(
-        r#"
-<|begin_of_text|><|start_header_id|>system<|end_header_id|>
-You are an AI evaluator that processes lists of items according to specific criteria.
-Always respond with ONLY comma-separated values matching the exact number and order of input items. 
-For ratings, use only the specified numbers, or categories, For yes/no questions, use only 'yes' or 'no'.
-<|eot_id|><|start_header_id|>user<|end_header_id|>
-{instruction}:
-{column_values_str}
-<|eot_id|><|start_header_id|>assistant<|end_header_id|>
-"#
+    template.render(
+        system_prompt,
+        &format!("{instruction}:\n{column_values_str}"),
     )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_prompt_overhead_excludes_only_the_values() {
+        let overhead = get_prompt_overhead(ChatTemplate::ChatMl, SYSTEM_PROMPT, "Classify");
+        assert!(overhead.contains(SYSTEM_PROMPT));
+        let prompt = get_prompt_with_system(
+            ChatTemplate::ChatMl,
+            SYSTEM_PROMPT,
+            "Classify",
+            &["a".to_string()],
+        );
+        assert_eq!(prompt.len(), overhead.len() + "1. a".len());
+    }
+
+    // needs the GGUF model, and the model is process-global, so run the model tests with
+    // `cargo test -- --ignored --test-threads=1`
     #[test]
+    #[ignore]
     fn test_llama_app_creation() {
         // Replace with a path to a real model for testing
         let model_path = "models/llama_df_ai.Q4_K_M.gguf";
@@ -186,8 +532,167 @@ mod tests {
             ],
         );
         println!("prompt: {}", prompt);
-        let res = llama_app.generate_text(&prompt, 512, 0.1, None).unwrap();
+        let res = llama_app
+            .generate_text(&prompt, 512, 0.1, None, None)
+            .unwrap();
         println!("res: {}", res);
+        LlamaApp::unload();
         assert_eq!(res.trim(), "1 -> likely\n2 -> unlikely\n3 -> likely");
     }
+
+    #[test]
+    #[ignore]
+    fn test_long_prompt_with_small_batch_capacity() {
+        // a prompt well over 64 tokens, decoded 16 tokens at a time
+        let model_path = "models/llama_df_ai.Q4_K_M.gguf";
+        let llama_app = LlamaApp::new(model_path).unwrap().with_batch_capacity(16);
+        let reviews: Vec<String> = (0..20)
+            .map(|i| format!("Review number {i}: the delivery was fast and the product works well"))
+            .collect();
+        let prompt = get_prompt(
+            "Categorize customer feedback as positive or negative",
+            &reviews,
+        );
+        let res = llama_app
+            .generate_text(&prompt, 2048, 0.1, None, None)
+            .unwrap();
+        LlamaApp::unload();
+        assert!(res.contains("->"));
+    }
+
+    #[test]
+    #[ignore]
+    fn test_small_token_cap_truncates_generation() {
+        let model_path = "models/llama_df_ai.Q4_K_M.gguf";
+        let llama_app = LlamaApp::new(model_path).unwrap();
+        let prompt = get_prompt(
+            "Categorize customer feedback as positive or negative",
+            &(0..10)
+                .map(|i| format!("Review {i}: great"))
+                .collect::<Vec<_>>(),
+        );
+        let tokens = llama_app
+            .generate_text_with_logprobs(&prompt, 2048, 0.1, None, Some(4), None)
+            .unwrap();
+        LlamaApp::unload();
+        assert!(tokens.len() <= 4);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_deterministic_generation_is_reproducible() {
+        let model_path = "models/llama_df_ai.Q4_K_M.gguf";
+        let llama_app = LlamaApp::new(model_path).unwrap().with_deterministic(true);
+        let prompt = get_prompt(
+            "Describe the customer's mood in a few words",
+            &["The package arrived late but support was helpful".to_string()],
+        );
+        // a high temperature and different seeds would vary the output if they applied
+        let first = llama_app
+            .generate_text(&prompt, 512, 1.5, Some(1), None)
+            .unwrap();
+        let second = llama_app
+            .generate_text(&prompt, 512, 1.5, Some(2), None)
+            .unwrap();
+        LlamaApp::unload();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_cancelled_generation_stops() {
+        let model_path = "models/llama_df_ai.Q4_K_M.gguf";
+        let cancellation = Cancellation::new();
+        let llama_app = LlamaApp::new(model_path)
+            .unwrap()
+            .with_cancellation(cancellation.clone());
+        let prompt = get_prompt(
+            "Write a long story about this customer",
+            &["The package arrived late but support was helpful".to_string()],
+        );
+        cancellation.cancel();
+        let tokens = llama_app
+            .generate_text_with_logprobs(&prompt, 2048, 0.1, None, None, None)
+            .unwrap();
+        LlamaApp::unload();
+        assert!(tokens.is_empty());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_generation_stops_after_the_expected_lines() {
+        let model_path = "models/llama_df_ai.Q4_K_M.gguf";
+        let llama_app = LlamaApp::new(model_path).unwrap();
+        // asks for 3 answers but invites the model to keep writing afterwards
+        let prompt = get_prompt(
+            "Categorize customer feedback as positive or negative, then explain each answer at length",
+            &[
+                "Excellent experience!".to_string(),
+                "Wrong item delivered.".to_string(),
+                "Fast delivery, great service!".to_string(),
+            ],
+        );
+        let stop = LineStop::answer_lines(3);
+        let res = llama_app
+            .generate_text(&prompt, 2048, 0.1, None, Some(&stop))
+            .unwrap();
+        LlamaApp::unload();
+        let answers: Vec<&str> = res.lines().filter(|line| line.contains("->")).collect();
+        assert_eq!(answers.len(), 3, "{res}");
+        assert!(res.trim_end().ends_with(answers[2]), "{res}");
+    }
+
+    #[test]
+    fn test_line_counter_counts_complete_matching_lines() {
+        let stop = LineStop::answer_lines(3);
+        let mut lines = stop.counter();
+        let tokens = [
+            "Here are the answers:\n",
+            "1 -> y",
+            "es\n2 -> no",
+            "\n\n3 -> ",
+            "yes",
+        ];
+        assert!(!tokens.iter().any(|token| lines.push(token)));
+        // the third line is only complete at its line break
+        assert!(lines.push("\n4"));
+
+        let mut lines = LineStop::new(2).counter();
+        assert!(!lines.push("a\n\n"));
+        assert!(lines.push("b\nc"));
+    }
+
+    #[test]
+    fn test_generation_budget() {
+        assert_eq!(generation_budget(2048, 48, None), 2000);
+        assert_eq!(generation_budget(2048, 48, Some(160)), 160);
+        assert_eq!(generation_budget(64, 48, Some(160)), 16);
+        assert_eq!(generation_budget(32, 48, None), 0);
+    }
+
+    #[test]
+    fn test_validate_batch_capacity() {
+        assert!(validate_batch_capacity(512, 2048).is_ok());
+        assert!(validate_batch_capacity(0, 2048).is_err());
+        assert!(validate_batch_capacity(4096, 2048).is_err());
+    }
+
+    #[test]
+    fn test_line_confidences() {
+        let token = |text: &str, prob: f32| GeneratedToken {
+            text: text.to_string(),
+            logprob: prob.ln(),
+        };
+        let tokens = vec![
+            token("1 -> yes", 0.9),
+            token("\n2", 0.4),
+            token(" -> no", 0.1),
+        ];
+        let lines = line_confidences(&tokens);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].0, "1 -> yes");
+        assert!((lines[0].1 - 0.9).abs() < 1e-6);
+        assert_eq!(lines[1].0, "2 -> no");
+        assert!((lines[1].1 - 0.2).abs() < 1e-6);
+    }
 }
@@ -1,34 +1,147 @@
+use std::path::PathBuf;
 use std::time::Instant;
 
+use clap::{Parser, ValueEnum};
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::error::DataFusionError;
 use datafusion::prelude::*;
-use datafusion_expr::ScalarUDF;
-mod llm_udf;
-mod llm_utils;
-mod ollama_utils;
+use datafusion_ai::config::{AiConfig, Backend};
+use datafusion_ai::dry_run::DryRun;
+use datafusion_ai::{RegisterOptions, register_ai_udfs_with, stream_dataframe};
+
+const DEFAULT_QUERY: &str = r#"
+    SELECT 
+        "Order ID", "Customer ID", "Customer Feedback", 
+        ask_llm('Categorize customer feedback as positive, negative, or neutral', "Customer Feedback") 
+        as sentiment
+    FROM sample_table 
+    limit 100
+    "#;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum BackendArg {
+    /// `ask_llm` and friends, served by an Ollama server
+    Ollama,
+    /// additionally registers `ask_llm_confidence`, backed by a local GGUF model
+    Llama,
+}
+
+impl From<BackendArg> for Backend {
+    fn from(backend: BackendArg) -> Self {
+        match backend {
+            BackendArg::Ollama => Backend::Ollama,
+            BackendArg::Llama => Backend::Llama,
+        }
+    }
+}
+
+/// Run SQL with AI UDFs over a CSV file.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// CSV file to query
+    #[arg(long, default_value = "sample_data/sample_data.csv")]
+    input: PathBuf,
+    /// Name the CSV file is registered under
+    #[arg(long, default_value = "sample_table")]
+    table: String,
+    /// SQL query to run
+    #[arg(long, conflicts_with = "query_file")]
+    query: Option<String>,
+    /// File containing the SQL query to run
+    #[arg(long)]
+    query_file: Option<PathBuf>,
+    /// Maximum number of result rows to show
+    #[arg(long)]
+    limit: Option<usize>,
+    /// TOML or JSON file with the AI configuration; the flags below override it
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Chat model used by ask_llm
+    #[arg(long)]
+    model: Option<String>,
+    /// Base URL of the Ollama server
+    #[arg(long)]
+    url: Option<String>,
+    #[arg(long, value_enum)]
+    backend: Option<BackendArg>,
+    /// GGUF model file, required with the llama backend
+    #[arg(long)]
+    model_path: Option<String>,
+    /// Print the estimated model calls and tokens of the query's ask_llm calls instead
+    /// of running it. Only ask_llm is estimated; other AI functions in the query, such
+    /// as ollama_embed or ask_llm_list, still call their models
+    #[arg(long)]
+    ask_llm_dry_run: bool,
+}
+
 #[tokio::main]
 async fn main() -> datafusion::error::Result<()> {
+    // RUST_LOG=debug logs how ask_llm is going to process each batch of rows
+    env_logger::init();
+    let args = Args::parse();
+
     // register the table
     let ctx = SessionContext::new();
     ctx.register_csv(
-        "sample_table",
-        "sample_data/sample_data.csv",
+        &args.table,
+        args.input.to_string_lossy().as_ref(),
         CsvReadOptions::new(),
     )
     .await?;
 
-    let ask_llm = ScalarUDF::from(llm_udf::AskLLM::new());
-    ctx.register_udf(ask_llm.clone());
-    let query = r#"
-    SELECT 
-        "Order ID", "Customer ID", "Customer Feedback", 
-        ask_llm('Categorize customer feedback as positive, negative, or neutral', "Customer Feedback") 
-        as sentiment
-    FROM sample_table 
-    limit 100
-    "#;
+    let mut config = match &args.config {
+        Some(path) => AiConfig::from_file(path)
+            .map_err(|e| DataFusionError::Configuration(format!("{e:#}")))?,
+        None => AiConfig::default(),
+    };
+    if let Some(backend) = args.backend {
+        config = config.with_backend(backend.into());
+    }
+    if let Some(model_path) = args.model_path {
+        config = config.with_model_path(model_path);
+    }
+    if let Some(model) = args.model {
+        config = config.with_chat_model(model);
+    }
+    if let Some(url) = args.url {
+        config = config.with_ollama_host(url);
+    }
+    config
+        .validate()
+        .map_err(|e| DataFusionError::Configuration(e.to_string()))?;
+    let dry_run = DryRun::new();
+    let mut options = RegisterOptions::default();
+    if args.ask_llm_dry_run {
+        options = options.with_dry_run(dry_run.clone());
+    }
+    let cancellation = register_ai_udfs_with(&ctx, &config, &options)
+        .map_err(|e| DataFusionError::Execution(e.to_string()))?;
+    // on Ctrl-C, let in-flight chunks finish and return partial results instead of aborting
+    cancellation.cancel_on_ctrl_c();
+
+    let query = match (args.query, args.query_file) {
+        (Some(query), _) => query,
+        (None, Some(query_file)) => std::fs::read_to_string(query_file)?,
+        (None, None) => DEFAULT_QUERY.to_string(),
+    };
     let time_start = Instant::now();
-    let df = ctx.sql(query).await?;
-    df.show().await?;
+    let mut df = ctx.sql(&query).await?;
+    if let Some(limit) = args.limit {
+        df = df.limit(0, Some(limit))?;
+    }
+    if args.ask_llm_dry_run {
+        // ask_llm answers NULL in dry-run mode, so this only reads the input
+        df.collect().await?;
+        println!("ask_llm dry run: {}", dry_run.total());
+        return Ok(());
+    }
+    // print batches as they complete so the first rows show up before the whole query is done
+    stream_dataframe(df, |batch| {
+        println!("{}", pretty_format_batches(&[batch])?);
+        Ok(())
+    })
+    .await?;
     let time_end = Instant::now();
     println!("Time taken: {:?}", time_end.duration_since(time_start));
     Ok(())
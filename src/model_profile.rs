@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use crate::chat_template::ChatTemplate;
+use crate::context_udf::ASK_LLM_CONTEXT_SYSTEM_PROMPT;
+use crate::explain_udf::ASK_LLM_EXPLAIN_SYSTEM_PROMPT;
+use crate::json_udf::ASK_LLM_JSON_SYSTEM_PROMPT;
+use crate::list_udf::ASK_LLM_LIST_SYSTEM_PROMPT;
+use crate::llm_udf::ASK_LLM_SYSTEM_PROMPT;
+use crate::llm_utils::SYSTEM_PROMPT;
+use crate::registry::AiUdf;
+
+/// Appended to the system prompts of reasoning models, which otherwise tend to put
+/// their chain of thought into the answer lines.
+pub const REASONING_MODEL_SUFFIX: &str =
+    " Do not explain your reasoning; write only the requested lines.";
+
+// name fragments of models that think out loud before answering
+const REASONING_MODELS: [&str; 3] = ["deepseek-r1", "qwq", "qwen3"];
+
+/// The chat template and per-UDF system prompts that suit a model, picked by
+/// [`crate::register_ai_udfs`] from [`crate::config::AiConfig::chat_model`] unless
+/// [`crate::RegisterOptions::with_model_profile`] provides one.
+///
+/// The template follows [`ChatTemplate::for_model_name`]. The system prompts are the
+/// UDF defaults (e.g. [`ASK_LLM_SYSTEM_PROMPT`]), with [`REASONING_MODEL_SUFFIX`]
+/// added for reasoning models (`deepseek-r1`, `qwq`, `qwen3`). Any prompt can be
+/// replaced with [`ModelProfile::with_system_prompt`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelProfile {
+    /// Set on the registered `ask_llm` and the UDFs built on it with
+    /// [`crate::llm_udf::AskLLM::with_chat_template`], so the template's special tokens
+    /// count towards their prompt limit, and used by `ask_llm_confidence` to format
+    /// its prompts.
+    pub chat_template: ChatTemplate,
+    system_prompts: HashMap<AiUdf, String>,
+}
+
+impl ModelProfile {
+    pub fn for_model(model_name: &str) -> Self {
+        let name = model_name.to_lowercase();
+        let suffix = if REASONING_MODELS.iter().any(|model| name.contains(model)) {
+            REASONING_MODEL_SUFFIX
+        } else {
+            ""
+        };
+        let system_prompts = [
+            (AiUdf::AskLlm, ASK_LLM_SYSTEM_PROMPT),
+            (AiUdf::AskLlmList, ASK_LLM_LIST_SYSTEM_PROMPT),
+            (AiUdf::AskLlmJson, ASK_LLM_JSON_SYSTEM_PROMPT),
+            (AiUdf::Explain, ASK_LLM_EXPLAIN_SYSTEM_PROMPT),
+            (AiUdf::Context, ASK_LLM_CONTEXT_SYSTEM_PROMPT),
+            (AiUdf::Raw, ASK_LLM_SYSTEM_PROMPT),
+            (AiUdf::Columns, ASK_LLM_SYSTEM_PROMPT),
+            (AiUdf::Date, ASK_LLM_SYSTEM_PROMPT),
+            (AiUdf::Confidence, SYSTEM_PROMPT),
+        ]
+        .into_iter()
+        .map(|(udf, prompt)| (udf, format!("{prompt}{suffix}")))
+        .collect();
+        Self {
+            chat_template: ChatTemplate::for_model_name(model_name).unwrap_or_default(),
+            system_prompts,
+        }
+    }
+
+    pub fn with_chat_template(mut self, chat_template: ChatTemplate) -> Self {
+        self.chat_template = chat_template;
+        self
+    }
+
+    /// Uses `system_prompt` for `udf` instead of the profile's choice.
+    pub fn with_system_prompt(mut self, udf: AiUdf, system_prompt: impl Into<String>) -> Self {
+        self.system_prompts.insert(udf, system_prompt.into());
+        self
+    }
+
+    /// The system prompt for `udf`, `None` for UDFs that send none.
+    pub fn system_prompt(&self, udf: AiUdf) -> Option<&str> {
+        self.system_prompts.get(&udf).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_follows_the_model_family() {
+        let profile = ModelProfile::for_model("qwen2.5:7b");
+        assert_eq!(profile.chat_template, ChatTemplate::ChatMl);
+        assert_eq!(
+            profile.system_prompt(AiUdf::AskLlm),
+            Some(ASK_LLM_SYSTEM_PROMPT)
+        );
+        assert_eq!(profile.system_prompt(AiUdf::OllamaEmbed), None);
+
+        let profile = ModelProfile::for_model("deepseek-r1:8b");
+        assert!(
+            profile
+                .system_prompt(AiUdf::AskLlmList)
+                .unwrap()
+                .ends_with(REASONING_MODEL_SUFFIX)
+        );
+        assert!(
+            profile
+                .system_prompt(AiUdf::Explain)
+                .unwrap()
+                .ends_with(REASONING_MODEL_SUFFIX)
+        );
+        let profile = profile.with_system_prompt(AiUdf::AskLlm, "Be brief.");
+        assert_eq!(profile.system_prompt(AiUdf::AskLlm), Some("Be brief."));
+    }
+}
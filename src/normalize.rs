@@ -0,0 +1,133 @@
+/// The casing [`Normalization`] applies to an answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Casing {
+    /// Keeps the casing of the model's answer.
+    #[default]
+    Keep,
+    Lower,
+    Upper,
+    /// Uppercases the first letter of every word and lowercases the rest.
+    Title,
+}
+
+/// Cleans up parsed answers so that variants such as `Positive`, ` positive` and
+/// `POSITIVE` collapse to one value, saving a layer of SQL `lower()`/`trim()` on
+/// categorical outputs. Whitespace is collapsed before the casing is applied.
+///
+/// The default keeps answers as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Normalization {
+    pub casing: Casing,
+    /// Removes leading and trailing whitespace.
+    pub trim: bool,
+    /// Replaces every run of whitespace inside the answer with a single space.
+    pub collapse_whitespace: bool,
+}
+
+impl Normalization {
+    /// Trims, collapses whitespace and lowercases, a good fit for category labels.
+    pub fn categorical() -> Self {
+        Self {
+            casing: Casing::Lower,
+            trim: true,
+            collapse_whitespace: true,
+        }
+    }
+
+    pub fn with_casing(mut self, casing: Casing) -> Self {
+        self.casing = casing;
+        self
+    }
+
+    pub fn with_trim(mut self, trim: bool) -> Self {
+        self.trim = trim;
+        self
+    }
+
+    pub fn with_collapse_whitespace(mut self, collapse_whitespace: bool) -> Self {
+        self.collapse_whitespace = collapse_whitespace;
+        self
+    }
+
+    pub fn apply(&self, answer: &str) -> String {
+        let answer = if self.trim { answer.trim() } else { answer };
+        let answer = if self.collapse_whitespace {
+            collapse_whitespace(answer)
+        } else {
+            answer.to_string()
+        };
+        match self.casing {
+            Casing::Keep => answer,
+            Casing::Lower => answer.to_lowercase(),
+            Casing::Upper => answer.to_uppercase(),
+            Casing::Title => title_case(&answer),
+        }
+    }
+}
+
+// keeps a single leading/trailing space where the answer had whitespace, so that
+// collapsing without trimming does not silently trim
+fn collapse_whitespace(answer: &str) -> String {
+    let mut collapsed = String::with_capacity(answer.len());
+    let mut in_whitespace = false;
+    for c in answer.chars() {
+        if c.is_whitespace() {
+            if !in_whitespace {
+                collapsed.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            collapsed.push(c);
+            in_whitespace = false;
+        }
+    }
+    collapsed
+}
+
+fn title_case(answer: &str) -> String {
+    let mut titled = String::with_capacity(answer.len());
+    let mut word_start = true;
+    for c in answer.chars() {
+        if word_start {
+            titled.extend(c.to_uppercase());
+        } else {
+            titled.extend(c.to_lowercase());
+        }
+        word_start = c.is_whitespace();
+    }
+    titled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_casing_modes() {
+        let answer = "very POSITIVE";
+        let normalize = |casing| Normalization::default().with_casing(casing).apply(answer);
+        assert_eq!(normalize(Casing::Keep), "very POSITIVE");
+        assert_eq!(normalize(Casing::Lower), "very positive");
+        assert_eq!(normalize(Casing::Upper), "VERY POSITIVE");
+        assert_eq!(normalize(Casing::Title), "Very Positive");
+    }
+
+    #[test]
+    fn test_whitespace_normalization() {
+        let answer = "  very \t positive\n";
+        assert_eq!(Normalization::default().apply(answer), answer);
+        assert_eq!(
+            Normalization::default().with_trim(true).apply(answer),
+            "very \t positive"
+        );
+        assert_eq!(
+            Normalization::default()
+                .with_collapse_whitespace(true)
+                .apply(answer),
+            " very positive "
+        );
+        for variant in ["Positive", " positive", "POSITIVE\n"] {
+            assert_eq!(Normalization::categorical().apply(variant), "positive");
+        }
+    }
+}
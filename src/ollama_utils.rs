@@ -1,12 +1,323 @@
 use anyhow::Context as AnyhowContext;
-use serde_json::{Value, json};
+use futures_util::StreamExt;
+use serde_json::{Map, Value, json};
 
+use crate::rate_limit::RateLimiter;
+use crate::streaming::NdjsonBuffer;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Connection settings of the HTTP client that talks to Ollama. The defaults are
+/// reqwest's: HTTP/1.1, with idle connections kept for 90 seconds and no limit on
+/// their number per host.
+///
+/// HTTP/2 multiplexes concurrent chunk requests over one connection, but over plain
+/// `http://` it needs a server that accepts cleartext HTTP/2 (h2c), e.g. a proxy in
+/// front of Ollama; Ollama itself only speaks HTTP/1.1 without TLS.
+///
+/// Compressed responses only help behind a server that compresses them, e.g. a
+/// reverse proxy in front of Ollama; large chunk answers and embedding batches shrink
+/// several times as gzip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpSettings {
+    /// Speaks HTTP/2 from the first request, without negotiating it.
+    pub http2_prior_knowledge: bool,
+    /// Advertises gzip, deflate and brotli in `Accept-Encoding` and transparently
+    /// decompresses responses that use them. On by default.
+    pub compression: bool,
+    /// Maximum idle connections kept per host.
+    pub pool_max_idle_per_host: Option<usize>,
+    /// How long an idle connection is kept open.
+    pub pool_idle_timeout: Option<Duration>,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            http2_prior_knowledge: false,
+            compression: true,
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: None,
+        }
+    }
+}
+
+impl HttpSettings {
+    pub fn client(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .gzip(self.compression)
+            .deflate(self.compression)
+            .brotli(self.compression);
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(max_idle) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(idle_timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(idle_timeout);
+        }
+        builder.build().context("Failed to build the HTTP client")
+    }
+}
+
+/// Returned when Ollama rejects the requested `format`, typically because the server
+/// predates JSON or schema structured outputs.
+#[derive(Debug)]
+pub struct UnsupportedFormatError(pub String);
+
+impl std::fmt::Display for UnsupportedFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Ollama does not support the requested format: {}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedFormatError {}
+
+pub fn is_unsupported_format(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<UnsupportedFormatError>().is_some()
+}
+
+/// Returned when a response body cannot be decoded, e.g. a proxy's HTML error page.
+#[derive(Debug)]
+pub struct MalformedResponseError(pub String);
+
+impl std::fmt::Display for MalformedResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to parse Ollama response: {}", self.0)
+    }
+}
+
+impl std::error::Error for MalformedResponseError {}
+
+// a non-2xx response: the reqwest error keeps the status for classify_reqwest_error,
+// the context the server's message (Ollama answers `{"error": "..."}`)
+fn status_error(error: reqwest::Error, body: &str) -> anyhow::Error {
+    let message = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|json| json["error"].as_str().map(String::from))
+        .unwrap_or_else(|| body.trim().to_string());
+    let status = error.status().map(|status| status.to_string());
+    anyhow::Error::new(error).context(format!(
+        "Ollama returned {}: {}",
+        status.unwrap_or_default(),
+        message
+    ))
+}
+
+async fn check_status(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    match response.error_for_status_ref().err() {
+        Some(error) => {
+            let body = response.text().await.unwrap_or_default();
+            Err(status_error(error, &body))
+        }
+        None => Ok(response),
+    }
+}
+
+/// Whether the request failed because the server could not be reached, as opposed to
+/// the server answering with an error.
+pub fn is_unreachable(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| e.is_connect() || e.is_timeout())
+}
+
+/// Whether retrying a failed request can help, see [`classify_error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// A transient transport failure: the connection could not be made, was reset or
+    /// timed out, or the server was overloaded (5xx, 429).
+    Retryable,
+    /// The same request would fail again: it could not be built, the response could
+    /// not be decoded, redirects failed, or the server rejected it (other 4xx).
+    Terminal,
+}
+
+/// Classifies a failed HTTP request for the retry loop.
+pub fn classify_reqwest_error(error: &reqwest::Error) -> ErrorClass {
+    if error.is_builder() || error.is_decode() || error.is_redirect() {
+        return ErrorClass::Terminal;
+    }
+    if let Some(status) = error.status() {
+        return if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            ErrorClass::Retryable
+        } else {
+            ErrorClass::Terminal
+        };
+    }
+    if error.is_connect() || error.is_timeout() || error.is_request() || is_reset(error) {
+        return ErrorClass::Retryable;
+    }
+    ErrorClass::Terminal
+}
+
+// whether an I/O error in the source chain dropped the connection
+fn is_reset(error: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        if let Some(io) = error.downcast_ref::<std::io::Error>() {
+            return matches!(
+                io.kind(),
+                std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::UnexpectedEof
+            );
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// Classifies a failed backend call. HTTP failures, including non-2xx responses, are
+/// classified with [`classify_reqwest_error`], and a body that cannot be decoded
+/// ([`MalformedResponseError`]) is terminal; other errors, such as an error object in
+/// a successful response, are retryable since the model may answer differently the
+/// next time.
+pub fn classify_error(error: &anyhow::Error) -> ErrorClass {
+    if error.downcast_ref::<MalformedResponseError>().is_some() {
+        return ErrorClass::Terminal;
+    }
+    match error.downcast_ref::<reqwest::Error>() {
+        Some(error) => classify_reqwest_error(error),
+        None => ErrorClass::Retryable,
+    }
+}
+
+fn unsupported_format(response: &Value) -> Option<UnsupportedFormatError> {
+    response["error"]
+        .as_str()
+        .filter(|error| error.contains("format"))
+        .map(|error| UnsupportedFormatError(error.to_string()))
+}
+
+/// The assistant message of a chat response.
+#[derive(Debug, Clone)]
+pub struct ChatResponse {
+    pub content: String,
+    /// Why generation stopped, e.g. `stop` or `length` when `num_predict` was hit.
+    pub done_reason: Option<String>,
+    /// The reasoning of a thinking model, only requested with [`OllamaApp::with_thinking`].
+    pub thinking: Option<String>,
+    /// The tokens the server reports for the request, if any.
+    pub usage: Option<TokenUsage>,
+}
+
+/// Token counts of a chat request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+// Ollama reports prompt_eval_count and eval_count, OpenAI-compatible servers a usage
+// object; the prompt count is left out when the prompt was served from the KV cache
+fn parse_usage(json: &Value) -> Option<TokenUsage> {
+    let (prompt, completion) = match json.get("usage") {
+        Some(usage) => (&usage["prompt_tokens"], &usage["completion_tokens"]),
+        None => (&json["prompt_eval_count"], &json["eval_count"]),
+    };
+    if prompt.is_null() && completion.is_null() {
+        return None;
+    }
+    Some(TokenUsage {
+        prompt_tokens: prompt.as_u64().unwrap_or_default(),
+        completion_tokens: completion.as_u64().unwrap_or_default(),
+    })
+}
+
+/// The content of a chat response that had no message content (nor captured thinking).
+pub const NO_RESPONSE_CONTENT: &str = "No response content";
+
+// some servers answer a non-streaming request with one JSON object per line anyway,
+// the leading ones with "done": false; their message parts are joined into the last
+// object, which carries done_reason. An error object is returned as is. Objects are
+// read back to back rather than per line, so a pretty-printed body still parses
+fn parse_response_body(body: &str) -> anyhow::Result<Value> {
+    let mut objects = serde_json::Deserializer::from_str(body)
+        .into_iter::<Value>()
+        .collect::<Result<Vec<_>, _>>()?;
+    if objects.len() <= 1 {
+        return objects
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("empty Ollama response"));
+    }
+    if let Some(error) = objects.iter().find(|json| json.get("error").is_some()) {
+        return Ok(error.clone());
+    }
+    let join = |field: &str| -> Option<String> {
+        let parts: Vec<&str> = objects
+            .iter()
+            .filter_map(|json| json["message"][field].as_str())
+            .collect();
+        (!parts.is_empty()).then(|| parts.concat())
+    };
+    let (content, thinking) = (join("content"), join("thinking"));
+    let mut json = objects.pop().unwrap_or_default();
+    anyhow::ensure!(json.is_object(), "unexpected Ollama response: {}", json);
+    if !json["message"].is_object() {
+        json["message"] = json!({ "role": "assistant" });
+    }
+    if let Some(content) = content {
+        json["message"]["content"] = json!(content);
+    }
+    if let Some(thinking) = thinking {
+        json["message"]["thinking"] = json!(thinking);
+    }
+    Ok(json)
+}
+
+// with thinking captured, a reasoning model that put its whole output in `thinking`
+// (or was cut off before writing any content) is answered from the thinking instead
+fn parse_chat_response(json: &Value, thinking: bool) -> ChatResponse {
+    let content = json["message"]["content"].as_str();
+    let done_reason = json["done_reason"].as_str().map(String::from);
+    let thinking = json["message"]["thinking"]
+        .as_str()
+        .filter(|_| thinking)
+        .filter(|thinking| !thinking.trim().is_empty())
+        .map(String::from);
+    let content = match (content, &thinking) {
+        (Some(content), _) if !content.trim().is_empty() => content.to_string(),
+        (_, Some(thinking)) => {
+            log::warn!(
+                "empty message content, falling back to the {} chars of thinking output",
+                thinking.len()
+            );
+            thinking.clone()
+        }
+        (content, None) => content.unwrap_or(NO_RESPONSE_CONTENT).to_string(),
+    };
+    ChatResponse {
+        content,
+        done_reason,
+        thinking,
+        usage: parse_usage(json),
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct OllamaApp {
     model_name: String,
     url: String,
     client: reqwest::Client,
+    temperature: Option<f32>,
+    thinking: bool,
+    system_prompt: String,
+    options: Map<String, Value>,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl OllamaApp {
@@ -16,30 +327,205 @@ impl OllamaApp {
             model_name: model_name.to_string(),
             url: url.to_string(),
             client: reqwest::Client::new(),
+            temperature: None,
+            thinking: false,
+            system_prompt: String::new(),
+            options: Map::new(),
+            rate_limiter: None,
         })
     }
 
+    /// Sends `system_prompt` as a system message before every chat request, none when
+    /// empty (the default).
+    pub fn with_system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.system_prompt = system_prompt.into();
+        self
+    }
+
+    fn messages(&self, content: String) -> Value {
+        let user = json!({ "role": "user", "content": content });
+        if self.system_prompt.is_empty() {
+            return json!([user]);
+        }
+        json!([{ "role": "system", "content": self.system_prompt }, user])
+    }
+
+    /// Asks thinking models to return their reasoning (Ollama's `think` option) and
+    /// keeps it in [`ChatResponse::thinking`]. When the message content is empty, the
+    /// thinking is used as the content. Off by default, as the reasoning can be far
+    /// longer than the answer.
+    pub fn with_thinking(mut self, thinking: bool) -> Self {
+        self.thinking = thinking;
+        self
+    }
+
+    /// Sends `options` (e.g. `mirostat`, `repeat_penalty` or `stop`) in the `options`
+    /// object of chat requests, so any model parameter Ollama supports can be set.
+    /// Options set here override typed settings such as
+    /// [`OllamaApp::with_temperature`].
+    pub fn with_options(mut self, options: Map<String, Value>) -> Self {
+        self.options = options;
+        self
+    }
+
+    // the typed settings, overridden by the free-form options
+    fn request_options(&self) -> Option<Value> {
+        let mut options = Map::new();
+        if let Some(temperature) = self.temperature {
+            options.insert("temperature".to_string(), json!(temperature));
+        }
+        options.extend(self.options.clone());
+        (!options.is_empty()).then_some(Value::Object(options))
+    }
+
+    /// Sends requests through `client` instead of a client of its own, so connections
+    /// are pooled across instances.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Waits for `rate_limiter` before every chat and embedding request, so instances
+    /// sharing it stay under its request rate together.
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    async fn throttle(&self) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+    }
+
+    /// Sets the sampling temperature sent with chat requests.
+    pub fn with_temperature(mut self, temperature: Option<f32>) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Checks that the server is reachable and the model is available, then asks Ollama
+    /// to load it into memory by sending a chat request without messages.
+    pub async fn preload_chat(&self) -> anyhow::Result<()> {
+        self.post_checked(json!({ "model": self.model_name, "messages": [] }))
+            .await
+    }
+
+    /// Same as [`OllamaApp::preload_chat`] for embedding models; `self.url` is expected
+    /// to point at `/api/embed`.
+    pub async fn preload_embed(&self) -> anyhow::Result<()> {
+        self.post_checked(json!({ "model": self.model_name, "input": [] }))
+            .await
+    }
+
+    async fn post_checked(&self, request: Value) -> anyhow::Result<()> {
+        self.throttle().await;
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach Ollama server at {}", self.url))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!(
+                "Ollama failed to load model {}: {} {}",
+                self.model_name,
+                status,
+                body
+            );
+        }
+        Ok(())
+    }
+
     /// Generates text by sending a prompt to the Ollama server.
     pub async fn generate_text(
         &self,
         instruction: &str,
         column_values: &[String],
     ) -> anyhow::Result<String> {
+        Ok(self
+            .chat(instruction, column_values, 1, None)
+            .await?
+            .content)
+    }
+
+    /// Like [`OllamaApp::generate_text`], but also returns why generation stopped.
+    pub async fn generate_chat(
+        &self,
+        instruction: &str,
+        column_values: &[String],
+    ) -> anyhow::Result<ChatResponse> {
+        self.chat(instruction, column_values, 1, None).await
+    }
+
+    /// Like [`OllamaApp::generate_chat`], but numbers the values from `first_row`
+    /// instead of 1.
+    pub async fn generate_chat_from(
+        &self,
+        instruction: &str,
+        column_values: &[String],
+        first_row: usize,
+    ) -> anyhow::Result<ChatResponse> {
+        self.chat(instruction, column_values, first_row, None).await
+    }
+
+    /// Like [`OllamaApp::generate_text`], but passes `format` (a JSON schema) as Ollama's
+    /// `format` so the model is constrained to produce matching JSON.
+    pub async fn generate_structured(
+        &self,
+        instruction: &str,
+        column_values: &[String],
+        format: &Value,
+    ) -> anyhow::Result<String> {
+        Ok(self
+            .chat(instruction, column_values, 1, Some(format))
+            .await?
+            .content)
+    }
+
+    /// Like [`OllamaApp::generate_structured`], but numbers the values from `first_row`
+    /// and also returns why generation stopped.
+    pub async fn generate_structured_from(
+        &self,
+        instruction: &str,
+        column_values: &[String],
+        first_row: usize,
+        format: &Value,
+    ) -> anyhow::Result<ChatResponse> {
+        self.chat(instruction, column_values, first_row, Some(format))
+            .await
+    }
+
+    async fn chat(
+        &self,
+        instruction: &str,
+        column_values: &[String],
+        first_row: usize,
+        format: Option<&Value>,
+    ) -> anyhow::Result<ChatResponse> {
         // Format the content string
-        let content = format_content(instruction, column_values);
+        let content = format_content(instruction, column_values, first_row);
 
         // Build the request JSON directly
-        let request = json!({
+        let mut request = json!({
             "model": self.model_name,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": content
-                }
-            ],
+            "messages": self.messages(content),
             "stream": false
         });
+        if let Some(format) = format {
+            request["format"] = format.clone();
+        }
+        if let Some(options) = self.request_options() {
+            request["options"] = options;
+        }
+        if self.thinking {
+            request["think"] = json!(true);
+        }
 
+        self.throttle().await;
         let response = self
             .client
             .post(&self.url)
@@ -49,27 +535,130 @@ impl OllamaApp {
             .context("Failed to send request to Ollama server")?;
 
         // Parse the response
+        let status = response.error_for_status_ref().err();
         let response_text = response.text().await?;
+        if let Some(status) = status {
+            if format.is_some()
+                && let Some(error) = serde_json::from_str(&response_text)
+                    .ok()
+                    .and_then(|json| unsupported_format(&json))
+            {
+                return Err(error.into());
+            }
+            return Err(status_error(status, &response_text));
+        }
 
-        let json: Value =
-            serde_json::from_str(&response_text).context("Failed to parse Ollama response")?;
+        let json = parse_response_body(&response_text)
+            .map_err(|e| MalformedResponseError(format!("{:#}", e)))?;
+        if format.is_some()
+            && let Some(error) = unsupported_format(&json)
+        {
+            return Err(error.into());
+        }
 
         // Extract the message content
-        let content = json["message"]["content"]
-            .as_str()
-            .unwrap_or("No response content")
-            .to_string();
+        Ok(parse_chat_response(&json, self.thinking))
+    }
+
+    /// Like [`OllamaApp::generate_text`], but requests a streamed response and
+    /// assembles the message content from the NDJSON chunks as they arrive.
+    pub async fn generate_text_streaming(
+        &self,
+        instruction: &str,
+        column_values: &[String],
+    ) -> anyhow::Result<String> {
+        let content = format_content(instruction, column_values, 1);
+        let mut request = json!({
+            "model": self.model_name,
+            "messages": self.messages(content),
+            "stream": true
+        });
+        if let Some(options) = self.request_options() {
+            request["options"] = options;
+        }
+
+        self.throttle().await;
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .context("Failed to send request to Ollama server")?;
+        let response = check_status(response).await?;
+
+        let mut buffer = NdjsonBuffer::new();
+        let mut output = String::new();
+        let mut body = response.bytes_stream();
+        while let Some(bytes) = body.next().await {
+            let bytes = bytes.context("Failed to read Ollama response stream")?;
+            for json in buffer
+                .push(&bytes)
+                .map_err(|e| MalformedResponseError(format!("{:#}", e)))?
+            {
+                output.push_str(json["message"]["content"].as_str().unwrap_or_default());
+            }
+        }
+        if let Some(json) = buffer
+            .finish()
+            .map_err(|e| MalformedResponseError(format!("{:#}", e)))?
+        {
+            output.push_str(json["message"]["content"].as_str().unwrap_or_default());
+        }
+
+        Ok(output)
+    }
+
+    /// Computes one embedding per input value using the Ollama `/api/embed` endpoint.
+    /// `self.url` is expected to point at that endpoint.
+    pub async fn embed(&self, column_values: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+        let request = json!({
+            "model": self.model_name,
+            "input": column_values,
+        });
+
+        self.throttle().await;
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .context("Failed to send request to Ollama server")?;
+        let response = check_status(response).await?;
+
+        let response_text = response.text().await?;
+
+        let json: Value = serde_json::from_str(&response_text)
+            .map_err(|e| MalformedResponseError(e.to_string()))?;
 
-        Ok(content)
+        let embeddings = json["embeddings"]
+            .as_array()
+            .context("Ollama response has no embeddings")?
+            .iter()
+            .map(|embedding| {
+                embedding
+                    .as_array()
+                    .map(|values| {
+                        values
+                            .iter()
+                            .filter_map(|v| v.as_f64().map(|v| v as f32))
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        Ok(embeddings)
     }
 }
 
 /// Helper function to format the content for the prompt
-fn format_content(instruction: &str, column_values: &[String]) -> String {
+fn format_content(instruction: &str, column_values: &[String], first_row: usize) -> String {
     let column_values_str = column_values
         .iter()
         .enumerate()
-        .map(|(i, value)| format!("{}. {}", i + 1, value))
+        .map(|(i, value)| format!("{}. {}", i + first_row, value))
         .collect::<Vec<_>>()
         .join("\n");
 
@@ -83,6 +672,246 @@ fn format_content(instruction: &str, column_values: &[String]) -> String {
 mod tests {
     use super::*;
 
+    // serves every connection with `respond`, which may read the request and write
+    // (part of) a response before the connection is dropped
+    fn spawn_server(respond: fn(&mut std::net::TcpStream)) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                std::thread::spawn(move || respond(&mut stream));
+            }
+        });
+        url
+    }
+
+    fn read_request(stream: &mut std::net::TcpStream) {
+        use std::io::Read;
+        let mut buf = [0u8; 4096];
+        let _ = stream.read(&mut buf);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_responses_are_decoded() {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        // whether the last response was gzip-encoded
+        static GZIPPED: AtomicBool = AtomicBool::new(false);
+        fn respond(stream: &mut std::net::TcpStream) {
+            use std::io::Read;
+            let body = br#"{"message":{"role":"assistant","content":"1 -> ok"},"done":true}"#;
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).unwrap_or_default();
+            let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+            let gzip = request.contains("accept-encoding: gzip");
+            GZIPPED.store(gzip, Ordering::SeqCst);
+            let (encoding, body) = if gzip {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).unwrap();
+                ("content-encoding: gzip\r\n", encoder.finish().unwrap())
+            } else {
+                ("", body.to_vec())
+            };
+            let head = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n{}content-length: {}\r\n\r\n",
+                encoding,
+                body.len()
+            );
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(&body);
+        }
+        let url = spawn_server(respond);
+        let fetch = |settings: HttpSettings| {
+            let url = url.clone();
+            async move {
+                let response = settings.client().unwrap().get(&url).send().await.unwrap();
+                let body = response.json::<Value>().await.unwrap();
+                (GZIPPED.load(Ordering::SeqCst), body)
+            }
+        };
+        let (gzipped, compressed) = fetch(HttpSettings::default()).await;
+        assert!(gzipped);
+        let plain = HttpSettings {
+            compression: false,
+            ..HttpSettings::default()
+        };
+        let (gzipped, uncompressed) = fetch(plain).await;
+        assert!(!gzipped);
+        assert_eq!(compressed, uncompressed);
+        assert_eq!(compressed["message"]["content"], "1 -> ok");
+    }
+
+    #[tokio::test]
+    async fn test_request_errors_are_classified() {
+        use std::io::Write;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let classify = |result: reqwest::Result<reqwest::Response>| {
+            classify_reqwest_error(&result.and_then(|r| r.error_for_status()).unwrap_err())
+        };
+
+        let error = client.get("not a url").build().unwrap_err();
+        assert!(error.is_builder());
+        assert_eq!(classify_reqwest_error(&error), ErrorClass::Terminal);
+
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", closed.local_addr().unwrap());
+        drop(closed);
+        assert_eq!(
+            classify(client.get(&url).send().await),
+            ErrorClass::Retryable
+        );
+
+        let silent = spawn_server(|stream| {
+            read_request(stream);
+            std::thread::sleep(Duration::from_secs(2));
+        });
+        assert_eq!(
+            classify(client.get(&silent).send().await),
+            ErrorClass::Retryable
+        );
+
+        let reset = spawn_server(read_request);
+        assert_eq!(
+            classify(client.get(&reset).send().await),
+            ErrorClass::Retryable
+        );
+
+        let overloaded = spawn_server(|stream| {
+            read_request(stream);
+            let _ =
+                stream.write_all(b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n");
+        });
+        assert_eq!(
+            classify(client.get(&overloaded).send().await),
+            ErrorClass::Retryable
+        );
+
+        let rejected = spawn_server(|stream| {
+            read_request(stream);
+            let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\ncontent-length: 0\r\n\r\n");
+        });
+        assert_eq!(
+            classify(client.get(&rejected).send().await),
+            ErrorClass::Terminal
+        );
+
+        let garbled = spawn_server(|stream| {
+            read_request(stream);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 3\r\n\r\nabc");
+        });
+        let response = client.get(&garbled).send().await.unwrap();
+        let error = response.json::<Value>().await.unwrap_err();
+        assert_eq!(classify_reqwest_error(&error), ErrorClass::Terminal);
+
+        // errors reported in the response body are left to the retry loop
+        let error = anyhow::anyhow!("model is loading");
+        assert_eq!(classify_error(&error), ErrorClass::Retryable);
+        let error = anyhow::Error::from(MalformedResponseError("abc".to_string()));
+        assert_eq!(classify_error(&error), ErrorClass::Terminal);
+    }
+
+    #[test]
+    fn test_content_numbers_rows_from_the_first_row() {
+        let values = ["a".to_string(), "b".to_string()];
+        assert_eq!(format_content("Rank", &values, 1), "Rank:\n1. a\n2. b");
+        assert_eq!(format_content("Rank", &values, 6), "Rank:\n6. a\n7. b");
+    }
+
+    #[test]
+    fn test_detects_unsupported_format_error() {
+        let response = json!({ "error": "invalid format: expected \"json\" or a JSON schema" });
+        let error: anyhow::Error = unsupported_format(&response).unwrap().into();
+        assert!(is_unsupported_format(&error));
+        assert!(unsupported_format(&json!({ "error": "model not found" })).is_none());
+        assert!(unsupported_format(&json!({ "message": { "content": "{}" } })).is_none());
+    }
+
+    #[test]
+    fn test_token_usage_is_read_from_either_format() {
+        let ollama =
+            json!({ "message": { "content": "ok" }, "prompt_eval_count": 40, "eval_count": 8 });
+        let usage = parse_chat_response(&ollama, false).usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (40, 8));
+        let openai = json!({ "usage": { "prompt_tokens": 12, "completion_tokens": 3 } });
+        assert_eq!(parse_usage(&openai).unwrap().completion_tokens, 3);
+        // a prompt served from the KV cache has no prompt_eval_count
+        assert_eq!(
+            parse_usage(&json!({ "eval_count": 5 }))
+                .unwrap()
+                .prompt_tokens,
+            0
+        );
+        assert!(parse_usage(&json!({ "message": { "content": "ok" } })).is_none());
+    }
+
+    #[test]
+    fn test_thinking_is_the_fallback_for_empty_content() {
+        let response = json!({
+            "message": { "content": "", "thinking": "1 -> positive" },
+            "done_reason": "stop"
+        });
+        assert_eq!(
+            parse_chat_response(&response, true).content,
+            "1 -> positive"
+        );
+        assert_eq!(
+            parse_chat_response(&response, false).content,
+            "",
+            "thinking is ignored unless captured"
+        );
+        let response = json!({ "message": { "content": "1 -> neutral", "thinking": "hmm" } });
+        let parsed = parse_chat_response(&response, true);
+        assert_eq!(parsed.content, "1 -> neutral");
+        assert_eq!(parsed.thinking.as_deref(), Some("hmm"));
+    }
+
+    #[test]
+    fn test_multi_object_body_is_joined() {
+        let body = concat!(
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"1 -> pos\"},\"done\":false}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"itive\\n2 -> \"},\"done\":false}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"negative\"},\"done\":true,\"done_reason\":\"stop\"}",
+        );
+        let parsed = parse_chat_response(&parse_response_body(body).unwrap(), false);
+        assert_eq!(parsed.content, "1 -> positive\n2 -> negative");
+        assert_eq!(parsed.done_reason.as_deref(), Some("stop"));
+
+        let single = r#"{"message":{"content":"1 -> ok"},"done":true}"#;
+        assert_eq!(
+            parse_response_body(single).unwrap()["message"]["content"],
+            "1 -> ok"
+        );
+        assert!(parse_response_body("").is_err());
+        assert!(parse_response_body("not json").is_err());
+    }
+
+    #[test]
+    fn test_system_prompt_is_sent_first() {
+        let app = OllamaApp::new("model", "http://localhost:11434/api/chat").unwrap();
+        assert_eq!(app.messages("hi".to_string()).as_array().unwrap().len(), 1);
+        let messages = app
+            .with_system_prompt("Be brief.")
+            .messages("hi".to_string());
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(messages[0]["content"], "Be brief.");
+        assert_eq!(messages[1]["content"], "hi");
+    }
+
+    #[test]
+    fn test_options_override_typed_settings() {
+        let app = OllamaApp::new("model", "http://localhost:11434/api/chat").unwrap();
+        assert_eq!(app.request_options(), None);
+        let options = json!({ "temperature": 0.7, "stop": ["\n\n"] });
+        let app = app
+            .with_temperature(Some(0.0))
+            .with_options(options.as_object().unwrap().clone());
+        assert_eq!(app.request_options(), Some(options));
+    }
+
     #[tokio::test]
     async fn test_ollama_app() {
         let ollama_app =
@@ -110,4 +939,36 @@ mod tests {
                 && res.contains("4 -> likely")
         );
     }
+
+    // compares concurrent chat requests over HTTP/1.1 and HTTP/2 against the server at
+    // OLLAMA_BENCH_HOST (http://localhost:11434 by default), which must accept h2c for
+    // the HTTP/2 run. Run with
+    // `cargo test --release -- --ignored --nocapture bench_http1_vs_http2`
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn bench_http1_vs_http2() {
+        let host = std::env::var("OLLAMA_BENCH_HOST")
+            .unwrap_or_else(|_| "http://localhost:11434".to_string());
+        let url = format!("{}/api/chat", host.trim_end_matches('/'));
+        let values = vec!["Great!".to_string()];
+        for http2_prior_knowledge in [false, true] {
+            let settings = HttpSettings {
+                http2_prior_knowledge,
+                ..HttpSettings::default()
+            };
+            let app = OllamaApp::new("llama32-df:latest", &url)
+                .unwrap()
+                .with_client(settings.client().unwrap());
+            let start = std::time::Instant::now();
+            let requests = (0..32).map(|_| app.generate_text("Rate the sentiment", &values));
+            let results = futures_util::future::join_all(requests).await;
+            let failed = results.iter().filter(|result| result.is_err()).count();
+            println!(
+                "http2_prior_knowledge={}: 32 requests in {:?}, {} failed",
+                http2_prior_knowledge,
+                start.elapsed(),
+                failed
+            );
+        }
+    }
 }
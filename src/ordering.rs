@@ -0,0 +1,85 @@
+use datafusion_common::{DataFusionError, Result};
+
+/// Reassembles answers computed for subsets of rows back into input order.
+///
+/// Paths such as pre-filtering, per-task grouping and windowing answer rows out of
+/// order and scatter the answers back. This checks the invariant they all rely on:
+/// every input position is filled exactly once and the output has the input's length.
+/// The check is a flag per row, cheap enough to stay on; a violation is an internal
+/// error rather than a silently misaligned column.
+#[derive(Debug)]
+pub(crate) struct Scatter<T> {
+    slots: Vec<Option<T>>,
+}
+
+impl<T> Scatter<T> {
+    pub fn new(len: usize) -> Self {
+        Self {
+            slots: std::iter::repeat_with(|| None).take(len).collect(),
+        }
+    }
+
+    /// Writes `answers[i]` to position `positions[i]`.
+    pub fn fill(&mut self, positions: &[usize], answers: Vec<T>) -> Result<()> {
+        if positions.len() != answers.len() {
+            return Err(DataFusionError::Internal(format!(
+                "ordering violation: {} answers for {} positions",
+                answers.len(),
+                positions.len()
+            )));
+        }
+        for (&position, answer) in positions.iter().zip(answers) {
+            self.fill_one(position, answer)?;
+        }
+        Ok(())
+    }
+
+    pub fn fill_one(&mut self, position: usize, answer: T) -> Result<()> {
+        match self.slots.get_mut(position) {
+            Some(slot @ None) => {
+                *slot = Some(answer);
+                Ok(())
+            }
+            Some(Some(_)) => Err(DataFusionError::Internal(format!(
+                "ordering violation: position {position} filled twice"
+            ))),
+            None => Err(DataFusionError::Internal(format!(
+                "ordering violation: position {position} out of bounds for {} rows",
+                self.slots.len()
+            ))),
+        }
+    }
+
+    /// Returns the answers in input order, failing if any position was never filled.
+    pub fn finish(self) -> Result<Vec<T>> {
+        let len = self.slots.len();
+        let answers: Vec<T> = self.slots.into_iter().flatten().collect();
+        if answers.len() != len {
+            return Err(DataFusionError::Internal(format!(
+                "ordering violation: {} of {} positions were never filled",
+                len - answers.len(),
+                len
+            )));
+        }
+        Ok(answers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scatter_detects_gaps_and_double_fills() {
+        let mut scatter = Scatter::new(3);
+        scatter.fill(&[2, 0], vec!["c", "a"]).unwrap();
+        assert!(scatter.fill_one(0, "again").is_err());
+        scatter.fill_one(1, "b").unwrap();
+        assert_eq!(scatter.finish().unwrap(), vec!["a", "b", "c"]);
+
+        let mut scatter = Scatter::new(2);
+        scatter.fill_one(0, "a").unwrap();
+        assert!(scatter.finish().is_err());
+        assert!(Scatter::new(1).fill(&[0], vec!["a", "b"]).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use datafusion::arrow::array::{Array, ArrayRef, StringArray, new_empty_array};
+use datafusion::arrow::compute::kernels::cast_utils::{parse_decimal, string_to_timestamp_nanos};
+use datafusion::arrow::compute::{can_cast_types, cast};
+use datafusion::arrow::datatypes::{DataType, Decimal128Type, TimeUnit};
+use datafusion_common::{DataFusionError, Result, ScalarValue, plan_err};
+use std::sync::Arc;
+
+type ParseFn = Arc<dyn Fn(&str) -> Option<ScalarValue> + Send + Sync>;
+
+/// Turns the parsed answers of `ask_llm` into its output column, see
+/// [`crate::llm_udf::AskLLM::with_output_builder`].
+///
+/// [`OutputBuilder::cast`] uses Arrow's cast from `Utf8`, which is what
+/// [`crate::llm_udf::AskLLM::with_output_type`] sets up. The other constructors parse
+/// each (trimmed) answer on its own, more leniently than a cast, since models
+/// decorate their answers: `Yes.`, `1,200`, `$19.99`. An answer that does not parse
+/// becomes NULL.
+///
+/// | constructor                              | output type             | accepts                             |
+/// |------------------------------------------|-------------------------|-------------------------------------|
+/// | [`OutputBuilder::boolean`]               | `Boolean`               | yes/no, true/false, y/n, 1/0        |
+/// | [`OutputBuilder::int64`]                 | `Int64`                 | digit grouping with `,` or `_`      |
+/// | [`OutputBuilder::float64`]               | `Float64`               | digit grouping with `,` or `_`      |
+/// | [`OutputBuilder::decimal`]               | `Decimal128(p, s)`      | grouping and a `$`, `€` or `£` sign |
+/// | [`OutputBuilder::timestamp`]             | `Timestamp(Nanosecond)` | RFC 3339 and `YYYY-MM-DD[ hh:mm]`   |
+/// | [`OutputBuilder::timestamp_with_format`] | `Timestamp(Nanosecond)` | a chrono format                     |
+/// | [`OutputBuilder::date32_with_format`]    | `Date32`                | a chrono format                     |
+/// | [`OutputBuilder::uuid`]                  | `FixedSizeBinary(16)`   | hyphenated, braced or `urn:uuid:`   |
+#[derive(Clone)]
+pub struct OutputBuilder {
+    data_type: DataType,
+    // None casts the whole column
+    parse: Option<ParseFn>,
+}
+
+impl std::fmt::Debug for OutputBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OutputBuilder")
+            .field("data_type", &self.data_type)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for OutputBuilder {
+    fn default() -> Self {
+        Self::cast(DataType::Utf8)
+    }
+}
+
+// trailing sentence punctuation models like to add
+fn strip_punctuation(answer: &str) -> &str {
+    answer.trim_end_matches(['.', '!'])
+}
+
+// removes digit grouping; decimal commas are not supported
+fn strip_grouping(answer: &str) -> String {
+    strip_punctuation(answer)
+        .chars()
+        .filter(|c| !matches!(c, ',' | '_'))
+        .collect()
+}
+
+impl OutputBuilder {
+    /// Parses every answer with `parse`, which returns a value of `data_type` or
+    /// `None` for an answer it cannot parse.
+    pub fn new(
+        data_type: DataType,
+        parse: impl Fn(&str) -> Option<ScalarValue> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            data_type,
+            parse: Some(Arc::new(parse)),
+        }
+    }
+
+    /// Casts the answers to `data_type` with Arrow's cast from `Utf8`.
+    pub fn cast(data_type: DataType) -> Self {
+        Self {
+            data_type,
+            parse: None,
+        }
+    }
+
+    pub fn boolean() -> Self {
+        Self::new(DataType::Boolean, |answer| {
+            let value = match strip_punctuation(answer).to_lowercase().as_str() {
+                "yes" | "y" | "true" | "1" => true,
+                "no" | "n" | "false" | "0" => false,
+                _ => return None,
+            };
+            Some(ScalarValue::Boolean(Some(value)))
+        })
+    }
+
+    pub fn int64() -> Self {
+        Self::new(DataType::Int64, |answer| {
+            let value = strip_grouping(answer).parse().ok()?;
+            Some(ScalarValue::Int64(Some(value)))
+        })
+    }
+
+    pub fn float64() -> Self {
+        Self::new(DataType::Float64, |answer| {
+            let value = strip_grouping(answer).parse().ok()?;
+            Some(ScalarValue::Float64(Some(value)))
+        })
+    }
+
+    /// Decimals with `precision` digits, `scale` of them after the decimal point.
+    /// Answers with more fractional digits are truncated.
+    pub fn decimal(precision: u8, scale: i8) -> Self {
+        Self::new(DataType::Decimal128(precision, scale), move |answer| {
+            let answer = strip_grouping(answer.trim_start_matches(['$', '€', '£']));
+            let value = parse_decimal::<Decimal128Type>(&answer, precision, scale).ok()?;
+            Some(ScalarValue::Decimal128(Some(value), precision, scale))
+        })
+    }
+
+    /// Timestamps without a time zone; answers with an offset are converted to UTC.
+    pub fn timestamp() -> Self {
+        Self::new(DataType::Timestamp(TimeUnit::Nanosecond, None), |answer| {
+            let value = string_to_timestamp_nanos(strip_punctuation(answer)).ok()?;
+            Some(ScalarValue::TimestampNanosecond(Some(value), None))
+        })
+    }
+
+    /// Timestamps without a time zone, parsed with the chrono (strftime-like)
+    /// `format`, e.g. `%d/%m/%Y %H:%M`. A format without a time of day reads
+    /// midnight.
+    pub fn timestamp_with_format(format: impl Into<String>) -> Self {
+        let format = format.into();
+        Self::new(
+            DataType::Timestamp(TimeUnit::Nanosecond, None),
+            move |answer| {
+                let answer = strip_punctuation(answer);
+                let value = match NaiveDateTime::parse_from_str(answer, &format) {
+                    Ok(value) => value,
+                    Err(_) => NaiveDate::parse_from_str(answer, &format)
+                        .ok()?
+                        .and_hms_opt(0, 0, 0)?,
+                };
+                let value = value.and_utc().timestamp_nanos_opt()?;
+                Some(ScalarValue::TimestampNanosecond(Some(value), None))
+            },
+        )
+    }
+
+    /// Dates parsed with the chrono (strftime-like) `format`, e.g. `%B %d, %Y`.
+    pub fn date32_with_format(format: impl Into<String>) -> Self {
+        let format = format.into();
+        Self::new(DataType::Date32, move |answer| {
+            let date = NaiveDate::parse_from_str(strip_punctuation(answer), &format).ok()?;
+            let days = date
+                .signed_duration_since(NaiveDate::from_ymd_opt(1970, 1, 1)?)
+                .num_days();
+            Some(ScalarValue::Date32(Some(days.try_into().ok()?)))
+        })
+    }
+
+    /// UUIDs as their 16 bytes, the storage of Arrow's `arrow.uuid` extension type.
+    pub fn uuid() -> Self {
+        Self::new(DataType::FixedSizeBinary(16), |answer| {
+            let answer = answer.strip_prefix("urn:uuid:").unwrap_or(answer);
+            let hex: String = answer
+                .trim_matches(['{', '}'])
+                .chars()
+                .filter(|&c| c != '-')
+                .collect();
+            if hex.len() != 32 {
+                return None;
+            }
+            let bytes = (0..16)
+                .map(|i| u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok())
+                .collect::<Option<Vec<u8>>>()?;
+            Some(ScalarValue::FixedSizeBinary(16, Some(bytes)))
+        })
+    }
+
+    pub fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    /// Fails for a cast to a type Arrow cannot cast `Utf8` to.
+    pub fn check(&self) -> Result<()> {
+        if self.parse.is_none() && !can_cast_types(&DataType::Utf8, &self.data_type) {
+            return plan_err!("ask_llm cannot produce answers of type {}", self.data_type);
+        }
+        Ok(())
+    }
+
+    pub fn build(&self, answers: Vec<Option<String>>) -> Result<ArrayRef> {
+        let Some(parse) = &self.parse else {
+            let answers: ArrayRef = Arc::new(StringArray::from(answers));
+            return match &self.data_type {
+                DataType::Utf8 => Ok(answers),
+                data_type => Ok(cast(&answers, data_type)?),
+            };
+        };
+        if answers.is_empty() {
+            return Ok(new_empty_array(&self.data_type));
+        }
+        let null = ScalarValue::try_from(&self.data_type)?;
+        let values = answers.iter().map(|answer| {
+            answer
+                .as_deref()
+                .and_then(|answer| parse(answer.trim()))
+                .unwrap_or_else(|| null.clone())
+        });
+        let array = ScalarValue::iter_to_array(values)?;
+        if array.data_type() != &self.data_type {
+            return Err(DataFusionError::Execution(format!(
+                "ask_llm output parser returned {} values, expected {}",
+                array.data_type(),
+                self.data_type
+            )));
+        }
+        Ok(array)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::AsArray;
+    use datafusion::arrow::datatypes::{Date32Type, Int64Type, TimestampNanosecondType};
+
+    fn answers(values: &[Option<&str>]) -> Vec<Option<String>> {
+        values.iter().map(|v| v.map(str::to_string)).collect()
+    }
+
+    #[test]
+    fn test_builtin_parsers_accept_decorated_answers() {
+        let booleans = OutputBuilder::boolean()
+            .build(answers(&[Some("Yes."), Some("no"), Some("maybe"), None]))
+            .unwrap();
+        let booleans = booleans.as_boolean();
+        assert!(booleans.value(0) && !booleans.value(1));
+        assert!(booleans.is_null(2) && booleans.is_null(3));
+
+        let ints = OutputBuilder::int64()
+            .build(answers(&[Some("1,200"), Some("about 3")]))
+            .unwrap();
+        assert_eq!(ints.as_primitive::<Int64Type>().value(0), 1200);
+        assert!(ints.is_null(1));
+
+        let decimals = OutputBuilder::decimal(10, 2)
+            .build(answers(&[Some("$1,019.99")]))
+            .unwrap();
+        assert_eq!(decimals.data_type(), &DataType::Decimal128(10, 2));
+        assert_eq!(decimals.as_primitive::<Decimal128Type>().value(0), 101999);
+
+        let timestamps = OutputBuilder::timestamp()
+            .build(answers(&[Some("2024-03-01T12:00:00Z")]))
+            .unwrap();
+        assert_eq!(
+            timestamps
+                .as_primitive::<TimestampNanosecondType>()
+                .value(0),
+            1_709_294_400_000_000_000
+        );
+
+        let uuids = OutputBuilder::uuid()
+            .build(answers(&[
+                Some("{67E55044-10B1-426F-9247-BB680E5FE0C8}"),
+                Some("not-a-uuid"),
+            ]))
+            .unwrap();
+        let uuids = uuids.as_fixed_size_binary();
+        assert_eq!(uuids.value(0)[..2], [0x67, 0xe5]);
+        assert!(uuids.is_null(1));
+    }
+
+    #[test]
+    fn test_dates_parse_with_a_format() {
+        let dates = OutputBuilder::date32_with_format("%d/%m/%Y")
+            .build(answers(&[
+                Some("02/01/1970."),
+                Some("1970-01-02"),
+                Some("31/02/2024"),
+            ]))
+            .unwrap();
+        let dates = dates.as_primitive::<Date32Type>();
+        assert_eq!(dates.value(0), 1);
+        assert!(dates.is_null(1) && dates.is_null(2));
+
+        let dates = OutputBuilder::date32_with_format("%B %d, %Y")
+            .build(answers(&[Some("March 1, 2024"), Some("sometime in March")]))
+            .unwrap();
+        let dates = dates.as_primitive::<Date32Type>();
+        assert_eq!(dates.value(0), 19783);
+        assert!(dates.is_null(1));
+
+        let timestamps = OutputBuilder::timestamp_with_format("%d.%m.%Y %H:%M")
+            .build(answers(&[Some("01.03.2024 12:00"), Some("01.03.2024")]))
+            .unwrap();
+        let timestamps = timestamps.as_primitive::<TimestampNanosecondType>();
+        assert_eq!(timestamps.value(0), 1_709_294_400_000_000_000);
+        assert!(timestamps.is_null(1));
+
+        // without a time of day in the format, the date is read as midnight
+        let midnight = OutputBuilder::timestamp_with_format("%Y-%m-%d")
+            .build(answers(&[Some("2024-03-01")]))
+            .unwrap();
+        assert_eq!(
+            midnight.as_primitive::<TimestampNanosecondType>().value(0),
+            1_709_251_200_000_000_000
+        );
+    }
+
+    #[test]
+    fn test_custom_parser_and_cast() {
+        let lengths = OutputBuilder::new(DataType::Int64, |answer| {
+            Some(ScalarValue::Int64(Some(answer.len() as i64)))
+        });
+        let built = lengths.build(answers(&[Some("abc")])).unwrap();
+        assert_eq!(built.as_primitive::<Int64Type>().value(0), 3);
+        assert_eq!(lengths.build(vec![]).unwrap().len(), 0);
+
+        // a parser returning another type than declared fails the batch
+        let wrong = OutputBuilder::new(DataType::Int64, |_| Some(ScalarValue::Boolean(Some(true))));
+        assert!(wrong.build(answers(&[Some("x")])).is_err());
+
+        let cast = OutputBuilder::cast(DataType::Int64);
+        let built = cast.build(answers(&[Some("7"), Some("seven")])).unwrap();
+        assert_eq!(built.as_primitive::<Int64Type>().value(0), 7);
+        assert!(built.is_null(1));
+        assert!(
+            OutputBuilder::cast(DataType::Struct(Default::default()))
+                .check()
+                .is_err()
+        );
+    }
+}
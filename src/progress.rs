@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Periodic progress logging for long `ask_llm` queries, see
+/// [`crate::llm_udf::AskLLM::with_progress_log`].
+///
+/// Every time the number of processed rows crosses a multiple of `interval`, a
+/// summary such as `ask_llm progress: processed 5000/8192 rows, 412 cache hits,
+/// 3 retries` is logged at info level. The total is the number of rows handed to the function so
+/// far, which grows batch by batch as DataFusion streams the input. The counters are
+/// atomics shared by all rayon workers and accumulate across invocations.
+#[derive(Debug)]
+pub struct ProgressLog {
+    interval: usize,
+    received: AtomicUsize,
+    processed: AtomicUsize,
+    cache_hits: AtomicUsize,
+    retries: AtomicUsize,
+}
+
+impl ProgressLog {
+    pub fn new(interval: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            received: AtomicUsize::new(0),
+            processed: AtomicUsize::new(0),
+            cache_hits: AtomicUsize::new(0),
+            retries: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn interval(&self) -> usize {
+        self.interval
+    }
+
+    pub(crate) fn add_received(&self, rows: usize) {
+        self.received.fetch_add(rows, Ordering::SeqCst);
+    }
+
+    pub(crate) fn add_cache_hits(&self, hits: usize) {
+        self.cache_hits.fetch_add(hits, Ordering::SeqCst);
+    }
+
+    pub(crate) fn add_retry(&self) {
+        self.retries.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Counts `rows` as processed and logs the summary if that crossed a multiple of
+    /// the interval. Returns whether it logged.
+    pub(crate) fn add_processed(&self, rows: usize) -> bool {
+        let before = self.processed.fetch_add(rows, Ordering::SeqCst);
+        if before / self.interval == (before + rows) / self.interval {
+            return false;
+        }
+        log::info!("ask_llm progress: {}", self.summary());
+        true
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "processed {}/{} rows, {} cache hits, {} retries",
+            self.processed.load(Ordering::SeqCst),
+            self.received.load(Ordering::SeqCst),
+            self.cache_hits.load(Ordering::SeqCst),
+            self.retries.load(Ordering::SeqCst)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_when_crossing_the_interval() {
+        let progress = ProgressLog::new(10);
+        progress.add_received(30);
+        assert!(!progress.add_processed(4));
+        assert!(!progress.add_processed(4));
+        assert!(progress.add_processed(4));
+        progress.add_cache_hits(2);
+        progress.add_retry();
+        assert!(progress.add_processed(10));
+        assert_eq!(
+            progress.summary(),
+            "processed 22/30 rows, 2 cache hits, 1 retries"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A token bucket that caps outgoing requests at `requests_per_second`, for hosted
+/// backends that enforce a request quota and answer bursts with 429s.
+///
+/// The bucket holds up to one second worth of requests (at least 1), so after a quiet
+/// period a burst of that size goes out at once and later requests are spaced evenly.
+/// [`RateLimiter::acquire`] waits asynchronously, so it works on the per-chunk tokio
+/// runtimes, and one limiter can be shared by every chunk through an `Arc`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_second: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: f64) -> Self {
+        let capacity = requests_per_second.max(1.0);
+        Self {
+            requests_per_second,
+            capacity,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn requests_per_second(&self) -> f64 {
+        self.requests_per_second
+    }
+
+    /// Waits until a request may be sent and takes its token.
+    pub async fn acquire(&self) {
+        while let Some(wait) = self.try_acquire() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // takes a token if one is available, otherwise returns how long until one is
+    fn try_acquire(&self) -> Option<Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.requests_per_second;
+        bucket.tokens = (bucket.tokens + refill).min(self.capacity);
+        bucket.refilled = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / self.requests_per_second,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_bursts_are_smoothed_to_the_rate() {
+        let limiter = Arc::new(RateLimiter::new(20.0));
+        let rt = crate::runtime::RuntimeConfig::multi_thread(4)
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        rt.block_on(async {
+            let tasks: Vec<_> = (0..30)
+                .map(|_| {
+                    let limiter = limiter.clone();
+                    tokio::spawn(async move { limiter.acquire().await })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+        });
+        // 20 go out as the initial burst, the other 10 at 20 per second
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(450), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    }
+}
@@ -0,0 +1,172 @@
+use datafusion::arrow::array::{ArrayRef, StringArray, StructArray};
+use datafusion::arrow::datatypes::{DataType, Field, Fields};
+use datafusion_common::cast::as_string_array;
+use datafusion_common::{Result, ScalarValue, plan_err};
+use datafusion_doc::Documentation;
+use datafusion_expr::ScalarUDFImpl;
+use datafusion_expr::{ColumnarValue, ScalarFunctionArgs, Signature, Volatility};
+use datafusion_macros::user_doc;
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::config::AiConfig;
+use crate::llm_udf::{AskLLM, ResponseParser, parse_llm_lines};
+
+/// Answers every row like `ask_llm`, but returns the model's raw output line next to
+/// the parsed value, to audit the parser on real data:
+///
+/// ```sql
+/// SELECT r['raw'], r['parsed'] FROM (SELECT ask_llm_raw('...', review) AS r FROM t)
+/// ```
+///
+/// `parsed` is the text after the first `->` of the line, post-processed by the
+/// [`AskLLM`] the calls are delegated to (normalization, NULL sentinels, output type,
+/// ...), so it is what `ask_llm` with the same settings would return. Rows without an
+/// attributable line, e.g. of a chunk whose response has a different number of answer
+/// lines than the chunk has rows, have a NULL `raw`.
+///
+/// This is a debugging aid and is not registered by default, see
+/// [`crate::AiUdf::DEFAULT`].
+#[user_doc(
+    doc_section(label = "AI functions"),
+    description = "Ask LLM and return each parsed answer with the raw output line it came from",
+    syntax_example = "ask_llm_raw('instruction', 'column_value')['raw']"
+)]
+#[derive(Debug)]
+pub struct AskLlmRaw {
+    ask_llm: AskLLM,
+    signature: Signature,
+}
+
+// answers with the whole `->` line, which the detail split of AskLlmRaw::with_ask_llm
+// takes apart again
+#[derive(Debug)]
+struct RawLines;
+
+impl ResponseParser for RawLines {
+    fn parse(&self, raw: &str, expected: usize) -> Vec<Option<String>> {
+        parse_llm_lines(raw, expected)
+            .into_iter()
+            .map(|(line, _)| Some(line))
+            .collect()
+    }
+}
+
+impl AskLlmRaw {
+    pub fn new() -> Self {
+        Self::with_config(&AiConfig::default())
+    }
+
+    /// Creates the UDF using the chat model from `config`.
+    pub fn with_config(config: &AiConfig) -> Self {
+        Self::with_ask_llm(AskLLM::with_config(config))
+    }
+
+    /// Asks `ask_llm` for the answers, with all its settings (prompt, chunking, retries,
+    /// post-processing, ...); its response parser is replaced by one that keeps the
+    /// answer lines.
+    pub fn with_ask_llm(ask_llm: AskLLM) -> Self {
+        let ask_llm = ask_llm
+            .with_name("ask_llm_raw")
+            .with_response_parser(RawLines)
+            .with_detail_split(|line| match line.split_once("->") {
+                Some((_, answer)) => (answer.trim().to_string(), Some(line.to_string())),
+                None => (line.to_string(), None),
+            });
+        Self {
+            ask_llm,
+            signature: Signature::exact(vec![DataType::Utf8, DataType::Utf8], Volatility::Volatile),
+        }
+    }
+
+    /// Registers the function under `name` instead of its default name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.ask_llm = self.ask_llm.with_name(name);
+        self
+    }
+
+    fn output_fields(&self) -> Fields {
+        Fields::from(vec![
+            Field::new("raw", DataType::Utf8, true),
+            Field::new("parsed", self.ask_llm.answer_type().clone(), true),
+        ])
+    }
+}
+
+impl Default for AskLlmRaw {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScalarUDFImpl for AskLlmRaw {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn name(&self) -> &str {
+        self.ask_llm.name()
+    }
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+    fn return_type(&self, args: &[DataType]) -> Result<DataType> {
+        if !matches!(args.first(), Some(&DataType::Utf8)) {
+            return plan_err!("ask_llm_raw only accepts Utf8 arguments");
+        }
+        Ok(DataType::Struct(self.output_fields()))
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        let ScalarFunctionArgs { args, .. } = args;
+        let (instruction, col_values) = match args.as_slice() {
+            [
+                ColumnarValue::Scalar(ScalarValue::Utf8(instruction)),
+                ColumnarValue::Array(col_values),
+            ] => (instruction.as_deref().unwrap_or_default(), col_values),
+            _ => {
+                return plan_err!(
+                    "ask_llm_raw only accepts 2 arguments in the form of 'instruction' (string), 'column_value' (column)"
+                );
+            }
+        };
+        let col_values = as_string_array(col_values.as_ref())?;
+        let (parsed, raw) = self
+            .ask_llm
+            .answer_with_details(instruction, col_values.iter().collect())?;
+        let columns: Vec<ArrayRef> = vec![Arc::new(StringArray::from(raw)), parsed];
+        let result = StructArray::try_new(self.output_fields(), columns, None)?;
+        Ok(ColumnarValue::Array(Arc::new(result)))
+    }
+
+    fn documentation(&self) -> Option<&Documentation> {
+        self.doc()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_udf::tests::{invoke, spawn_mock_server, strings};
+    use crate::normalize::Normalization;
+    use datafusion::arrow::array::AsArray;
+
+    #[test]
+    fn test_lines_are_returned_next_to_the_parsed_answers() {
+        let (host, _) = spawn_mock_server(|_| {
+            let body = r#"{"message":{"content":"Sure, here you go:\n1 -> Positive\n2 ->  NEGATIVE "},"done_reason":"stop"}"#;
+            (200, body.to_string())
+        });
+        let config = AiConfig::default().with_ollama_host(host);
+        let udf = AskLlmRaw::with_ask_llm(
+            AskLLM::with_config(&config).with_normalization(Normalization::categorical()),
+        );
+        let result = invoke(&udf, "Categorize", vec![strings(&["Great!", "Awful."])]).unwrap();
+        let result = result.as_struct();
+        let raw = result.column_by_name("raw").unwrap().as_string::<i32>();
+        let parsed = result.column_by_name("parsed").unwrap().as_string::<i32>();
+        assert_eq!(raw.value(0), "1 -> Positive");
+        assert_eq!(raw.value(1), "2 ->  NEGATIVE ");
+        assert_eq!(parsed.value(0), "positive");
+        assert_eq!(parsed.value(1), "negative");
+    }
+}
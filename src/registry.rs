@@ -0,0 +1,355 @@
+use datafusion::prelude::SessionContext;
+use datafusion_expr::ScalarUDF;
+use serde_json::{Value, json};
+
+use crate::cancellation::Cancellation;
+use crate::columns_udf::AskLlmColumns;
+use crate::config::{AiConfig, Backend};
+use crate::context_udf::AskLlmWithContext;
+use crate::date_udf::AskLlmDate;
+use crate::dry_run::DryRun;
+use crate::embed_udf::OllamaEmbed;
+use crate::explain_udf::AskLlmExplain;
+use crate::info_udf::AskLlmInfo;
+use crate::json_udf::AskLlmJson;
+use crate::list_udf::AskLlmList;
+use crate::llama_udf::AskLlamaWithConfidence;
+use crate::llm_udf::AskLLM;
+use crate::model_profile::ModelProfile;
+use crate::raw_udf::AskLlmRaw;
+use crate::shared_backend::SharedBackend;
+use crate::warmup::{WarmupReport, warmup};
+
+/// The UDFs provided by this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AiUdf {
+    /// Registered as `ask_llm` by default.
+    AskLlm,
+    /// Registered as `ollama_embed` by default.
+    OllamaEmbed,
+    /// Registered as `ask_llm_list` by default.
+    AskLlmList,
+    /// Registered as `ask_llm_info` by default.
+    Info,
+    /// Registered as `ask_llm_json` by default, see [`RegisterOptions::json_schema`].
+    AskLlmJson,
+    /// Registered as `ask_llm_explain` by default.
+    Explain,
+    /// Registered as `ask_llm_context` by default.
+    Context,
+    /// Registered as `ask_llm_raw` by default.
+    Raw,
+    /// Registered as `ask_llm_columns` by default.
+    Columns,
+    /// Registered as `ask_llm_date` by default.
+    Date,
+    /// Registered as `ask_llm_confidence` by default, only with [`Backend::Llama`].
+    Confidence,
+}
+
+impl AiUdf {
+    pub const ALL: [AiUdf; 11] = [
+        AiUdf::AskLlm,
+        AiUdf::OllamaEmbed,
+        AiUdf::AskLlmList,
+        AiUdf::Info,
+        AiUdf::AskLlmJson,
+        AiUdf::Explain,
+        AiUdf::Context,
+        AiUdf::Raw,
+        AiUdf::Columns,
+        AiUdf::Date,
+        AiUdf::Confidence,
+    ];
+
+    /// The UDFs registered unless [`RegisterOptions::with_udfs`] selects others: all
+    /// but `ask_llm_explain`, whose reasons make every response several times longer,
+    /// and the debugging aid `ask_llm_raw`.
+    pub const DEFAULT: [AiUdf; 9] = [
+        AiUdf::AskLlm,
+        AiUdf::OllamaEmbed,
+        AiUdf::AskLlmList,
+        AiUdf::Info,
+        AiUdf::AskLlmJson,
+        AiUdf::Context,
+        AiUdf::Columns,
+        AiUdf::Date,
+        AiUdf::Confidence,
+    ];
+
+    pub fn default_name(&self) -> &'static str {
+        match self {
+            AiUdf::AskLlm => "ask_llm",
+            AiUdf::OllamaEmbed => "ollama_embed",
+            AiUdf::AskLlmList => "ask_llm_list",
+            AiUdf::Info => "ask_llm_info",
+            AiUdf::AskLlmJson => "ask_llm_json",
+            AiUdf::Explain => "ask_llm_explain",
+            AiUdf::Context => "ask_llm_context",
+            AiUdf::Raw => "ask_llm_raw",
+            AiUdf::Columns => "ask_llm_columns",
+            AiUdf::Date => "ask_llm_date",
+            AiUdf::Confidence => "ask_llm_confidence",
+        }
+    }
+}
+
+/// Controls which UDFs [`register_ai_udfs_with`] registers and under which names.
+#[derive(Debug, Clone)]
+pub struct RegisterOptions {
+    /// The UDFs to register. Defaults to [`AiUdf::DEFAULT`].
+    pub udfs: Vec<AiUdf>,
+    /// Prepended to each default name, e.g. `ai_` registers `ai_ask_llm`.
+    pub name_prefix: Option<String>,
+    /// Whether [`register_ai_udfs_async`] preloads the models before registering.
+    /// Off by default so tests do not need a running backend.
+    pub warmup: bool,
+    /// Registers `ask_llm` in dry-run mode, see [`AskLLM::with_dry_run`].
+    pub dry_run: Option<DryRun>,
+    /// The chat template and system prompts to use. `None` picks them from the chat
+    /// model name with [`ModelProfile::for_model`], and those of `ask_llm_confidence`
+    /// from the GGUF file name.
+    pub model_profile: Option<ModelProfile>,
+    /// The schema every answer of `ask_llm_json` must match, see [`AskLlmJson`]. Any
+    /// JSON value by default.
+    pub json_schema: Value,
+}
+
+impl Default for RegisterOptions {
+    fn default() -> Self {
+        Self {
+            udfs: AiUdf::DEFAULT.to_vec(),
+            name_prefix: None,
+            warmup: false,
+            dry_run: None,
+            model_profile: None,
+            json_schema: json!({}),
+        }
+    }
+}
+
+impl RegisterOptions {
+    pub fn with_udfs(mut self, udfs: Vec<AiUdf>) -> Self {
+        self.udfs = udfs;
+        self
+    }
+
+    pub fn with_name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.name_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn with_warmup(mut self, warmup: bool) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: DryRun) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    pub fn with_model_profile(mut self, model_profile: ModelProfile) -> Self {
+        self.model_profile = Some(model_profile);
+        self
+    }
+
+    pub fn with_json_schema(mut self, json_schema: Value) -> Self {
+        self.json_schema = json_schema;
+        self
+    }
+
+    /// The profile the UDFs are configured with for `config`.
+    pub fn model_profile_for(&self, config: &AiConfig) -> ModelProfile {
+        self.model_profile
+            .clone()
+            .unwrap_or_else(|| ModelProfile::for_model(&config.chat_model))
+    }
+
+    pub fn name_for(&self, udf: AiUdf) -> String {
+        format!(
+            "{}{}",
+            self.name_prefix.as_deref().unwrap_or_default(),
+            udf.default_name()
+        )
+    }
+}
+
+/// Registers the [default](AiUdf::DEFAULT) AI UDFs on `ctx` under their default
+/// names, wiring the chat UDFs to the chat model and `ollama_embed` to the embedding
+/// model from `config`. `ask_llm_confidence` is only registered with
+/// [`Backend::Llama`], and loads the model at [`AiConfig::model_path`]. If a
+/// [`SharedBackend`] is installed in the session config, the chat UDFs use it.
+/// Returns the cancellation handle shared by the registered chat UDFs.
+pub fn register_ai_udfs(ctx: &SessionContext, config: &AiConfig) -> anyhow::Result<Cancellation> {
+    register_ai_udfs_with(ctx, config, &RegisterOptions::default())
+}
+
+/// Like [`register_ai_udfs`], but registers only the UDFs selected in `options`
+/// and applies its name prefix. Fails if the llama model cannot be loaded.
+pub fn register_ai_udfs_with(
+    ctx: &SessionContext,
+    config: &AiConfig,
+    options: &RegisterOptions,
+) -> anyhow::Result<Cancellation> {
+    let cancellation = Cancellation::new();
+    let profile = options.model_profile_for(config);
+    let shared_backend = SharedBackend::from_session(ctx);
+    // the AskLLM the chat UDFs delegate to, sharing the backend and the cancellation
+    let base_ask_llm = |system_prompt: &str| {
+        let mut ask_llm = AskLLM::with_config(config)
+            .with_system_prompt(system_prompt)
+            .with_chat_template(profile.chat_template)
+            .with_cancellation(cancellation.clone());
+        if let Some(backend) = &shared_backend {
+            ask_llm = ask_llm.with_shared_backend(backend.clone());
+        }
+        ask_llm
+    };
+    for &udf in &options.udfs {
+        let name = options.name_for(udf);
+        let system_prompt = profile.system_prompt(udf).unwrap_or_default();
+        let scalar_udf = match udf {
+            AiUdf::AskLlm => {
+                let mut ask_llm = base_ask_llm(system_prompt).with_name(name);
+                if let Some(dry_run) = &options.dry_run {
+                    ask_llm = ask_llm.with_dry_run(dry_run.clone());
+                }
+                ScalarUDF::from(ask_llm)
+            }
+            AiUdf::OllamaEmbed => ScalarUDF::from(OllamaEmbed::with_config(config).with_name(name)),
+            AiUdf::AskLlmList => ScalarUDF::from(
+                AskLlmList::with_config(config)
+                    .with_name(name)
+                    .with_system_prompt(system_prompt),
+            ),
+            AiUdf::Info => ScalarUDF::from(AskLlmInfo::with_config(config).with_name(name)),
+            AiUdf::AskLlmJson => ScalarUDF::from(
+                AskLlmJson::with_ask_llm(base_ask_llm(system_prompt), options.json_schema.clone())
+                    .with_name(name),
+            ),
+            AiUdf::Explain => ScalarUDF::from(
+                AskLlmExplain::with_ask_llm(base_ask_llm(system_prompt)).with_name(name),
+            ),
+            AiUdf::Context => ScalarUDF::from(
+                AskLlmWithContext::with_ask_llm(base_ask_llm(system_prompt)).with_name(name),
+            ),
+            AiUdf::Raw => ScalarUDF::from(
+                AskLlmRaw::with_ask_llm(base_ask_llm(system_prompt)).with_name(name),
+            ),
+            AiUdf::Columns => ScalarUDF::from(
+                AskLlmColumns::with_ask_llm(base_ask_llm(system_prompt)).with_name(name),
+            ),
+            AiUdf::Date => ScalarUDF::from(
+                AskLlmDate::with_ask_llm(base_ask_llm(system_prompt)).with_name(name),
+            ),
+            AiUdf::Confidence => {
+                let (Backend::Llama, Some(model_path)) = (config.backend, &config.model_path)
+                else {
+                    continue;
+                };
+                // the profile picked from the chat model does not describe the GGUF
+                // model; without an explicit one, the template detected on load is kept
+                let llama_profile = options
+                    .model_profile
+                    .clone()
+                    .unwrap_or_else(|| ModelProfile::for_model(model_path));
+                let mut ask_llama = AskLlamaWithConfidence::with_config(config)?
+                    .with_name(name)
+                    .with_system_prompt(llama_profile.system_prompt(udf).unwrap_or_default())
+                    .with_cancellation(cancellation.clone());
+                if options.model_profile.is_some() {
+                    ask_llama = ask_llama.with_chat_template(llama_profile.chat_template);
+                }
+                ScalarUDF::from(ask_llama)
+            }
+        };
+        ctx.register_udf(scalar_udf);
+    }
+    Ok(cancellation)
+}
+
+/// Like [`register_ai_udfs_with`], but first runs a [`warmup`] pass if
+/// `options.warmup` is set. Nothing is registered if the warmup fails.
+pub async fn register_ai_udfs_async(
+    ctx: &SessionContext,
+    config: &AiConfig,
+    options: &RegisterOptions,
+) -> anyhow::Result<(Cancellation, WarmupReport)> {
+    let report = if options.warmup {
+        warmup(config, options).await?
+    } else {
+        WarmupReport::default()
+    };
+    Ok((register_ai_udfs_with(ctx, config, options)?, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_subset_with_prefix() {
+        let ctx = SessionContext::new();
+        let options = RegisterOptions::default()
+            .with_udfs(vec![AiUdf::AskLlm])
+            .with_name_prefix("ai_");
+        register_ai_udfs_with(&ctx, &AiConfig::default(), &options).unwrap();
+        let state = ctx.state();
+        assert!(state.scalar_functions().contains_key("ai_ask_llm"));
+        assert!(!state.scalar_functions().contains_key("ask_llm"));
+        assert!(!state.scalar_functions().contains_key("ai_ollama_embed"));
+    }
+
+    #[test]
+    fn test_register_every_udf() {
+        let ctx = SessionContext::new();
+        let options = RegisterOptions::default().with_udfs(AiUdf::ALL.to_vec());
+        register_ai_udfs_with(&ctx, &AiConfig::default(), &options).unwrap();
+        let state = ctx.state();
+        for udf in AiUdf::ALL {
+            // only the llama backend serves ask_llm_confidence
+            let registered = udf != AiUdf::Confidence;
+            assert_eq!(
+                state.scalar_functions().contains_key(udf.default_name()),
+                registered,
+                "{}",
+                udf.default_name()
+            );
+        }
+
+        let ctx = SessionContext::new();
+        register_ai_udfs(&ctx, &AiConfig::default()).unwrap();
+        assert!(ctx.state().scalar_functions().contains_key("ask_llm_date"));
+        assert!(
+            !ctx.state()
+                .scalar_functions()
+                .contains_key("ask_llm_explain")
+        );
+    }
+
+    #[test]
+    fn test_register_differently_configured_instances() {
+        let ctx = SessionContext::new();
+        let fast = AskLLM::with_config(&AiConfig::default().with_chat_model("llama3.2:1b"))
+            .with_name("ask_llm_fast");
+        let accurate = AskLLM::with_config(&AiConfig::default().with_chat_model("llama3.1:70b"))
+            .with_name("ask_llm_accurate");
+        ctx.register_udf(ScalarUDF::from(fast));
+        ctx.register_udf(ScalarUDF::from(accurate));
+        let state = ctx.state();
+        assert!(state.scalar_functions().contains_key("ask_llm_fast"));
+        assert!(state.scalar_functions().contains_key("ask_llm_accurate"));
+    }
+
+    #[tokio::test]
+    async fn test_async_registration_skips_warmup_by_default() {
+        let ctx = SessionContext::new();
+        let (_, report) =
+            register_ai_udfs_async(&ctx, &AiConfig::default(), &RegisterOptions::default())
+                .await
+                .unwrap();
+        assert!(report.checks.is_empty());
+        assert!(ctx.state().scalar_functions().contains_key("ask_llm"));
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Caps the total number of retries across all chunks of a single UDF invocation.
+///
+/// Each chunk still has its own retry limit; the budget bounds the aggregate so a
+/// failing backend cannot multiply the number of calls. Once the budget is spent,
+/// further failures are returned immediately.
+///
+/// It is enforced by `ask_llm` and the UDFs built on it (`ask_llm_json`,
+/// `ask_llm_date`, ...) and by `ask_llm_confidence`; `ask_llm_list` does not retry.
+#[derive(Debug)]
+pub struct RetryBudget {
+    remaining: Option<AtomicUsize>,
+    exhausted_logged: AtomicBool,
+}
+
+impl RetryBudget {
+    /// A budget of `max_retries` total retries, or unlimited if `None`.
+    pub fn new(max_retries: Option<usize>) -> Self {
+        Self {
+            remaining: max_retries.map(AtomicUsize::new),
+            exhausted_logged: AtomicBool::new(false),
+        }
+    }
+
+    /// Takes one retry from the budget. Returns `false` once the budget is exhausted.
+    pub fn try_acquire(&self) -> bool {
+        let Some(remaining) = &self.remaining else {
+            return true;
+        };
+        let acquired = remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if !acquired && !self.exhausted_logged.swap(true, Ordering::SeqCst) {
+            log::warn!("retry budget exhausted, further chunk failures will not be retried");
+        }
+        acquired
+    }
+}
+
+/// The sampling seed for `attempt` (0 is the first request) of a chunk whose first
+/// request uses `seed`. Retrying with the seed that produced a malformed answer tends
+/// to reproduce it, so every retry gets a different seed, derived deterministically
+/// so that a run with a fixed seed stays reproducible as a whole.
+pub fn attempt_seed(seed: u32, attempt: usize) -> u32 {
+    if attempt == 0 {
+        return seed;
+    }
+    // a splitmix64 step, so consecutive attempts get unrelated seeds
+    let mut z = (seed as u64).wrapping_add((attempt as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    ((z ^ (z >> 31)) >> 32) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retries_get_distinct_deterministic_seeds() {
+        assert_eq!(attempt_seed(42, 0), 42);
+        assert_ne!(attempt_seed(42, 1), attempt_seed(43, 1));
+        let seeds: Vec<u32> = (0..4).map(|attempt| attempt_seed(42, attempt)).collect();
+        let mut distinct = seeds.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), seeds.len());
+    }
+
+    #[test]
+    fn test_budget_is_shared_and_bounded() {
+        let budget = RetryBudget::new(Some(2));
+        assert!(budget.try_acquire());
+        assert!(budget.try_acquire());
+        assert!(!budget.try_acquire());
+        assert!(RetryBudget::new(None).try_acquire());
+    }
+}
@@ -0,0 +1,88 @@
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+type RoutePredicate = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Which rows [`crate::llm_udf::AskLLM::with_routing`] sends to the premium model;
+/// all other rows go to the UDF's own model.
+///
+/// Routing trades cost for quality on every row, independently of failures: unlike
+/// [`crate::llm_udf::AskLLM::with_fallback`], a routed row is never sent to the other
+/// model. NULL inputs are never routed.
+///
+/// | policy                   | routes                                               |
+/// |--------------------------|------------------------------------------------------|
+/// | [`Routing::fraction`]    | about `fraction` of the rows, chosen by their value  |
+/// | [`Routing::predicate`]   | the rows whose value satisfies the predicate         |
+#[derive(Clone)]
+pub enum Routing {
+    Fraction(f64),
+    Predicate(RoutePredicate),
+}
+
+impl std::fmt::Debug for Routing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Routing::Fraction(fraction) => f.debug_tuple("Fraction").field(fraction).finish(),
+            Routing::Predicate(_) => f.debug_tuple("Predicate").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl Routing {
+    /// Routes about `fraction` (clamped to 0..=1) of the rows. The choice is a hash
+    /// of the row's value rather than a random draw, so a value is routed the same
+    /// way in every query and repeated values share their answer cache entries.
+    pub fn fraction(fraction: f64) -> Self {
+        Routing::Fraction(fraction.clamp(0.0, 1.0))
+    }
+
+    /// Routes the rows whose value satisfies `predicate`, e.g. long inputs.
+    pub fn predicate(predicate: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        Routing::Predicate(Arc::new(predicate))
+    }
+
+    /// Whether the row with `value` goes to the premium model.
+    pub fn routes(&self, value: &str) -> bool {
+        match self {
+            Routing::Fraction(fraction) if *fraction >= 1.0 => true,
+            Routing::Fraction(fraction) => {
+                let digest = Sha256::digest(value.as_bytes());
+                let bucket = u64::from_be_bytes(digest[..8].try_into().unwrap());
+                (bucket as f64 / u64::MAX as f64) < *fraction
+            }
+            Routing::Predicate(predicate) => predicate(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fraction_routes_a_stable_share_of_values() {
+        let routing = Routing::fraction(0.25);
+        let values: Vec<String> = (0..4000).map(|i| format!("review {i}")).collect();
+        let routed = values.iter().filter(|value| routing.routes(value)).count();
+        assert!((800..1200).contains(&routed), "{routed} routed");
+        assert!(
+            values
+                .iter()
+                .all(|value| routing.routes(value) == routing.routes(value))
+        );
+        assert!(
+            !values
+                .iter()
+                .any(|value| Routing::fraction(0.0).routes(value))
+        );
+        assert!(
+            values
+                .iter()
+                .all(|value| Routing::fraction(2.0).routes(value))
+        );
+
+        let long = Routing::predicate(|value| value.len() > 5);
+        assert!(long.routes("a long review") && !long.routes("ok"));
+    }
+}
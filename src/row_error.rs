@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+/// Why `ask_llm` could not answer a row.
+///
+/// By default the row's answer is the error message, e.g. `Error processing chunk: ...`
+/// (timeouts answer NULL). With [`crate::llm_udf::AskLLM::with_error_column`] the
+/// message goes to a separate `error` field instead, so failures can be selected with
+/// `WHERE error IS NOT NULL`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowError {
+    /// The backend request failed after its retries.
+    Backend(String),
+    /// The response did not contain one answer per row.
+    CountMismatch(String),
+    /// Processing the chunk panicked.
+    Panic(String),
+    /// The chunk did not finish within the chunk deadline.
+    Timeout(Duration),
+}
+
+impl RowError {
+    /// The answer a failed row gets without an error column.
+    pub fn answer(&self) -> Option<String> {
+        match self {
+            RowError::Timeout(_) => None,
+            error => Some(error.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RowError::Backend(error) => write!(f, "Error processing chunk: {}", error),
+            // the message already names the counts and the parsed results
+            RowError::CountMismatch(message) => write!(f, "{}", message),
+            RowError::Panic(message) => write!(f, "Error processing chunk: panicked: {}", message),
+            RowError::Timeout(deadline) => {
+                write!(f, "Error: chunk exceeded the {:?} deadline", deadline)
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use tokio::runtime::{Builder, Runtime};
+
+/// The kind of tokio runtime a chunk's requests run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RuntimeFlavor {
+    /// A multi-thread runtime with its own worker threads.
+    #[default]
+    MultiThread,
+    /// Runs the chunk's future on the calling rayon worker, without extra threads.
+    CurrentThread,
+}
+
+/// How the sync-to-async bridge of `ask_llm` builds the tokio runtime its chunks are
+/// driven by, see [`crate::llm_udf::AskLLM::with_runtime`].
+///
+/// A chunk is a handful of sequential HTTP requests, which are I/O-bound and leave a
+/// runtime idle most of the time. Concurrency already comes from the rayon pool (see
+/// `max_concurrent_prompts`), so [`RuntimeConfig::current_thread`] is the recommended
+/// setting: it has no worker threads of its own, the rayon workers blocking on it
+/// drive it in turn. The multi-thread
+/// default, sized to the CPU count unless `worker_threads` is set, is kept for
+/// compatibility. Both enable the I/O and time drivers, which chunk deadlines need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RuntimeConfig {
+    pub flavor: RuntimeFlavor,
+    /// Worker threads of a multi-thread runtime; `None` uses tokio's default.
+    pub worker_threads: Option<usize>,
+}
+
+impl RuntimeConfig {
+    pub fn current_thread() -> Self {
+        Self {
+            flavor: RuntimeFlavor::CurrentThread,
+            worker_threads: None,
+        }
+    }
+
+    pub fn multi_thread(worker_threads: usize) -> Self {
+        Self {
+            flavor: RuntimeFlavor::MultiThread,
+            worker_threads: Some(worker_threads.max(1)),
+        }
+    }
+
+    pub fn build(&self) -> std::io::Result<Runtime> {
+        let mut builder = match self.flavor {
+            RuntimeFlavor::MultiThread => Builder::new_multi_thread(),
+            RuntimeFlavor::CurrentThread => Builder::new_current_thread(),
+        };
+        if let (RuntimeFlavor::MultiThread, Some(worker_threads)) =
+            (self.flavor, self.worker_threads)
+        {
+            builder.worker_threads(worker_threads);
+        }
+        builder.enable_all().build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_runtimes_drive_timers() {
+        for config in [
+            RuntimeConfig::default(),
+            RuntimeConfig::multi_thread(2),
+            RuntimeConfig::current_thread(),
+        ] {
+            let rt = config.build().unwrap();
+            let sleep = tokio::time::sleep(Duration::from_secs(5));
+            let timed_out = rt.block_on(tokio::time::timeout(Duration::from_millis(10), sleep));
+            assert!(timed_out.is_err(), "{config:?}");
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use datafusion::prelude::SessionContext;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+use crate::cache::ShardedCache;
+use crate::ollama_utils::{ChatResponse, HttpSettings};
+use crate::runtime::RuntimeConfig;
+
+/// Backend state shared by every `ask_llm` registered on a session, so a long-lived
+/// [`SessionContext`] (e.g. in a server) keeps it across queries instead of rebuilding
+/// it for each one:
+///
+/// - one HTTP client, whose connection pool keeps connections to Ollama open,
+/// - one tokio runtime that drives every chunk and the pooled connections, in place
+///   of a runtime and client per UDF (see [`crate::llm_udf::AskLLM::with_runtime`]),
+/// - one response cache (see [`crate::llm_udf::AskLLM::with_response_cache`]), keyed
+///   by model, prompt and generation settings, so UDFs with different settings can
+///   share it without replaying each other's responses.
+///
+/// The model itself stays loaded in Ollama between queries; run a warmup once (see
+/// [`crate::register_ai_udfs_async`]) to load it before the first query.
+///
+/// Install it as an extension of the session config before creating the context;
+/// [`crate::register_ai_udfs`] then picks it up:
+///
+/// ```ignore
+/// let config = SessionConfig::new().with_extension(Arc::new(SharedBackend::new()?));
+/// let ctx = SessionContext::new_with_config(config);
+/// register_ai_udfs(&ctx, &AiConfig::default())?;
+/// ```
+///
+/// Scalar UDFs cannot read the session during execution, so UDFs built by hand use
+/// it through [`crate::llm_udf::AskLLM::with_shared_backend`].
+#[derive(Debug)]
+pub struct SharedBackend {
+    client: reqwest::Client,
+    // only None while dropping, see the Drop impl
+    runtime: Option<Runtime>,
+    response_cache: Arc<ShardedCache<[u8; 32], ChatResponse>>,
+}
+
+impl SharedBackend {
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::new(),
+            // rayon workers block on it concurrently, so it must be multi-threaded
+            runtime: Some(RuntimeConfig::default().build()?),
+            response_cache: Arc::new(ShardedCache::for_workers(
+                std::thread::available_parallelism().map_or(4, |n| n.get()),
+            )),
+        })
+    }
+
+    /// A backend whose runtime is built with `runtime` and whose client with `http`, as
+    /// the UDFs build for themselves when the session has none installed.
+    pub fn with_settings(runtime: RuntimeConfig, http: &HttpSettings) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: http.client()?,
+            runtime: Some(runtime.build()?),
+            response_cache: Arc::new(ShardedCache::for_workers(
+                std::thread::available_parallelism().map_or(4, |n| n.get()),
+            )),
+        })
+    }
+
+    /// Builds the shared client with `http`, e.g. the [`crate::config::AiConfig::http`]
+    /// of the UDFs, so its pooled connections follow those settings.
+    pub fn with_http(mut self, http: &HttpSettings) -> anyhow::Result<Self> {
+        self.client = http.client()?;
+        Ok(self)
+    }
+
+    /// The backend installed in the session config of `ctx`, if any.
+    pub fn from_session(ctx: &SessionContext) -> Option<Arc<Self>> {
+        ctx.state().config().get_extension::<Self>()
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        self.runtime
+            .as_ref()
+            .expect("the runtime is only taken when the backend is dropped")
+    }
+
+    pub fn response_cache(&self) -> Arc<ShardedCache<[u8; 32], ChatResponse>> {
+        self.response_cache.clone()
+    }
+}
+
+// the last session holding the backend is often dropped inside async code (e.g. a
+// server's request handler), where dropping a runtime panics, so it is shut down in
+// the background instead
+impl Drop for SharedBackend {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::prelude::SessionConfig;
+
+    #[test]
+    fn test_backend_is_found_in_the_session() {
+        assert!(SharedBackend::from_session(&SessionContext::new()).is_none());
+        let backend = Arc::new(SharedBackend::new().unwrap());
+        let config = SessionConfig::new().with_extension(backend.clone());
+        let ctx = SessionContext::new_with_config(config);
+        let installed = SharedBackend::from_session(&ctx).unwrap();
+        assert!(Arc::ptr_eq(&installed, &backend));
+    }
+
+    #[tokio::test]
+    async fn test_session_can_be_dropped_in_async_code() {
+        let backend = Arc::new(SharedBackend::new().unwrap());
+        let config = SessionConfig::new().with_extension(backend);
+        let ctx = SessionContext::new_with_config(config);
+        // the session holds the last reference to the backend and its runtime
+        drop(ctx);
+    }
+}
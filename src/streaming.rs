@@ -0,0 +1,73 @@
+use serde_json::Value;
+
+/// Incremental parser for Ollama's NDJSON streaming body.
+///
+/// Network chunks can end anywhere, including inside a JSON line or inside a
+/// multi-byte UTF-8 sequence. Bytes are buffered until a full `\n`-terminated line
+/// is available, and only complete lines are decoded, so nothing is lost or mangled.
+#[derive(Debug, Default)]
+pub struct NdjsonBuffer {
+    pending: Vec<u8>,
+}
+
+impl NdjsonBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `bytes` and returns every complete line parsed so far.
+    /// The unterminated tail stays buffered until more bytes arrive.
+    pub fn push(&mut self, bytes: &[u8]) -> anyhow::Result<Vec<Value>> {
+        self.pending.extend_from_slice(bytes);
+        let mut values = Vec::new();
+        while let Some(newline) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=newline).collect();
+            if let Some(value) = parse_line(&line)? {
+                values.push(value);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Parses whatever is left once the body has ended (a final line without `\n`).
+    pub fn finish(&mut self) -> anyhow::Result<Option<Value>> {
+        let line = std::mem::take(&mut self.pending);
+        parse_line(&line)
+    }
+}
+
+fn parse_line(line: &[u8]) -> anyhow::Result<Option<Value>> {
+    let line = std::str::from_utf8(line)?.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(line)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_mid_character_and_mid_line() {
+        let body = "{\"message\":{\"content\":\"caf\u{e9} \"}}\n{\"message\":{\"content\":\"\u{1f600}\"},\"done\":true}\n";
+        let bytes = body.as_bytes();
+        // split inside the two-byte 'é' and inside the four-byte emoji
+        let e_acute = body.find('\u{e9}').unwrap() + 1;
+        let emoji = body.find('\u{1f600}').unwrap() + 2;
+
+        let mut buffer = NdjsonBuffer::new();
+        let mut values = buffer.push(&bytes[..e_acute]).unwrap();
+        assert!(values.is_empty());
+        values.extend(buffer.push(&bytes[e_acute..emoji]).unwrap());
+        assert_eq!(values.len(), 1);
+        values.extend(buffer.push(&bytes[emoji..]).unwrap());
+        assert!(buffer.finish().unwrap().is_none());
+
+        let content: String = values
+            .iter()
+            .filter_map(|v| v["message"]["content"].as_str())
+            .collect();
+        assert_eq!(content, "caf\u{e9} \u{1f600}");
+    }
+}
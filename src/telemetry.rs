@@ -0,0 +1,46 @@
+//! Metric names emitted through the [`metrics`] facade.
+//!
+//! Nothing is recorded unless the application installs a recorder (e.g.
+//! `metrics-exporter-prometheus`); without one every call is a no-op.
+
+use std::sync::Arc;
+
+/// Counter of requests sent to the backend, labelled by `model` and `status` (`ok`/`error`).
+pub const LLM_REQUESTS_TOTAL: &str = "llm_requests_total";
+/// Histogram of backend request latency in seconds, labelled by `model`.
+pub const LLM_REQUEST_DURATION_SECONDS: &str = "llm_request_duration_seconds";
+/// Counter of chunks where the model returned a different number of answers than rows.
+pub const LLM_COUNT_MISMATCH_TOTAL: &str = "llm_count_mismatch_total";
+/// Counter of results served from the cache instead of the backend.
+pub const LLM_CACHE_HITS_TOTAL: &str = "llm_cache_hits_total";
+/// Counter of chunk requests retried after a retryable error, labelled by `model`.
+pub const LLM_RETRIES_TOTAL: &str = "llm_retries_total";
+/// Counter of chunks sent to the fallback model after the retries failed, labelled by
+/// `model` (the primary one) and `fallback`.
+pub const LLM_FALLBACKS_TOTAL: &str = "llm_fallbacks_total";
+
+// the model label is shared rather than copied, as it is recorded for every request
+pub(crate) fn record_request(model: &Arc<str>, ok: bool, duration: std::time::Duration) {
+    let status = if ok { "ok" } else { "error" };
+    metrics::counter!(LLM_REQUESTS_TOTAL, "model" => model.clone(), "status" => status)
+        .increment(1);
+    metrics::histogram!(LLM_REQUEST_DURATION_SECONDS, "model" => model.clone())
+        .record(duration.as_secs_f64());
+}
+
+pub(crate) fn record_count_mismatch(model: &Arc<str>) {
+    metrics::counter!(LLM_COUNT_MISMATCH_TOTAL, "model" => model.clone()).increment(1);
+}
+
+pub(crate) fn record_cache_hits(model: &Arc<str>, hits: usize) {
+    metrics::counter!(LLM_CACHE_HITS_TOTAL, "model" => model.clone()).increment(hits as u64);
+}
+
+pub(crate) fn record_retry(model: &Arc<str>) {
+    metrics::counter!(LLM_RETRIES_TOTAL, "model" => model.clone()).increment(1);
+}
+
+pub(crate) fn record_fallback(model: &Arc<str>, fallback: &Arc<str>) {
+    metrics::counter!(LLM_FALLBACKS_TOTAL, "model" => model.clone(), "fallback" => fallback.clone())
+        .increment(1);
+}
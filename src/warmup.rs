@@ -0,0 +1,145 @@
+use anyhow::Context;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+
+use crate::config::{AiConfig, Backend};
+use crate::ollama_utils::OllamaApp;
+use crate::registry::{AiUdf, RegisterOptions};
+
+/// What a warmup pass verified, one line per check.
+#[derive(Debug, Clone, Default)]
+pub struct WarmupReport {
+    pub checks: Vec<String>,
+}
+
+impl fmt::Display for WarmupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.checks.is_empty() {
+            return write!(f, "warmup skipped");
+        }
+        write!(f, "warmup ok: {}", self.checks.join("; "))
+    }
+}
+
+/// Reaches the Ollama servers and preloads the model behind every UDF selected in
+/// `options`, so the first query does not pay the model load time and a wrong URL
+/// or model name fails here instead of in the middle of a query.
+///
+/// The chat model is loaded on [`AiConfig::ollama_host`] and every
+/// [`AiConfig::ollama_hosts`], and the fallback model on its host, see
+/// [`AiConfig::fallback_chat`]. With [`Backend::Llama`], the GGUF file of
+/// `ask_llm_confidence` is only checked to be readable: the model is process-global
+/// and loaded when the UDF is registered, so loading it here would make the
+/// registration fail.
+pub async fn warmup(config: &AiConfig, options: &RegisterOptions) -> anyhow::Result<WarmupReport> {
+    let mut report = WarmupReport::default();
+    let chat_udfs: Vec<String> = options
+        .udfs
+        .iter()
+        .filter(|udf| !matches!(udf, AiUdf::OllamaEmbed | AiUdf::Info | AiUdf::Confidence))
+        .map(|&udf| options.name_for(udf))
+        .collect();
+    if !chat_udfs.is_empty() {
+        let chat_udfs = chat_udfs.join(", ");
+        for url in config.chat_urls() {
+            OllamaApp::new(&config.chat_model, &url)?
+                .preload_chat()
+                .await?;
+            report.checks.push(format!(
+                "{}: chat model {} loaded at {}",
+                chat_udfs, config.chat_model, url
+            ));
+        }
+        if let Some((model, url)) = config.fallback_chat() {
+            OllamaApp::new(&model, &url)?.preload_chat().await?;
+            report.checks.push(format!(
+                "{}: fallback model {} loaded at {}",
+                chat_udfs, model, url
+            ));
+        }
+    }
+    for &udf in &options.udfs {
+        match udf {
+            AiUdf::OllamaEmbed => {
+                OllamaApp::new(&config.embed_model, &config.embed_url())?
+                    .preload_embed()
+                    .await?;
+                report.checks.push(format!(
+                    "{}: embedding model {} loaded at {}",
+                    options.name_for(udf),
+                    config.embed_model,
+                    config.ollama_host
+                ));
+            }
+            AiUdf::Confidence => {
+                let (Backend::Llama, Some(model_path)) = (config.backend, &config.model_path)
+                else {
+                    continue;
+                };
+                verify_gguf(model_path)?;
+                report.checks.push(format!(
+                    "{}: GGUF model {} found",
+                    options.name_for(udf),
+                    model_path
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(report)
+}
+
+// reads the magic number every GGUF file starts with
+fn verify_gguf(model_path: &str) -> anyhow::Result<()> {
+    let mut magic = [0; 4];
+    File::open(model_path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .with_context(|| format!("Unable to read model file {}", model_path))?;
+    if &magic != b"GGUF" {
+        anyhow::bail!("{} is not a GGUF model file", model_path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_udf::tests::spawn_mock_server;
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test]
+    async fn test_warmup_reaches_every_chat_endpoint() {
+        let (primary, primary_requests) = spawn_mock_server(|_| (200, "{}".to_string()));
+        let (replica, replica_requests) = spawn_mock_server(|_| (200, "{}".to_string()));
+        let (fallback, fallback_requests) = spawn_mock_server(|_| (200, "{}".to_string()));
+        let config = AiConfig {
+            ollama_host: primary,
+            ollama_hosts: vec![replica],
+            fallback_host: Some(fallback),
+            ..AiConfig::default()
+        };
+        let options = RegisterOptions::default().with_udfs(vec![AiUdf::AskLlm, AiUdf::Date]);
+        let report = warmup(&config, &options).await.unwrap();
+        assert_eq!(report.checks.len(), 3);
+        for requests in [primary_requests, replica_requests, fallback_requests] {
+            assert_eq!(requests.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warmup_checks_the_gguf_file() {
+        let path = std::env::temp_dir().join(format!("model_{}.gguf", std::process::id()));
+        std::fs::write(&path, b"not a model").unwrap();
+        let config = AiConfig::default()
+            .with_backend(Backend::Llama)
+            .with_model_path(path.to_string_lossy());
+        let options = RegisterOptions::default().with_udfs(vec![AiUdf::Confidence]);
+        assert!(warmup(&config, &options).await.is_err());
+
+        std::fs::write(&path, b"GGUF\x03\x00\x00\x00").unwrap();
+        let report = warmup(&config, &options).await.unwrap();
+        assert_eq!(report.checks.len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
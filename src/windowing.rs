@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+/// How the per-window answers of an oversized value are merged into one result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindowCombine {
+    /// Joins the answers with a single space, in window order.
+    /// Suited for extraction or summarization style instructions.
+    #[default]
+    Concatenate,
+    /// Returns the most frequent answer. Ties go to the answer seen first,
+    /// so earlier windows win. Suited for classification style instructions.
+    MajorityVote,
+}
+
+/// Splits values longer than `max_chars` into overlapping windows that are sent to
+/// the model as separate items, instead of truncating them.
+///
+/// Values of at most `max_chars` characters are never split. Lengths are counted in
+/// characters, not bytes, so windows never cut a UTF-8 sequence.
+#[derive(Debug, Clone)]
+pub struct WindowingConfig {
+    pub max_chars: usize,
+    pub window_size: usize,
+    pub overlap: usize,
+    pub combine: WindowCombine,
+}
+
+impl WindowingConfig {
+    pub fn new(max_chars: usize, window_size: usize, overlap: usize) -> Self {
+        Self {
+            max_chars,
+            window_size,
+            overlap,
+            combine: WindowCombine::default(),
+        }
+    }
+
+    pub fn with_combine(mut self, combine: WindowCombine) -> Self {
+        self.combine = combine;
+        self
+    }
+
+    pub fn is_oversized(&self, value: &str) -> bool {
+        value.chars().count() > self.max_chars
+    }
+
+    /// Splits `value` into windows of `window_size` characters, each starting
+    /// `window_size - overlap` characters after the previous one.
+    pub fn split(&self, value: &str) -> Vec<String> {
+        let chars: Vec<char> = value.chars().collect();
+        let size = self.window_size.max(1);
+        // an overlap as large as the window would never advance
+        let step = size.saturating_sub(self.overlap).max(1);
+        let mut windows = Vec::new();
+        let mut start = 0;
+        loop {
+            let end = (start + size).min(chars.len());
+            windows.push(chars[start..end].iter().collect());
+            if end == chars.len() {
+                break;
+            }
+            start += step;
+        }
+        windows
+    }
+
+    pub fn combine(&self, answers: Vec<String>) -> String {
+        match self.combine {
+            WindowCombine::Concatenate => answers.join(" "),
+            WindowCombine::MajorityVote => {
+                let mut counts: HashMap<&str, usize> = HashMap::new();
+                for answer in &answers {
+                    *counts.entry(answer.as_str()).or_default() += 1;
+                }
+                let max = counts.values().copied().max().unwrap_or_default();
+                answers
+                    .iter()
+                    .find(|answer| counts[answer.as_str()] == max)
+                    .cloned()
+                    .unwrap_or_default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_with_overlap() {
+        let windowing = WindowingConfig::new(5, 4, 1);
+        assert!(!windowing.is_oversized("abcde"));
+        assert!(windowing.is_oversized("abcdefghij"));
+        assert_eq!(windowing.split("abcdefghij"), vec!["abcd", "defg", "ghij"]);
+    }
+
+    #[test]
+    fn test_majority_vote_prefers_earliest_on_tie() {
+        let windowing = WindowingConfig::new(5, 4, 1).with_combine(WindowCombine::MajorityVote);
+        let answers = vec!["neutral", "positive", "positive", "neutral"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(windowing.combine(answers), "neutral");
+    }
+}